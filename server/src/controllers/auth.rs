@@ -4,17 +4,46 @@
  * SPDX-License-Identifier: MIT
  */
 
-use base64::engine::general_purpose::STANDARD_NO_PAD as BASE64_NO_PAD;
+use std::time::Duration;
+
+use base64::engine::general_purpose::{
+    STANDARD_NO_PAD as BASE64_NO_PAD, URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD,
+};
 use base64::Engine as _;
 use chrono::Utc;
 use const_format::formatcp;
-use pbkdf2::password_verify;
-use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use small_http::{Request, Response, Status};
-use validate::Report;
+use uuid::Uuid;
+use validate::{Report, Validate};
 
 use crate::database::Extension;
-use crate::models::{Session, User};
+use crate::mail::Mail;
+use crate::models::email_verification::find_valid_email_verification;
+use crate::models::login_attempt::{
+    clear_failed_logins, count_failed_logins, login_retry_after, normalize_logon,
+    record_failed_login, LOGIN_ATTEMPTS_BEFORE_POW,
+};
+use crate::models::magic_link::{
+    find_valid_magic_link, magic_link_retry_after, record_magic_link_request,
+};
+use crate::models::password_reset::find_valid_password_reset;
+use crate::models::pow_challenge::verify_pow_challenge;
+use crate::models::security_event::{record_security_event, send_new_device_alert};
+use crate::models::session::{
+    revoke_session, revoke_user_sessions, revoke_user_tokens, SESSION_TOKEN_PREFIX,
+};
+use crate::models::two_factor::{
+    find_enabled_two_factor, find_two_factor_challenge, use_recovery_code,
+};
+use crate::models::user::is_unique_email;
+use crate::models::user_suspension::find_active_user_suspension;
+use crate::models::{
+    MagicLink, PasswordReset, PowChallenge, SecurityEventType, Session, TwoFactorChallenge, User,
+};
+use crate::password::{
+    password_hash, password_needs_rehash, password_verify, password_verify_dummy,
+};
 use crate::{api, Context, USER_AGENT_PARSER};
 
 // MARK: Auth login
@@ -31,6 +60,29 @@ pub fn auth_login(req: &Request, ctx: &Context) -> Response {
         }
     };
 
+    // Check if logon or ip address is locked out
+    let logon = normalize_logon(&body.logon);
+    let ip_address = req.client_addr.ip().to_string();
+    if let Some(retry_after) = login_retry_after(ctx, &logon, &ip_address) {
+        let mut report = Report::new();
+        report.insert_error("logon", "Too many login attempts, try again later");
+        return Response::new()
+            .status(Status::TooManyRequests)
+            .header("Retry-After", retry_after.to_string())
+            .json(report);
+    }
+
+    // Require proof of work after repeated failures
+    if count_failed_logins(ctx, &logon, &ip_address) >= LOGIN_ATTEMPTS_BEFORE_POW {
+        if let Some(res) = check_pow_challenge(
+            ctx,
+            body.pow_challenge.as_deref(),
+            body.pow_nonce.as_deref(),
+        ) {
+            return res;
+        }
+    }
+
     // Find user by username or email
     let user = ctx
         .database
@@ -45,6 +97,8 @@ pub fn auth_login(req: &Request, ctx: &Context) -> Response {
     let user = match user {
         Some(user) => user,
         None => {
+            password_verify_dummy(&body.password);
+            record_failed_login(ctx, &logon, &ip_address);
             let mut report = Report::new();
             report.insert_error("logon", "Wrong username, email address or password");
             return Response::new().status(Status::Unauthorized).json(report);
@@ -52,67 +106,198 @@ pub fn auth_login(req: &Request, ctx: &Context) -> Response {
     };
 
     // Check password
-    if !password_verify(&body.password, &user.password).expect("Can't verify password") {
+    if !password_verify(&body.password, &user.password) {
+        record_failed_login(ctx, &logon, &ip_address);
+        record_security_event(req, ctx, user.id, SecurityEventType::LoginFailed);
         let mut report = Report::new();
         report.insert_error("logon", "Wrong username, email address or password");
         return Response::new().status(Status::Unauthorized).json(report);
     }
 
-    // Get IP information from ipinfo.io
-    #[derive(Deserialize)]
-    struct IpInfo {
-        city: String,
-        country: String,
-        loc: String,
+    // Upgrade hash when it was created with an older algorithm or parameters
+    if password_needs_rehash(&user.password) {
+        ctx.database.execute(
+            "UPDATE users SET password = ? WHERE id = ?",
+            (password_hash(&body.password), user.id),
+        );
+    }
+
+    // Check if user is suspended
+    if let Some(user_suspension) = find_active_user_suspension(ctx, user.id) {
+        let mut report = Report::new();
+        report.insert_error("logon", &user_suspension.message());
+        return Response::new().status(Status::Forbidden).json(report);
     }
-    let ip_address = req.client_addr.ip();
-    let ip_info = match Request::with_url(format!("http://ipinfo.io/{}/json", ip_address)).fetch() {
-        Ok(res) => serde_json::from_slice::<IpInfo>(&res.body).ok(),
-        Err(_) => None,
+
+    // Reset failed login attempts
+    clear_failed_logins(ctx, &[&logon]);
+
+    continue_login(
+        req,
+        ctx,
+        user,
+        body.remember.unwrap_or(false),
+        body.cookie.unwrap_or(false),
+    )
+}
+
+// Returns a two factor challenge when enabled, else completes the login
+fn continue_login(
+    req: &Request,
+    ctx: &Context,
+    user: User,
+    remember: bool,
+    cookie: bool,
+) -> Response {
+    if find_enabled_two_factor(ctx, user.id).is_some() {
+        let two_factor_challenge = TwoFactorChallenge {
+            user_id: user.id,
+            token: generate_random_token(),
+            remember,
+            cookie,
+            ..Default::default()
+        };
+        ctx.database
+            .insert_two_factor_challenge(two_factor_challenge.clone());
+        return Response::new()
+            .status(Status::Accepted)
+            .json(api::AuthLoginChallengeResponse {
+                challenge: two_factor_challenge.token,
+                expires_at: two_factor_challenge.expires_at,
+            });
+    }
+    complete_login(req, ctx, user, remember, cookie)
+}
+
+// MARK: Auth login two factor
+pub fn auth_login_two_factor(req: &Request, ctx: &Context) -> Response {
+    // Parse body
+    let body = match serde_urlencoded::from_bytes::<api::AuthLoginTwoFactorBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => body,
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
     };
 
-    // Parse user agent info
-    let user_agent = req
-        .headers
-        .get("User-Agent")
-        .map(|user_agent| USER_AGENT_PARSER.parse(user_agent));
+    // Find challenge and user
+    let two_factor_challenge = find_two_factor_challenge(ctx, &body.challenge);
+    let user = two_factor_challenge
+        .as_ref()
+        .and_then(|two_factor_challenge| {
+            ctx.database
+                .query::<User>(
+                    formatcp!("SELECT {} FROM users WHERE id = ? LIMIT 1", User::columns()),
+                    two_factor_challenge.user_id,
+                )
+                .next()
+        });
+    let (two_factor_challenge, user) = match (two_factor_challenge, user) {
+        (Some(two_factor_challenge), Some(user)) => (two_factor_challenge, user),
+        _ => {
+            let mut report = Report::new();
+            report.insert_error("challenge", "Invalid or expired challenge");
+            return Response::new().status(Status::Unauthorized).json(report);
+        }
+    };
 
-    // Generate token
-    let token = generate_random_token();
+    // Check if user or ip address is locked out
+    let logon = normalize_logon(&user.username);
+    let ip_address = req.client_addr.ip().to_string();
+    if let Some(retry_after) = login_retry_after(ctx, &logon, &ip_address) {
+        let mut report = Report::new();
+        report.insert_error("code", "Too many login attempts, try again later");
+        return Response::new()
+            .status(Status::TooManyRequests)
+            .header("Retry-After", retry_after.to_string())
+            .json(report);
+    }
+
+    // Check TOTP or recovery code
+    let is_valid_code = match find_enabled_two_factor(ctx, user.id) {
+        Some(mut two_factor) => {
+            two_factor.verify_code(ctx, &body.code) || use_recovery_code(ctx, user.id, &body.code)
+        }
+        None => false,
+    };
+    if !is_valid_code {
+        record_failed_login(ctx, &logon, &ip_address);
+        record_security_event(req, ctx, user.id, SecurityEventType::LoginFailed);
+        let mut report = Report::new();
+        report.insert_error("code", "Wrong two factor code");
+        return Response::new().status(Status::Unauthorized).json(report);
+    }
 
-    // Create new session
+    // Challenges can only be used once
+    ctx.database.execute(
+        "DELETE FROM two_factor_challenges WHERE id = ?",
+        two_factor_challenge.id,
+    );
+    clear_failed_logins(ctx, &[&logon]);
+
+    complete_login(
+        req,
+        ctx,
+        user,
+        two_factor_challenge.remember,
+        two_factor_challenge.cookie,
+    )
+}
+
+// Returns the session token in the response, or in a HttpOnly cookie for the web client
+pub fn complete_login(
+    req: &Request,
+    ctx: &Context,
+    user: User,
+    remember: bool,
+    cookie: bool,
+) -> Response {
+    // Cancel possible pending account deletion
+    ctx.database
+        .execute("DELETE FROM user_deletions WHERE user_id = ?", user.id);
+
+    // Generate token, only its hash is stored
+    let token = generate_session_token();
+
+    // Create new session, remembered sessions get the long lifetime
+    let lifetime = if remember {
+        ctx.settings.session_long_lifetime
+    } else {
+        ctx.settings.session_short_lifetime
+    };
     let session = Session {
-        user_id: user.id,
-        token: token.clone(),
-        ip_address: req.client_addr.ip().to_string(),
-        ip_latitude: ip_info.as_ref().and_then(|info| {
-            info.loc
-                .split(',')
-                .next()
-                .expect("Should exists")
-                .parse()
-                .ok()
-        }),
-        ip_longitude: ip_info.as_ref().and_then(|info| {
-            info.loc
-                .split(',')
-                .nth(1)
-                .expect("Should exists")
-                .parse()
-                .ok()
-        }),
-        ip_country: ip_info.as_ref().map(|info| info.country.clone()),
-        ip_city: ip_info.as_ref().map(|info| info.city.clone()),
-        client_name: user_agent.as_ref().map(|ua| ua.client.family.to_string()),
-        client_version: user_agent.as_ref().and_then(|ua| ua.client.version.clone()),
-        client_os: user_agent.as_ref().map(|ua| ua.os.family.to_string()),
-        ..Default::default()
+        token: hash_token(&token),
+        remember,
+        expires_at: Utc::now() + lifetime,
+        ..request_session(req, ctx, user.id)
     };
     ctx.database.insert_session(session.clone());
 
+    // Record login and alert the user when it is from a new device
+    let security_event =
+        record_security_event(req, ctx, user.id, SecurityEventType::LoginSucceeded);
+    send_new_device_alert(ctx, &user, &security_event);
+
     // Return session
+    if cookie {
+        return Response::new()
+            .header(
+                "Set-Cookie",
+                session_cookie_header(&token, remember.then_some(lifetime)),
+            )
+            .json(api::AuthLoginResponse {
+                token: "".to_string(),
+                csrf_token: Some(generate_csrf_token(&token)),
+                session: session.into(),
+                user: user.into(),
+            });
+    }
     Response::new().json(api::AuthLoginResponse {
         token,
+        csrf_token: None,
         session: session.into(),
         user: user.into(),
     })
@@ -124,37 +309,502 @@ pub fn generate_random_token() -> String {
     BASE64_NO_PAD.encode(token_bytes)
 }
 
+pub fn generate_session_token() -> String {
+    format!("{}{}", SESSION_TOKEN_PREFIX, generate_random_token())
+}
+
+// Shorter token that is safe to use in links that are sent by mail
+pub fn generate_url_token() -> String {
+    let mut token_bytes = [0u8; 32];
+    getrandom::fill(&mut token_bytes).expect("Can't get random bytes");
+    BASE64_URL_SAFE_NO_PAD.encode(token_bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// PKCE S256 code challenge of a code verifier (RFC 7636)
+pub fn generate_code_challenge(code_verifier: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+// MARK: Proof of work
+pub fn auth_pow_challenge(_: &Request, ctx: &Context) -> Response {
+    // Remove expired challenges
+    ctx.database.execute(
+        "DELETE FROM pow_challenges WHERE expires_at <= ?",
+        Utc::now(),
+    );
+
+    let pow_challenge = PowChallenge {
+        difficulty: ctx.settings.pow_difficulty,
+        ..Default::default()
+    };
+    ctx.database.insert_pow_challenge(pow_challenge.clone());
+    Response::new().json(Into::<api::PowChallenge>::into(pow_challenge))
+}
+
+// Returns an error response when a proof of work is configured and the solution is missing or wrong
+pub fn check_pow_challenge(
+    ctx: &Context,
+    challenge: Option<&str>,
+    nonce: Option<&str>,
+) -> Option<Response> {
+    if ctx.settings.pow_difficulty == 0 {
+        return None;
+    }
+    if let (Some(challenge), Some(nonce)) = (challenge, nonce) {
+        if verify_pow_challenge(ctx, challenge, nonce) {
+            return None;
+        }
+    }
+    let mut report = Report::new();
+    report.insert_error("powChallenge", "proof of work required");
+    Some(Response::new().status(Status::BadRequest).json(report))
+}
+
+// MARK: Request session
+// New session with the ip and client info of the request, the caller sets token and expiry
+pub fn request_session(req: &Request, ctx: &Context, user_id: Uuid) -> Session {
+    // Get IP information from the configured GeoIP provider
+    let ip_address = req.client_addr.ip().to_string();
+    let ip_info = ctx.geoip.lookup(req.client_addr.ip()).unwrap_or_default();
+
+    // Parse user agent info
+    let user_agent = req
+        .headers
+        .get("User-Agent")
+        .map(|user_agent| USER_AGENT_PARSER.parse(user_agent));
+
+    Session {
+        user_id,
+        last_ip_address: ip_address.clone(),
+        ip_address,
+        ip_latitude: ip_info.latitude,
+        ip_longitude: ip_info.longitude,
+        ip_country: ip_info.country,
+        ip_city: ip_info.city,
+        client_name: user_agent.as_ref().map(|ua| ua.client.family.to_string()),
+        client_version: user_agent.as_ref().and_then(|ua| ua.client.version.clone()),
+        client_os: user_agent.as_ref().map(|ua| ua.os.family.to_string()),
+        ..Default::default()
+    }
+}
+
+// MARK: Session cookie
+pub const SESSION_COOKIE_NAME: &str = "plaatbook_session";
+
+pub fn session_cookie_header(token: &str, max_age: Option<Duration>) -> String {
+    let mut header = format!(
+        "{}={}; Path=/; HttpOnly; Secure; SameSite=Strict",
+        SESSION_COOKIE_NAME, token
+    );
+    if let Some(max_age) = max_age {
+        header.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+    }
+    header
+}
+
+pub fn session_cookie(req: &Request) -> Option<String> {
+    req.headers
+        .get("Cookie")
+        .or(req.headers.get("cookie"))?
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE_NAME)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
+// Double submit token for cookie sessions, it's derived from the session token so it can't be
+// guessed, but can be read by the web client unlike the HttpOnly cookie
+pub fn generate_csrf_token(session_token: &str) -> String {
+    hash_token(&format!("csrf:{}", session_token))
+}
+
+// MARK: Auth forgot password
+pub fn auth_forgot_password(req: &Request, ctx: &Context) -> Response {
+    // Parse body
+    let body = match serde_urlencoded::from_bytes::<api::AuthForgotPasswordBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => body,
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+
+    // Find user by email, always respond the same so emails can't be enumerated
+    let user = ctx
+        .database
+        .query::<User>(
+            formatcp!(
+                "SELECT {} FROM users WHERE email = ? LIMIT 1",
+                User::columns()
+            ),
+            body.email.trim().to_string(),
+        )
+        .next();
+    let user = match user {
+        Some(user) => user,
+        None => return Response::new(),
+    };
+
+    // Invalidate previous reset tokens and create a new one
+    let now = Utc::now();
+    ctx.database.execute(
+        "UPDATE password_resets SET used_at = ?, updated_at = ? WHERE user_id = ? AND used_at IS NULL",
+        (now, now, user.id),
+    );
+    let token = generate_url_token();
+    ctx.database.insert_password_reset(PasswordReset {
+        user_id: user.id,
+        token: hash_token(&token),
+        ..Default::default()
+    });
+
+    // Send reset mail
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: "Reset your PlaatBook password".to_string(),
+        body: format!(
+            "Hi {},\n\nSomeone requested a password reset for your PlaatBook account. Use the link below to choose a new password:\n\n{}/auth/reset_password?token={}\n\nThis link expires in one hour. If you didn't request this, you can ignore this email.\n",
+            user.username, ctx.settings.app_url, token
+        ),
+    };
+    if let Err(err) = ctx.mailer.send(&mail) {
+        eprintln!("Can't send password reset mail: {}", err);
+    }
+    Response::new()
+}
+
+// MARK: Auth reset password
+#[derive(Validate)]
+struct AuthResetPasswordBody {
+    token: String,
+    #[validate(ascii, length(min = 6, max = 128))]
+    password: String,
+}
+
+impl From<api::AuthResetPasswordBody> for AuthResetPasswordBody {
+    fn from(body: api::AuthResetPasswordBody) -> Self {
+        Self {
+            token: body.token,
+            password: body.password,
+        }
+    }
+}
+
+pub fn auth_reset_password(req: &Request, ctx: &Context) -> Response {
+    // Parse and validate body
+    let body = match serde_urlencoded::from_bytes::<api::AuthResetPasswordBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => Into::<AuthResetPasswordBody>::into(body),
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    if let Err(errors) = body.validate() {
+        return Response::new().status(Status::BadRequest).json(errors);
+    }
+
+    // Find reset token and user
+    let password_reset = find_valid_password_reset(ctx, &body.token);
+    let user = password_reset.as_ref().and_then(|password_reset| {
+        ctx.database
+            .query::<User>(
+                formatcp!("SELECT {} FROM users WHERE id = ? LIMIT 1", User::columns()),
+                password_reset.user_id,
+            )
+            .next()
+    });
+    let (password_reset, user) = match (password_reset, user) {
+        (Some(password_reset), Some(user)) => (password_reset, user),
+        _ => {
+            let mut report = Report::new();
+            report.insert_error("token", "Invalid or expired token");
+            return Response::new().status(Status::BadRequest).json(report);
+        }
+    };
+
+    // Update password and mark token as used
+    let now = Utc::now();
+    ctx.database.execute(
+        "UPDATE users SET password = ?, updated_at = ? WHERE id = ?",
+        (password_hash(&body.password), now, user.id),
+    );
+    ctx.database.execute(
+        "UPDATE password_resets SET used_at = ?, updated_at = ? WHERE id = ?",
+        (now, now, password_reset.id),
+    );
+    record_security_event(req, ctx, user.id, SecurityEventType::PasswordChanged);
+
+    // Revoke all sessions and tokens and clear lockouts
+    revoke_user_sessions(ctx, user.id, None);
+    revoke_user_tokens(ctx, user.id);
+    clear_failed_logins(
+        ctx,
+        &[
+            &normalize_logon(&user.username),
+            &normalize_logon(&user.email),
+        ],
+    );
+    Response::new()
+}
+
+// MARK: Auth magic link
+pub fn auth_magic_link(req: &Request, ctx: &Context) -> Response {
+    // Parse body
+    let body = match serde_urlencoded::from_bytes::<api::AuthMagicLinkBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => body,
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+
+    // Rate limit by email address and ip address, also for unknown emails
+    let email = normalize_logon(&body.email);
+    let ip_address = req.client_addr.ip().to_string();
+    if let Some(retry_after) = magic_link_retry_after(ctx, &email, &ip_address) {
+        let mut report = Report::new();
+        report.insert_error("email", "Too many login link requests, try again later");
+        return Response::new()
+            .status(Status::TooManyRequests)
+            .header("Retry-After", retry_after.to_string())
+            .json(report);
+    }
+    record_magic_link_request(ctx, &email, &ip_address);
+
+    // Find user by email, always respond the same so emails can't be enumerated
+    let user = ctx
+        .database
+        .query::<User>(
+            formatcp!(
+                "SELECT {} FROM users WHERE email = ? LIMIT 1",
+                User::columns()
+            ),
+            body.email.trim().to_string(),
+        )
+        .next();
+    let user = match user {
+        Some(user) => user,
+        None => return Response::new(),
+    };
+
+    // Invalidate previous magic links and create a new one
+    let now = Utc::now();
+    ctx.database.execute(
+        "UPDATE magic_links SET used_at = ?, updated_at = ? WHERE user_id = ? AND used_at IS NULL",
+        (now, now, user.id),
+    );
+    let token = generate_url_token();
+    ctx.database.insert_magic_link(MagicLink {
+        user_id: user.id,
+        token: hash_token(&token),
+        ..Default::default()
+    });
+
+    // Send magic link mail
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: "Your PlaatBook login link".to_string(),
+        body: format!(
+            "Hi {},\n\nUse the link below to login to your PlaatBook account:\n\n{}/auth/magic_link?token={}\n\nThis link can be used once and expires in 15 minutes. If you didn't request this, you can ignore this email.\n",
+            user.username, ctx.settings.app_url, token
+        ),
+    };
+    if let Err(err) = ctx.mailer.send(&mail) {
+        eprintln!("Can't send magic link mail: {}", err);
+    }
+    Response::new()
+}
+
+// MARK: Auth magic link login
+pub fn auth_magic_link_login(req: &Request, ctx: &Context) -> Response {
+    // Parse body
+    let body = match serde_urlencoded::from_bytes::<api::AuthMagicLinkLoginBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => body,
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+
+    // Find magic link and user
+    let magic_link = find_valid_magic_link(ctx, &body.token);
+    let user = magic_link.as_ref().and_then(|magic_link| {
+        ctx.database
+            .query::<User>(
+                formatcp!("SELECT {} FROM users WHERE id = ? LIMIT 1", User::columns()),
+                magic_link.user_id,
+            )
+            .next()
+    });
+    let (magic_link, mut user) = match (magic_link, user) {
+        (Some(magic_link), Some(user)) => (magic_link, user),
+        _ => {
+            let mut report = Report::new();
+            report.insert_error("token", "Invalid or expired token");
+            return Response::new().status(Status::Unauthorized).json(report);
+        }
+    };
+
+    // Mark magic link as used
+    let now = Utc::now();
+    ctx.database.execute(
+        "UPDATE magic_links SET used_at = ?, updated_at = ? WHERE id = ?",
+        (now, now, magic_link.id),
+    );
+
+    // Check if user is suspended
+    if let Some(user_suspension) = find_active_user_suspension(ctx, user.id) {
+        let mut report = Report::new();
+        report.insert_error("token", &user_suspension.message());
+        return Response::new().status(Status::Forbidden).json(report);
+    }
+
+    // Opening the link proves the user owns the email address
+    if user.email_verified_at.is_none() {
+        user.email_verified_at = Some(now);
+        user.updated_at = now;
+        ctx.database.execute(
+            "UPDATE users SET email_verified_at = ?, updated_at = ? WHERE id = ?",
+            (user.email_verified_at, user.updated_at, user.id),
+        );
+    }
+
+    continue_login(
+        req,
+        ctx,
+        user,
+        body.remember.unwrap_or(false),
+        body.cookie.unwrap_or(false),
+    )
+}
+
+// MARK: Auth verify email
+pub fn auth_verify_email(req: &Request, ctx: &Context) -> Response {
+    // Parse body
+    let body = match serde_urlencoded::from_bytes::<api::AuthVerifyEmailBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => body,
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+
+    // Find verification and user
+    let email_verification = find_valid_email_verification(ctx, &body.token);
+    let user = email_verification.as_ref().and_then(|email_verification| {
+        ctx.database
+            .query::<User>(
+                formatcp!("SELECT {} FROM users WHERE id = ? LIMIT 1", User::columns()),
+                email_verification.user_id,
+            )
+            .next()
+    });
+    let (email_verification, mut user) = match (email_verification, user) {
+        (Some(email_verification), Some(user)) => (email_verification, user),
+        _ => {
+            let mut report = Report::new();
+            report.insert_error("token", "Invalid or expired token");
+            return Response::new().status(Status::BadRequest).json(report);
+        }
+    };
+
+    // Pending email could be taken in the meantime
+    if email_verification.email != user.email
+        && is_unique_email(&email_verification.email, ctx).is_err()
+    {
+        let mut report = Report::new();
+        report.insert_error("email", "not unique");
+        return Response::new().status(Status::BadRequest).json(report);
+    }
+
+    // Mark email as verified
+    user.email = email_verification.email;
+    user.email_verified_at = Some(Utc::now());
+    user.updated_at = Utc::now();
+    ctx.database.execute(
+        "UPDATE users SET email = ?, email_verified_at = ?, updated_at = ? WHERE id = ?",
+        (
+            user.email.clone(),
+            user.email_verified_at,
+            user.updated_at,
+            user.id,
+        ),
+    );
+    ctx.database
+        .execute("DELETE FROM email_verifications WHERE user_id = ?", user.id);
+    Response::new().json(Into::<api::User>::into(user))
+}
+
 // MARK: Auth validate
-pub fn auth_validate(_: &Request, ctx: &Context) -> Response {
+pub fn auth_validate(req: &Request, ctx: &Context) -> Response {
+    let session = ctx.auth_session.clone().expect("Should be authed");
+
+    // Give cookie sessions their CSRF token back after a page reload
+    let csrf_token = session_cookie(req)
+        .filter(|token| hash_token(token) == session.token)
+        .map(|token| generate_csrf_token(&token));
+
     Response::new().json(api::AuthValidateResponse {
-        session: ctx.auth_session.clone().expect("Should be authed").into(),
+        csrf_token,
+        session: session.with_impersonator(ctx).into(),
         user: ctx.auth_user.clone().expect("Should be authed").into(),
     })
 }
 
 // MARK: Auth logout
-pub fn auth_logout(_: &Request, ctx: &Context) -> Response {
+pub fn auth_logout(req: &Request, ctx: &Context) -> Response {
     // Expire session
-    ctx.database.execute(
-        "UPDATE sessions SET expires_at = ? WHERE token = ?",
-        (
-            Utc::now(),
-            ctx.auth_session.as_ref().expect("Not authed").token.clone(),
-        ),
-    );
+    let session = ctx.auth_session.clone().expect("Not authed");
+    revoke_session(ctx, session);
+
+    // Clear session cookie
+    if session_cookie(req).is_some() {
+        return Response::new().status(Status::Ok).header(
+            "Set-Cookie",
+            session_cookie_header("", Some(Duration::ZERO)),
+        );
+    }
     Response::new().status(Status::Ok)
 }
 
 #[cfg(test)]
 mod test {
-    use pbkdf2::password_hash;
+    use std::sync::Arc;
+
     use small_http::Method;
 
     use super::*;
-    use crate::database::Extension;
-    use crate::models::UserRole;
+    use crate::geoip::CsvGeoIpProvider;
+    use crate::mail::OutboxTransport;
+    use crate::models::login_attempt::LOGIN_ATTEMPTS_PER_LOGON;
+    use crate::models::magic_link::MAGIC_LINK_REQUESTS_PER_EMAIL;
+    use crate::models::pow_challenge::solve_pow_challenge;
+    use crate::models::{OAuthToken, PersonalAccessToken, UserRole};
     use crate::router;
-    use crate::test_utils::create_user_session;
+    use crate::test_utils::{create_session, create_user_session};
 
     // MARK: Test Auth login
     #[test]
@@ -214,6 +864,521 @@ mod test {
         );
     }
 
+    // MARK: Test Auth login rehash
+    #[test]
+    fn test_auth_login_rehash() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let user = User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: pbkdf2::password_hash("password"),
+            ..Default::default()
+        };
+        ctx.database.insert_user(user.clone());
+
+        // Legacy PBKDF2 hash is upgraded on successful login
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let stored_password = ctx
+            .database
+            .query::<String>("SELECT password FROM users WHERE id = ?", user.id)
+            .next()
+            .unwrap();
+        assert!(stored_password.starts_with("$argon2id$"));
+        assert!(!password_needs_rehash(&stored_password));
+
+        // Login keeps working with the upgraded hash
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+
+    // MARK: Test Auth login new device alert
+    #[test]
+    fn test_auth_login_new_device_alert() {
+        let outbox = Arc::new(OutboxTransport::default());
+        let ctx = Context {
+            mailer: outbox.clone(),
+            ..Context::with_test_database()
+        };
+        let router = router(ctx.clone());
+        ctx.database.insert_user(User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        });
+        let login = |user_agent: &str| {
+            let req = Request::with_url("http://localhost/auth/login")
+                .method(Method::Post)
+                .header("User-Agent", user_agent)
+                .body("logon=test&password=password");
+            let res = router.handle(&req);
+            assert_eq!(res.status, Status::Ok);
+        };
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:133.0) Gecko/20100101 Firefox/133.0";
+        let chrome = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36";
+
+        // First login of an account and logins from a known device send no alert
+        login(firefox);
+        login(firefox);
+        assert!(outbox.mails().is_empty());
+
+        // Login from a new client and OS sends an alert
+        login(chrome);
+        let mails = outbox.mails();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].to, "test@example.com");
+        assert!(mails[0].body.contains("Chrome"));
+
+        login(chrome);
+        assert_eq!(outbox.mails().len(), 1);
+    }
+
+    // MARK: Test Auth login geoip
+    #[test]
+    fn test_auth_login_geoip() {
+        let ctx = Context {
+            geoip: Arc::new(CsvGeoIpProvider::parse(
+                "0.0.0.0,255.255.255.255,NL,Gouda,52.0167,4.7083\n\
+                 ::,ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff,NL,Gouda,52.0167,4.7083\n",
+            )),
+            ..Context::with_test_database()
+        };
+        let router = router(ctx.clone());
+        ctx.database.insert_user(User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        });
+
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let session = serde_json::from_slice::<api::AuthLoginResponse>(&res.body)
+            .unwrap()
+            .session;
+        assert_eq!(session.ip_country, Some("NL".to_string()));
+        assert_eq!(session.ip_city, Some("Gouda".to_string()));
+        assert_eq!(session.ip_latitude, Some(52.0167));
+    }
+
+    // MARK: Test Auth login remember
+    #[test]
+    fn test_auth_login_remember() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        ctx.database.insert_user(User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        });
+
+        // Session without remember gets the short lifetime
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let session = serde_json::from_slice::<api::AuthLoginResponse>(&res.body)
+            .unwrap()
+            .session;
+        assert!(!session.remember);
+        assert!(session.expires_at <= Utc::now() + ctx.settings.session_short_lifetime);
+
+        // Remembered session gets the long lifetime
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password&remember=true");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let session = serde_json::from_slice::<api::AuthLoginResponse>(&res.body)
+            .unwrap()
+            .session;
+        assert!(session.remember);
+        assert!(session.expires_at > Utc::now() + ctx.settings.session_short_lifetime);
+    }
+
+    // MARK: Test Auth login cookie
+    #[test]
+    fn test_auth_login_cookie() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        ctx.database.insert_user(User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        });
+
+        // Login with cookie keeps the token out of the response body
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password&cookie=true");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let json = serde_json::from_slice::<api::AuthLoginResponse>(&res.body).unwrap();
+        assert!(json.token.is_empty());
+        let set_cookie = res.headers.get("Set-Cookie").unwrap();
+        assert!(set_cookie.starts_with("plaatbook_session=pbs_"));
+        assert!(set_cookie.contains("HttpOnly; Secure; SameSite=Strict"));
+        assert!(!set_cookie.contains("Max-Age"));
+
+        // CSRF token belongs to the cookie session token
+        let token = set_cookie
+            .split(';')
+            .next()
+            .and_then(|cookie| cookie.split_once('='))
+            .map(|(_, token)| token.to_string())
+            .unwrap();
+        assert_eq!(json.csrf_token, Some(generate_csrf_token(&token)));
+
+        // Remembered cookie sessions persist across browser restarts
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password&remember=true&cookie=true");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        assert!(res.headers.get("Set-Cookie").unwrap().contains(&format!(
+            "Max-Age={}",
+            ctx.settings.session_long_lifetime.as_secs()
+        )));
+    }
+
+    // MARK: Test Auth login pow
+    #[test]
+    fn test_auth_login_pow() {
+        let mut ctx = Context::with_test_database();
+        ctx.settings.pow_difficulty = 8;
+        let router = router(ctx.clone());
+        ctx.database.insert_user(User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        });
+
+        // First failures don't need a proof of work
+        for _ in 0..LOGIN_ATTEMPTS_BEFORE_POW {
+            let req = Request::with_url("http://localhost/auth/login")
+                .method(Method::Post)
+                .body("logon=test&password=wrong");
+            let res = router.handle(&req);
+            assert_eq!(res.status, Status::Unauthorized);
+        }
+
+        // After repeated failures the login needs a solved challenge
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+        let report = serde_json::from_slice::<Report>(&res.body).unwrap();
+        assert!(report.get_errors("powChallenge").is_some());
+
+        let req = Request::with_url("http://localhost/auth/pow_challenge").method(Method::Post);
+        let res = router.handle(&req);
+        let pow_challenge = serde_json::from_slice::<api::PowChallenge>(&res.body).unwrap();
+        let nonce = solve_pow_challenge(&pow_challenge.challenge, pow_challenge.difficulty);
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body(format!(
+                "logon=test&password=password&powChallenge={}&powNonce={}",
+                pow_challenge.challenge, nonce
+            ));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+
+    // MARK: Test Auth login lockout
+    #[test]
+    fn test_auth_login_lockout() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, admin_session) = create_user_session(&ctx, UserRole::Admin);
+        let user = User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        };
+        ctx.database.insert_user(user.clone());
+
+        // Failed attempts keep the same error
+        for _ in 0..LOGIN_ATTEMPTS_PER_LOGON {
+            let req = Request::with_url("http://localhost/auth/login")
+                .method(Method::Post)
+                .body("logon=Test&password=wrongpassword");
+            let res = router.handle(&req);
+            assert_eq!(res.status, Status::Unauthorized);
+            let report = serde_json::from_slice::<Report>(&res.body).unwrap();
+            assert_eq!(
+                report.get_errors("logon").unwrap().as_slice(),
+                &["Wrong username, email address or password".to_string()]
+            );
+        }
+
+        // Correct password is locked out
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::TooManyRequests);
+        assert!(res.headers.get("Retry-After").is_some());
+
+        // Admin clears lockout
+        let req = Request::with_url(format!("http://localhost/users/{}/lockout", user.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", admin_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // Unknown logons are locked out the same way
+        for _ in 0..LOGIN_ATTEMPTS_PER_LOGON {
+            let req = Request::with_url("http://localhost/auth/login")
+                .method(Method::Post)
+                .body("logon=unknown&password=password");
+            let res = router.handle(&req);
+            assert_eq!(res.status, Status::Unauthorized);
+        }
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=unknown&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::TooManyRequests);
+    }
+
+    // MARK: Test Auth forgot and reset password
+    #[test]
+    fn test_auth_forgot_reset_password() {
+        let outbox = Arc::new(OutboxTransport::default());
+        let ctx = Context {
+            mailer: outbox.clone(),
+            ..Context::with_test_database()
+        };
+        let router = router(ctx.clone());
+        let user = User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        };
+        ctx.database.insert_user(user.clone());
+        let session = create_session(&ctx, &user);
+        ctx.database
+            .insert_personal_access_token(PersonalAccessToken {
+                user_id: user.id,
+                token: hash_token("pbp_test"),
+                ..Default::default()
+            });
+        ctx.database.insert_oauth_token(OAuthToken {
+            user_id: user.id,
+            access_token: hash_token("pbo_test"),
+            refresh_token: hash_token("pbr_test"),
+            ..Default::default()
+        });
+
+        // Unknown email responds the same but sends no mail
+        let req = Request::with_url("http://localhost/auth/forgot_password")
+            .method(Method::Post)
+            .body("email=unknown@example.com");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        assert!(outbox.mails().is_empty());
+
+        // Request reset mail
+        let req = Request::with_url("http://localhost/auth/forgot_password")
+            .method(Method::Post)
+            .body("email=test@example.com");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let mails = outbox.mails();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].to, "test@example.com");
+        let token = mails[0]
+            .body
+            .split("token=")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap()
+            .to_string();
+
+        // Token is stored hashed
+        let stored_token = ctx
+            .database
+            .query::<String>("SELECT token FROM password_resets", ())
+            .next()
+            .unwrap();
+        assert_ne!(stored_token, token);
+
+        // Reset with too short password
+        let req = Request::with_url("http://localhost/auth/reset_password")
+            .method(Method::Post)
+            .body(format!("token={}&password=short", token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Reset with wrong token
+        let req = Request::with_url("http://localhost/auth/reset_password")
+            .method(Method::Post)
+            .body("token=wrong&password=new_password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Reset password, which clears the failed logins of the differently cased email
+        record_failed_login(&ctx, &normalize_logon("Test@Example.com"), "10.0.0.1");
+        let req = Request::with_url("http://localhost/auth/reset_password")
+            .method(Method::Post)
+            .body(format!("token={}&password=new_password", token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        assert_eq!(
+            count_failed_logins(&ctx, &normalize_logon(&user.email), "10.0.0.1"),
+            0
+        );
+
+        // Token can't be reused
+        let req = Request::with_url("http://localhost/auth/reset_password")
+            .method(Method::Post)
+            .body(format!("token={}&password=other_password", token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Existing sessions and tokens are revoked
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+        let personal_access_tokens_count = ctx
+            .database
+            .query::<i64>("SELECT COUNT(id) FROM personal_access_tokens", ())
+            .next()
+            .unwrap();
+        assert_eq!(personal_access_tokens_count, 0);
+        let oauth_tokens_count = ctx
+            .database
+            .query::<i64>("SELECT COUNT(id) FROM oauth_tokens", ())
+            .next()
+            .unwrap();
+        assert_eq!(oauth_tokens_count, 0);
+
+        // Login with new password
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=new_password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+
+    // MARK: Test Auth magic link
+    #[test]
+    fn test_auth_magic_link() {
+        let outbox = Arc::new(OutboxTransport::default());
+        let ctx = Context {
+            mailer: outbox.clone(),
+            ..Context::with_test_database()
+        };
+        let router = router(ctx.clone());
+        ctx.database.insert_user(User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            ..Default::default()
+        });
+
+        // Unknown email responds the same but sends no mail
+        let req = Request::with_url("http://localhost/auth/magic_link")
+            .method(Method::Post)
+            .body("email=unknown@example.com");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        assert!(res.body.is_empty());
+        assert!(outbox.mails().is_empty());
+
+        // Request magic link mail
+        let req = Request::with_url("http://localhost/auth/magic_link")
+            .method(Method::Post)
+            .body("email=test@example.com");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        assert!(res.body.is_empty());
+        let mails = outbox.mails();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].to, "test@example.com");
+        let token = mails[0]
+            .body
+            .split("token=")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap()
+            .to_string();
+
+        // Login with wrong token
+        let req = Request::with_url("http://localhost/auth/magic_link/login")
+            .method(Method::Post)
+            .body("token=wrong");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+
+        // Login with magic link creates a normal session
+        let req = Request::with_url("http://localhost/auth/magic_link/login")
+            .method(Method::Post)
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64; rv:133.0) Gecko/20100101 Firefox/133.0",
+            )
+            .body(format!("token={}", token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let json = serde_json::from_slice::<api::AuthLoginResponse>(&res.body).unwrap();
+        assert_eq!(json.user.username, "test");
+        assert!(json.user.email_verified_at.is_some());
+        assert_eq!(json.session.client_name, Some("Firefox".to_string()));
+
+        // Magic link can't be reused
+        let req = Request::with_url("http://localhost/auth/magic_link/login")
+            .method(Method::Post)
+            .body(format!("token={}", token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+    }
+
+    // MARK: Test Auth magic link rate limit
+    #[test]
+    fn test_auth_magic_link_rate_limit() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+
+        // Unknown emails are limited the same as existing ones
+        for _ in 0..MAGIC_LINK_REQUESTS_PER_EMAIL {
+            let req = Request::with_url("http://localhost/auth/magic_link")
+                .method(Method::Post)
+                .body("email=unknown@example.com");
+            let res = router.handle(&req);
+            assert_eq!(res.status, Status::Ok);
+        }
+        let req = Request::with_url("http://localhost/auth/magic_link")
+            .method(Method::Post)
+            .body("email=Unknown@Example.com");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::TooManyRequests);
+        assert!(res.headers.get("Retry-After").is_some());
+    }
+
     // MARK: Test Auth validate
     #[test]
     fn test_auth_validate() {
@@ -0,0 +1,214 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use small_http::{Request, Response, Status};
+use uuid::Uuid;
+use validate::{Report, Validate};
+
+use crate::controllers::not_found;
+use crate::database::Extension;
+use crate::models::invite_code::count_remaining_invite_uses;
+use crate::models::InviteCode;
+use crate::permissions::{authorize, can, Ability};
+use crate::{api, Context};
+
+// MARK: Helpers
+fn find_invite_code(req: &Request, ctx: &Context) -> Option<InviteCode> {
+    let invite_code_id = match req
+        .params
+        .get("invite_code_id")
+        .expect("Should be some")
+        .parse::<Uuid>()
+    {
+        Ok(id) => id,
+        Err(_) => return None,
+    };
+    ctx.database
+        .query::<InviteCode>(
+            formatcp!(
+                "SELECT {} FROM invite_codes WHERE id = ? LIMIT 1",
+                InviteCode::columns()
+            ),
+            invite_code_id,
+        )
+        .next()
+}
+
+// MARK: Invite codes create
+#[derive(Validate)]
+struct InviteCodeCreateBody {
+    #[validate(range(min = 1, max = 1000))]
+    max_uses: i64,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<api::InviteCodeCreateBody> for InviteCodeCreateBody {
+    fn from(body: api::InviteCodeCreateBody) -> Self {
+        Self {
+            max_uses: body.max_uses.unwrap_or(1),
+            expires_at: body.expires_at,
+        }
+    }
+}
+
+pub fn invite_codes_create(req: &Request, ctx: &Context) -> Response {
+    // Parse and validate body
+    let body = match serde_urlencoded::from_bytes::<api::InviteCodeCreateBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => Into::<InviteCodeCreateBody>::into(body),
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    if let Err(errors) = body.validate() {
+        return Response::new().status(Status::BadRequest).json(errors);
+    }
+
+    // Authorization, admins can always create invite codes and users within their quota of uses
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+    if !can(auth_user, Ability::InviteCodeCreate, None)
+        && count_remaining_invite_uses(ctx, auth_user.id) + body.max_uses
+            > ctx.settings.user_invite_quota
+    {
+        let mut report = Report::new();
+        report.insert_error("inviteCode", "invite quota reached");
+        return Response::new().status(Status::Forbidden).json(report);
+    }
+
+    // Create new invite code
+    let invite_code = InviteCode {
+        user_id: auth_user.id,
+        max_uses: body.max_uses,
+        expires_at: body.expires_at,
+        ..Default::default()
+    };
+    ctx.database.insert_invite_code(invite_code.clone());
+
+    Response::new().json(Into::<api::InviteCode>::into(invite_code))
+}
+
+// MARK: Invite codes delete
+pub fn invite_codes_delete(req: &Request, ctx: &Context) -> Response {
+    let invite_code = match find_invite_code(req, ctx) {
+        Some(invite_code) => invite_code,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::InviteCodeDelete, Some(invite_code.user_id)) {
+        return res;
+    }
+
+    ctx.database
+        .execute("DELETE FROM invite_codes WHERE id = ?", invite_code.id);
+    Response::new()
+}
+
+#[cfg(test)]
+mod test {
+    use small_http::Method;
+
+    use super::*;
+    use crate::models::UserRole;
+    use crate::router;
+    use crate::test_utils::create_user_session;
+
+    // MARK: Test Invite codes create
+    #[test]
+    fn test_invite_codes_create() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, admin_session) = create_user_session(&ctx, UserRole::Admin);
+
+        let req = Request::with_url("http://localhost/invite_codes")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", admin_session.token))
+            .body("maxUses=5");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::InviteCode>(&res.body).unwrap();
+        assert_eq!(res.max_uses, 5);
+        assert_eq!(res.uses, 0);
+
+        // Invalid max uses
+        let req = Request::with_url("http://localhost/invite_codes")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", admin_session.token))
+            .body("maxUses=0");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+    }
+
+    #[test]
+    fn test_invite_codes_create_quota() {
+        let mut ctx = Context::with_test_database();
+        ctx.settings.user_invite_quota = 1;
+        let router = router(ctx.clone());
+        let (_, session) = create_user_session(&ctx, UserRole::Normal);
+
+        // User can create invite codes within the quota
+        let req = Request::with_url("http://localhost/invite_codes")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        let req = Request::with_url("http://localhost/invite_codes")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+    }
+
+    #[test]
+    fn test_invite_codes_create_quota_max_uses() {
+        let mut ctx = Context::with_test_database();
+        ctx.settings.user_invite_quota = 1;
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+
+        // Quota limits the uses, not the number of codes
+        let req = Request::with_url("http://localhost/invite_codes")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("maxUses=5");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+        assert_eq!(count_remaining_invite_uses(&ctx, user.id), 0);
+    }
+
+    // MARK: Test Invite codes delete
+    #[test]
+    fn test_invite_codes_delete() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let invite_code = InviteCode {
+            user_id: user.id,
+            ..Default::default()
+        };
+        ctx.database.insert_invite_code(invite_code.clone());
+
+        // Other user can't delete invite code
+        let (_, other_session) = create_user_session(&ctx, UserRole::Normal);
+        let req = Request::with_url(format!("http://localhost/invite_codes/{}", invite_code.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", other_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        let req = Request::with_url(format!("http://localhost/invite_codes/{}", invite_code.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+}
@@ -9,15 +9,22 @@ use small_http::{Request, Response, Status};
 use crate::{api, Context};
 
 pub mod auth;
+pub mod invite_codes;
+pub mod oauth;
+pub mod oidc;
+pub mod personal_access_tokens;
+pub mod post_filters;
 pub mod posts;
 pub mod sessions;
+pub mod two_factor;
 pub mod users;
 
 // MARK: Home
-pub fn home(_: &Request, _: &Context) -> Response {
+pub fn home(_: &Request, ctx: &Context) -> Response {
     Response::new().json(api::HomeResponse {
         name: "PlaatBook".to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
+        registration_mode: ctx.settings.registration_mode.name().to_string(),
     })
 }
 
@@ -44,6 +51,7 @@ mod test {
         let json = serde_json::from_slice::<api::HomeResponse>(&res.body).unwrap();
         assert_eq!(json.name, "PlaatBook");
         assert_eq!(json.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(json.registration_mode, "open");
     }
 
     // MARK: Test Not Found
@@ -0,0 +1,744 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use const_format::formatcp;
+use serde::{Deserialize, Serialize};
+use small_http::{Request, Response, Status};
+use uuid::Uuid;
+use validate::{Report, Validate};
+
+use crate::controllers::auth::{generate_url_token, hash_token};
+use crate::controllers::not_found;
+use crate::database::Extension;
+use crate::models::oauth::{
+    find_oauth_app, find_oauth_grant, find_valid_oauth_refresh_token, issue_oauth_token,
+    take_oauth_authorization_code, upsert_oauth_grant, OAUTH_ACCESS_TOKEN_EXPIRE_DURATION,
+};
+use crate::models::security_event::record_security_event;
+use crate::models::user_deletion::find_user_deletion;
+use crate::models::user_suspension::find_active_user_suspension;
+use crate::models::{OAuthApp, OAuthAuthorizationCode, OAuthGrant, SecurityEventType, TokenScope};
+use crate::permissions::{authorize, deny_impersonation, Ability};
+use crate::{api, Context};
+
+// MARK: Helpers
+fn find_oauth_app_by_param(req: &Request, ctx: &Context) -> Option<OAuthApp> {
+    let oauth_app_id = match req
+        .params
+        .get("oauth_app_id")
+        .expect("Should be some")
+        .parse::<Uuid>()
+    {
+        Ok(id) => id,
+        Err(_) => return None,
+    };
+    find_oauth_app(ctx, oauth_app_id)
+}
+
+fn find_oauth_grant_by_param(req: &Request, ctx: &Context) -> Option<OAuthGrant> {
+    let oauth_grant_id = match req
+        .params
+        .get("oauth_grant_id")
+        .expect("Should be some")
+        .parse::<Uuid>()
+    {
+        Ok(id) => id,
+        Err(_) => return None,
+    };
+    ctx.database
+        .query::<OAuthGrant>(
+            formatcp!(
+                "SELECT {} FROM oauth_grants WHERE id = ? LIMIT 1",
+                OAuthGrant::columns()
+            ),
+            oauth_grant_id,
+        )
+        .next()
+}
+
+// Allows https, loopback http for development and private-use schemes for native apps (RFC 8252)
+fn is_redirect_uri(value: &str, _: &Context) -> validate::Result {
+    let is_valid = match value.split_once(':') {
+        Some(("https", rest)) => rest.starts_with("//"),
+        Some(("http", _)) => {
+            value.starts_with("http://localhost") || value.starts_with("http://127.0.0.1")
+        }
+        Some((scheme, _)) => scheme.contains('.'),
+        None => false,
+    };
+    if !is_valid || value.contains('#') {
+        return Err(validate::Error::new("invalid redirect uri"));
+    }
+    Ok(())
+}
+
+// OAuth clients expect the parameter and field names of RFC 6749, so these don't use the api types
+#[derive(Deserialize)]
+struct OAuthAuthorizeParams {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    #[serde(default)]
+    scope: String,
+    #[serde(default)]
+    state: String,
+    #[serde(default)]
+    code_challenge: String,
+    #[serde(default)]
+    code_challenge_method: String,
+}
+
+fn validate_authorize_params(
+    ctx: &Context,
+    params: &OAuthAuthorizeParams,
+) -> Result<(OAuthApp, i64), Report> {
+    let mut report = Report::new();
+    let oauth_app = match params
+        .client_id
+        .parse::<Uuid>()
+        .ok()
+        .and_then(|client_id| find_oauth_app(ctx, client_id))
+    {
+        Some(oauth_app) => oauth_app,
+        None => {
+            report.insert_error("client_id", "Unknown client");
+            return Err(report);
+        }
+    };
+    if params.redirect_uri != oauth_app.redirect_uri {
+        report.insert_error(
+            "redirect_uri",
+            "Redirect URI doesn't match the registered redirect URI",
+        );
+        return Err(report);
+    }
+    if params.response_type != "code" {
+        report.insert_error("response_type", "Only the code response type is supported");
+        return Err(report);
+    }
+    if params.code_challenge_method != "S256" || params.code_challenge.len() != 43 {
+        report.insert_error("code_challenge", "A S256 PKCE code challenge is required");
+        return Err(report);
+    }
+    let scopes = match TokenScope::parse_list(
+        &params
+            .scope
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(","),
+    ) {
+        Some(scopes) => scopes,
+        None => {
+            report.insert_error("scope", "Unknown scope");
+            return Err(report);
+        }
+    };
+    Ok((oauth_app, scopes))
+}
+
+// MARK: OAuth apps create
+#[derive(Validate)]
+#[validate(context(Context))]
+struct OAuthAppCreateBody {
+    #[validate(length(min = 1, max = 64))]
+    name: String,
+    #[validate(length(max = 512), custom(is_redirect_uri))]
+    redirect_uri: String,
+}
+
+impl From<api::OAuthAppCreateBody> for OAuthAppCreateBody {
+    fn from(body: api::OAuthAppCreateBody) -> Self {
+        Self {
+            name: body.name,
+            redirect_uri: body.redirect_uri,
+        }
+    }
+}
+
+pub fn oauth_apps_create(req: &Request, ctx: &Context) -> Response {
+    // Authorization
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+
+    // Parse and validate body
+    let body = match serde_urlencoded::from_bytes::<api::OAuthAppCreateBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => Into::<OAuthAppCreateBody>::into(body),
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    if let Err(errors) = body.validate_with(ctx) {
+        return Response::new().status(Status::BadRequest).json(errors);
+    }
+
+    // Register new OAuth app, its id is the client id
+    let oauth_app = OAuthApp {
+        user_id: auth_user.id,
+        name: body.name,
+        redirect_uri: body.redirect_uri,
+        ..Default::default()
+    };
+    ctx.database.insert_oauth_app(oauth_app.clone());
+
+    Response::new().json(Into::<api::OAuthApp>::into(oauth_app))
+}
+
+// MARK: OAuth apps delete
+pub fn oauth_apps_delete(req: &Request, ctx: &Context) -> Response {
+    let oauth_app = match find_oauth_app_by_param(req, ctx) {
+        Some(oauth_app) => oauth_app,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::OAuthAppDelete, Some(oauth_app.user_id)) {
+        return res;
+    }
+
+    // Deleting the app also deletes all its grants and tokens
+    ctx.database
+        .execute("DELETE FROM oauth_apps WHERE id = ?", oauth_app.id);
+    Response::new()
+}
+
+// MARK: OAuth authorize
+pub fn oauth_authorize(req: &Request, ctx: &Context) -> Response {
+    // Parse and validate query
+    let params =
+        match serde_urlencoded::from_str::<OAuthAuthorizeParams>(req.url.query().unwrap_or("")) {
+            Ok(params) => params,
+            Err(_) => return Response::with_status(Status::BadRequest),
+        };
+    let (oauth_app, scopes) = match validate_authorize_params(ctx, &params) {
+        Ok(result) => result,
+        Err(report) => return Response::with_status(Status::BadRequest).json(report),
+    };
+
+    // Return app and scopes for the consent screen
+    Response::new().json(api::OAuthAuthorizeResponse {
+        app: oauth_app.into(),
+        scopes: TokenScope::names(scopes),
+    })
+}
+
+// MARK: OAuth authorize approve
+pub fn oauth_authorize_approve(req: &Request, ctx: &Context) -> Response {
+    // Authorization
+    if let Some(res) = deny_impersonation(ctx) {
+        return res;
+    }
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+
+    // Parse and validate body
+    let params = match serde_urlencoded::from_bytes::<OAuthAuthorizeParams>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(params) => params,
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    let (oauth_app, scopes) = match validate_authorize_params(ctx, &params) {
+        Ok(result) => result,
+        Err(report) => return Response::new().status(Status::BadRequest).json(report),
+    };
+
+    // Store consent and create authorization code, the grant gives the app token access
+    upsert_oauth_grant(ctx, oauth_app.id, auth_user.id, scopes);
+    record_security_event(req, ctx, auth_user.id, SecurityEventType::TokenCreated);
+    let code = generate_url_token();
+    ctx.database
+        .insert_oauth_authorization_code(OAuthAuthorizationCode {
+            app_id: oauth_app.id,
+            user_id: auth_user.id,
+            code: hash_token(&code),
+            redirect_uri: oauth_app.redirect_uri.clone(),
+            scopes,
+            code_challenge: params.code_challenge,
+            ..Default::default()
+        });
+
+    // Return redirect uri with code and state for the client to redirect to
+    let mut query = vec![("code", code)];
+    if !params.state.is_empty() {
+        query.push(("state", params.state));
+    }
+    Response::new().json(api::OAuthAuthorizeApproveResponse {
+        redirect_uri: format!(
+            "{}{}{}",
+            oauth_app.redirect_uri,
+            if oauth_app.redirect_uri.contains('?') {
+                '&'
+            } else {
+                '?'
+            },
+            serde_urlencoded::to_string(query).expect("Can't encode query")
+        ),
+    })
+}
+
+// MARK: OAuth token
+#[derive(Deserialize)]
+struct OAuthTokenBody {
+    grant_type: String,
+    client_id: String,
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    redirect_uri: String,
+    #[serde(default)]
+    code_verifier: String,
+    #[serde(default)]
+    refresh_token: String,
+}
+
+#[derive(Serialize)]
+struct OAuthTokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+    refresh_token: String,
+    scope: String,
+}
+
+#[derive(Serialize)]
+struct OAuthErrorResponse {
+    error: &'static str,
+}
+
+fn oauth_error(error: &'static str) -> Response {
+    Response::new()
+        .status(Status::BadRequest)
+        .json(OAuthErrorResponse { error })
+}
+
+// Suspended users and users pending deletion can't get new tokens
+fn is_active_user(ctx: &Context, user_id: Uuid) -> bool {
+    find_active_user_suspension(ctx, user_id).is_none()
+        && find_user_deletion(ctx, user_id).is_none()
+}
+
+pub fn oauth_token(req: &Request, ctx: &Context) -> Response {
+    // Parse body
+    let body =
+        match serde_urlencoded::from_bytes::<OAuthTokenBody>(req.body.as_deref().unwrap_or(&[])) {
+            Ok(body) => body,
+            Err(_) => return oauth_error("invalid_request"),
+        };
+    let client_id = match body.client_id.parse::<Uuid>() {
+        Ok(client_id) => client_id,
+        Err(_) => return oauth_error("invalid_client"),
+    };
+
+    // Get grant and scopes for the new token pair
+    let (oauth_grant, scopes) = match body.grant_type.as_str() {
+        "authorization_code" => {
+            let authorization_code = match take_oauth_authorization_code(ctx, &body.code) {
+                Some(authorization_code) => authorization_code,
+                None => return oauth_error("invalid_grant"),
+            };
+            if authorization_code.app_id != client_id
+                || authorization_code.redirect_uri != body.redirect_uri
+                || !authorization_code.verify_code_verifier(&body.code_verifier)
+                || !is_active_user(ctx, authorization_code.user_id)
+            {
+                return oauth_error("invalid_grant");
+            }
+            match find_oauth_grant(ctx, client_id, authorization_code.user_id) {
+                Some(oauth_grant) => (oauth_grant, authorization_code.scopes),
+                None => return oauth_error("invalid_grant"),
+            }
+        }
+        "refresh_token" => {
+            let oauth_token = match find_valid_oauth_refresh_token(ctx, &body.refresh_token)
+                .filter(|oauth_token| oauth_token.app_id == client_id)
+            {
+                Some(oauth_token) => oauth_token,
+                None => return oauth_error("invalid_grant"),
+            };
+            if !is_active_user(ctx, oauth_token.user_id) {
+                return oauth_error("invalid_grant");
+            }
+
+            // Refresh tokens rotate, the old token pair stops working
+            ctx.database
+                .execute("DELETE FROM oauth_tokens WHERE id = ?", oauth_token.id);
+            match find_oauth_grant(ctx, client_id, oauth_token.user_id) {
+                Some(oauth_grant) => (oauth_grant, oauth_token.scopes),
+                None => return oauth_error("invalid_grant"),
+            }
+        }
+        _ => return oauth_error("unsupported_grant_type"),
+    };
+
+    // Issue new token pair
+    let (oauth_token, access_token, refresh_token) = issue_oauth_token(ctx, &oauth_grant, scopes);
+    Response::new()
+        .header("Cache-Control", "no-store")
+        .json(OAuthTokenResponse {
+            access_token,
+            token_type: "Bearer",
+            expires_in: OAUTH_ACCESS_TOKEN_EXPIRE_DURATION.as_secs(),
+            refresh_token,
+            scope: TokenScope::names(oauth_token.scopes).join(" "),
+        })
+}
+
+// MARK: OAuth revoke
+#[derive(Deserialize)]
+struct OAuthRevokeBody {
+    token: String,
+    client_id: String,
+}
+
+pub fn oauth_revoke(req: &Request, ctx: &Context) -> Response {
+    // Parse body
+    let body =
+        match serde_urlencoded::from_bytes::<OAuthRevokeBody>(req.body.as_deref().unwrap_or(&[])) {
+            Ok(body) => body,
+            Err(_) => return oauth_error("invalid_request"),
+        };
+
+    // Revoke token pair by access or refresh token, unknown tokens are no error (RFC 7009)
+    let token_hash = hash_token(&body.token);
+    ctx.database.execute(
+        "DELETE FROM oauth_tokens WHERE (access_token = ? OR refresh_token = ?) AND app_id = ?",
+        (
+            token_hash.clone(),
+            token_hash,
+            body.client_id.parse::<Uuid>().unwrap_or(Uuid::nil()),
+        ),
+    );
+    Response::new()
+}
+
+// MARK: OAuth grants delete
+pub fn oauth_grants_delete(req: &Request, ctx: &Context) -> Response {
+    let oauth_grant = match find_oauth_grant_by_param(req, ctx) {
+        Some(oauth_grant) => oauth_grant,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::OAuthGrantRevoke, Some(oauth_grant.user_id)) {
+        return res;
+    }
+
+    // Revoking the grant also deletes all tokens of the app for the user
+    ctx.database
+        .execute("DELETE FROM oauth_grants WHERE id = ?", oauth_grant.id);
+    Response::new()
+}
+
+#[cfg(test)]
+mod test {
+    use small_http::Method;
+
+    use super::*;
+    use crate::models::{UserRole, UserSuspension};
+    use crate::router;
+    use crate::test_utils::{create_user, create_user_session};
+
+    // Example verifier and challenge from RFC 7636 appendix B
+    const CODE_VERIFIER: &str = "dBjjJiEjHBgQpS2eYn1u7ZKyG2YrCCAmpUt4Hj5kPwc";
+    const CODE_CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    fn create_oauth_app(ctx: &Context, user_id: Uuid) -> OAuthApp {
+        let oauth_app = OAuthApp {
+            user_id,
+            name: "Client".to_string(),
+            redirect_uri: "https://client.example.com/callback".to_string(),
+            ..Default::default()
+        };
+        ctx.database.insert_oauth_app(oauth_app.clone());
+        oauth_app
+    }
+
+    fn authorize_params(oauth_app: &OAuthApp, scope: &str) -> String {
+        serde_urlencoded::to_string([
+            ("response_type", "code"),
+            ("client_id", &oauth_app.id.to_string()),
+            ("redirect_uri", &oauth_app.redirect_uri),
+            ("scope", scope),
+            ("state", "xyz"),
+            ("code_challenge", CODE_CHALLENGE),
+            ("code_challenge_method", "S256"),
+        ])
+        .unwrap()
+    }
+
+    fn token_response(res: &Response) -> serde_json::Value {
+        serde_json::from_slice::<serde_json::Value>(&res.body).unwrap()
+    }
+
+    // MARK: Test OAuth apps create
+    #[test]
+    fn test_oauth_apps_create() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, session) = create_user_session(&ctx, UserRole::Normal);
+
+        let req = Request::with_url("http://localhost/oauth/apps")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("name=Client&redirectUri=https%3A%2F%2Fclient.example.com%2Fcallback");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::OAuthApp>(&res.body).unwrap();
+        assert_eq!(res.redirect_uri, "https://client.example.com/callback");
+
+        // Unsafe redirect uri
+        let req = Request::with_url("http://localhost/oauth/apps")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("name=Client&redirectUri=javascript%3Aalert(1)");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+    }
+
+    // MARK: Test OAuth authorization code flow
+    #[test]
+    fn test_oauth_authorization_code_flow() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let oauth_app = create_oauth_app(&ctx, user.id);
+
+        // Consent screen shows app and requested scopes
+        let req = Request::with_url(format!(
+            "http://localhost/oauth/authorize?{}",
+            authorize_params(&oauth_app, "posts:read posts:write")
+        ))
+        .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::OAuthAuthorizeResponse>(&res.body).unwrap();
+        assert_eq!(res.app.name, "Client");
+        assert_eq!(res.scopes, ["posts:read", "posts:write"]);
+
+        // Approve and get code from redirect uri
+        let req = Request::with_url("http://localhost/oauth/authorize")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body(authorize_params(&oauth_app, "posts:read posts:write"));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::OAuthAuthorizeApproveResponse>(&res.body).unwrap();
+        let (_, query) = res.redirect_uri.split_once('?').unwrap();
+        let query = serde_urlencoded::from_str::<Vec<(String, String)>>(query).unwrap();
+        assert_eq!(query[1], ("state".to_string(), "xyz".to_string()));
+        let code = query[0].1.clone();
+        let token_created_count = ctx
+            .database
+            .query::<i64>(
+                formatcp!(
+                    "SELECT COUNT(id) FROM security_events WHERE user_id = ? AND type = {}",
+                    SecurityEventType::TokenCreated as i64
+                ),
+                user.id,
+            )
+            .next()
+            .unwrap();
+        assert_eq!(token_created_count, 1);
+
+        // Wrong code verifier is rejected and burns the code
+        let token_body = |code_verifier: &str| {
+            serde_urlencoded::to_string([
+                ("grant_type", "authorization_code"),
+                ("client_id", &oauth_app.id.to_string()),
+                ("code", &code),
+                ("redirect_uri", &oauth_app.redirect_uri),
+                ("code_verifier", code_verifier),
+            ])
+            .unwrap()
+        };
+        let req = Request::with_url("http://localhost/oauth/token")
+            .method(Method::Post)
+            .body(token_body("wrong"));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+        assert_eq!(token_response(&res)["error"], "invalid_grant");
+        let req = Request::with_url("http://localhost/oauth/token")
+            .method(Method::Post)
+            .body(token_body(CODE_VERIFIER));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Approve again and exchange code for tokens
+        let req = Request::with_url("http://localhost/oauth/authorize")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body(authorize_params(&oauth_app, "posts:read"));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::OAuthAuthorizeApproveResponse>(&res.body).unwrap();
+        let (_, query) = res.redirect_uri.split_once('?').unwrap();
+        let query = serde_urlencoded::from_str::<Vec<(String, String)>>(query).unwrap();
+        let code = query[0].1.clone();
+        let req = Request::with_url("http://localhost/oauth/token")
+            .method(Method::Post)
+            .body(
+                serde_urlencoded::to_string([
+                    ("grant_type", "authorization_code"),
+                    ("client_id", &oauth_app.id.to_string()),
+                    ("code", &code),
+                    ("redirect_uri", &oauth_app.redirect_uri),
+                    ("code_verifier", CODE_VERIFIER),
+                ])
+                .unwrap(),
+            );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let tokens = token_response(&res);
+        assert_eq!(tokens["token_type"], "Bearer");
+        assert_eq!(tokens["scope"], "posts:read");
+        let access_token = tokens["access_token"].as_str().unwrap().to_string();
+        let refresh_token = tokens["refresh_token"].as_str().unwrap().to_string();
+
+        // Access token works with the auth layer within its scopes
+        let req = Request::with_url(format!("http://localhost/users/{}/posts", user.id))
+            .header("Authorization", format!("Bearer {}", access_token));
+        assert_eq!(router.handle(&req).status, Status::Ok);
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .header("Authorization", format!("Bearer {}", access_token));
+        assert_eq!(router.handle(&req).status, Status::Forbidden);
+
+        // Refresh rotates the token pair
+        let req = Request::with_url("http://localhost/oauth/token")
+            .method(Method::Post)
+            .body(
+                serde_urlencoded::to_string([
+                    ("grant_type", "refresh_token"),
+                    ("client_id", &oauth_app.id.to_string()),
+                    ("refresh_token", &refresh_token),
+                ])
+                .unwrap(),
+            );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let new_access_token = token_response(&res)["access_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let req = Request::with_url("http://localhost/users")
+            .header("Authorization", format!("Bearer {}", access_token));
+        assert_eq!(router.handle(&req).status, Status::Unauthorized);
+        let req = Request::with_url("http://localhost/users")
+            .header("Authorization", format!("Bearer {}", new_access_token));
+        assert_eq!(router.handle(&req).status, Status::Forbidden);
+
+        // Revoke new access token
+        let req = Request::with_url("http://localhost/oauth/revoke")
+            .method(Method::Post)
+            .body(format!(
+                "token={}&client_id={}",
+                new_access_token, oauth_app.id
+            ));
+        assert_eq!(router.handle(&req).status, Status::Ok);
+        let req = Request::with_url("http://localhost/users")
+            .header("Authorization", format!("Bearer {}", new_access_token));
+        assert_eq!(router.handle(&req).status, Status::Unauthorized);
+        let count = ctx
+            .database
+            .query::<i64>("SELECT COUNT(id) FROM oauth_tokens", ())
+            .next()
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_oauth_tokens_suspended_user() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let admin = create_user(&ctx, UserRole::Admin);
+        let (user, _) = create_user_session(&ctx, UserRole::Normal);
+        let oauth_app = create_oauth_app(&ctx, user.id);
+        let oauth_grant = upsert_oauth_grant(&ctx, oauth_app.id, user.id, TokenScope::ALL);
+        let (_, access_token, refresh_token) =
+            issue_oauth_token(&ctx, &oauth_grant, TokenScope::ALL);
+        ctx.database.insert_user_suspension(UserSuspension {
+            user_id: user.id,
+            suspended_by_user_id: Some(admin.id),
+            ..Default::default()
+        });
+
+        // Access token of suspended user is rejected
+        let req = Request::with_url(format!("http://localhost/users/{}/posts", user.id))
+            .header("Authorization", format!("Bearer {}", access_token));
+        assert_eq!(router.handle(&req).status, Status::Forbidden);
+
+        // Refresh token can't be used and stays valid for when the suspension is lifted
+        let req = Request::with_url("http://localhost/oauth/token")
+            .method(Method::Post)
+            .body(
+                serde_urlencoded::to_string([
+                    ("grant_type", "refresh_token"),
+                    ("client_id", &oauth_app.id.to_string()),
+                    ("refresh_token", &refresh_token),
+                ])
+                .unwrap(),
+            );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+        assert_eq!(token_response(&res)["error"], "invalid_grant");
+        ctx.database
+            .execute("DELETE FROM user_suspensions WHERE user_id = ?", user.id);
+        assert_eq!(router.handle(&req).status, Status::Ok);
+    }
+
+    // MARK: Test OAuth authorize
+    #[test]
+    fn test_oauth_authorize_invalid() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let oauth_app = create_oauth_app(&ctx, user.id);
+
+        // Redirect uri must match the registered redirect uri
+        let params = authorize_params(&oauth_app, "posts:read")
+            .replace("client.example.com", "evil.example.com");
+        let req = Request::with_url(format!("http://localhost/oauth/authorize?{}", params))
+            .header("Authorization", format!("Bearer {}", session.token));
+        assert_eq!(router.handle(&req).status, Status::BadRequest);
+
+        // PKCE is required
+        let params = authorize_params(&oauth_app, "posts:read").replace("S256", "plain");
+        let req = Request::with_url(format!("http://localhost/oauth/authorize?{}", params))
+            .header("Authorization", format!("Bearer {}", session.token));
+        assert_eq!(router.handle(&req).status, Status::BadRequest);
+
+        // Unknown scope
+        let params = authorize_params(&oauth_app, "posts:read admin");
+        let req = Request::with_url(format!("http://localhost/oauth/authorize?{}", params))
+            .header("Authorization", format!("Bearer {}", session.token));
+        assert_eq!(router.handle(&req).status, Status::BadRequest);
+    }
+
+    // MARK: Test OAuth grants delete
+    #[test]
+    fn test_oauth_grants_delete() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let oauth_app = create_oauth_app(&ctx, user.id);
+        let oauth_grant = upsert_oauth_grant(&ctx, oauth_app.id, user.id, TokenScope::ALL);
+        let (_, access_token, _) = issue_oauth_token(&ctx, &oauth_grant, TokenScope::ALL);
+
+        let req = Request::with_url(format!("http://localhost/oauth/grants/{}", oauth_grant.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", session.token));
+        assert_eq!(router.handle(&req).status, Status::Ok);
+
+        // Tokens of the revoked grant stop working
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .header("Authorization", format!("Bearer {}", access_token));
+        assert_eq!(router.handle(&req).status, Status::Unauthorized);
+    }
+}
@@ -0,0 +1,423 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use chrono::Utc;
+use const_format::formatcp;
+use small_http::{Request, Response, Status};
+use validate::Report;
+
+use crate::controllers::auth::{
+    complete_login, generate_code_challenge, generate_random_token, generate_url_token, hash_token,
+};
+use crate::controllers::not_found;
+use crate::database::Extension;
+use crate::models::oidc_login_request::take_oidc_login_request;
+use crate::models::security_event::record_security_event;
+use crate::models::user::{is_unique_email, is_unique_username};
+use crate::models::user_identity::find_user_identity;
+use crate::models::user_suspension::find_active_user_suspension;
+use crate::models::{OidcLoginRequest, SecurityEventType, User, UserIdentity, UserRole};
+use crate::oidc::{
+    exchange_code, fetch_json_web_key_set, fetch_provider_metadata, verify_id_token, IdTokenClaims,
+};
+use crate::password::password_hash;
+use crate::permissions::deny_impersonation;
+use crate::{api, Context};
+
+// MARK: Helpers
+fn oidc_error(status: Status, message: &str) -> Response {
+    let mut report = Report::new();
+    report.insert_error("oidc", message);
+    Response::new().status(status).json(report)
+}
+
+// Derives a unique username from the claims for auto provisioned users
+fn provision_username(ctx: &Context, claims: &IdTokenClaims) -> String {
+    let base = claims
+        .preferred_username
+        .as_deref()
+        .or_else(|| {
+            claims
+                .email
+                .as_deref()
+                .and_then(|email| email.split('@').next())
+        })
+        .unwrap_or_default()
+        .chars()
+        .filter(|char| char.is_ascii_alphanumeric() || matches!(char, '_' | '-' | '.'))
+        .take(24)
+        .collect::<String>();
+    let base = if base.is_empty() {
+        "user".to_string()
+    } else {
+        base
+    };
+
+    let mut username = base.clone();
+    let mut suffix = 2;
+    while is_unique_username(&username, ctx).is_err() {
+        username = format!("{}{}", base, suffix);
+        suffix += 1;
+    }
+    username
+}
+
+// MARK: Auth OIDC authorize
+pub fn auth_oidc_authorize(req: &Request, ctx: &Context) -> Response {
+    // Single sign-on is disabled when no issuer is configured
+    if ctx.settings.oidc_issuer_url.is_empty() {
+        return not_found(req, ctx);
+    }
+    let metadata = match fetch_provider_metadata(&ctx.settings) {
+        Some(metadata) => metadata,
+        None => return oidc_error(Status::BadRequest, "provider unavailable"),
+    };
+
+    // Store login request, the state is only stored hashed
+    let state = generate_url_token();
+    let oidc_login_request = OidcLoginRequest {
+        state: hash_token(&state),
+        nonce: generate_url_token(),
+        code_verifier: generate_url_token(),
+        ..Default::default()
+    };
+    ctx.database
+        .insert_oidc_login_request(oidc_login_request.clone());
+
+    let code_challenge = generate_code_challenge(&oidc_login_request.code_verifier);
+    let query = [
+        ("response_type", "code"),
+        ("client_id", ctx.settings.oidc_client_id.as_str()),
+        ("redirect_uri", ctx.settings.oidc_redirect_uri.as_str()),
+        ("scope", ctx.settings.oidc_scopes.as_str()),
+        ("state", state.as_str()),
+        ("nonce", oidc_login_request.nonce.as_str()),
+        ("code_challenge", code_challenge.as_str()),
+        ("code_challenge_method", "S256"),
+    ];
+    Response::new().json(api::AuthOidcAuthorizeResponse {
+        authorization_url: format!(
+            "{}{}{}",
+            metadata.authorization_endpoint,
+            if metadata.authorization_endpoint.contains('?') {
+                '&'
+            } else {
+                '?'
+            },
+            serde_urlencoded::to_string(query).expect("Can't encode query")
+        ),
+    })
+}
+
+// MARK: Auth OIDC callback
+pub fn auth_oidc_callback(req: &Request, ctx: &Context) -> Response {
+    if ctx.settings.oidc_issuer_url.is_empty() {
+        return not_found(req, ctx);
+    }
+
+    // Parse body
+    let body = match serde_urlencoded::from_bytes::<api::AuthOidcCallbackBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => body,
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+
+    // Exchange code and validate ID token
+    let oidc_login_request = match take_oidc_login_request(ctx, &body.state) {
+        Some(oidc_login_request) => oidc_login_request,
+        None => return oidc_error(Status::BadRequest, "invalid or expired state"),
+    };
+    let metadata = match fetch_provider_metadata(&ctx.settings) {
+        Some(metadata) => metadata,
+        None => return oidc_error(Status::BadRequest, "provider unavailable"),
+    };
+    let id_token = match exchange_code(
+        &ctx.settings,
+        &metadata,
+        &body.code,
+        &oidc_login_request.code_verifier,
+    ) {
+        Some(id_token) => id_token,
+        None => return oidc_error(Status::BadRequest, "code exchange failed"),
+    };
+    let json_web_key_set = match fetch_json_web_key_set(&metadata) {
+        Some(json_web_key_set) => json_web_key_set,
+        None => return oidc_error(Status::BadRequest, "provider unavailable"),
+    };
+    let claims = match verify_id_token(
+        &id_token,
+        &json_web_key_set,
+        &metadata.issuer,
+        &ctx.settings.oidc_client_id,
+        &oidc_login_request.nonce,
+    ) {
+        Ok(claims) => claims,
+        Err(error) => return oidc_error(Status::BadRequest, error),
+    };
+
+    // Find user by linked identity, identities are matched on subject and never on email
+    let user_identity = find_user_identity(ctx, &metadata.issuer, &claims.sub);
+    let user = if let Some(user_identity) = user_identity {
+        if ctx
+            .auth_user
+            .as_ref()
+            .is_some_and(|auth_user| auth_user.id != user_identity.user_id)
+        {
+            return oidc_error(
+                Status::Forbidden,
+                "identity is already linked to another account",
+            );
+        }
+        ctx.database
+            .query::<User>(
+                formatcp!("SELECT {} FROM users WHERE id = ? LIMIT 1", User::columns()),
+                user_identity.user_id,
+            )
+            .next()
+            .expect("Should be some")
+    } else if let Some(auth_user) = &ctx.auth_user {
+        // Link identity to the authed user, impersonators can't link their own identity as it
+        // would let them login as the user after the impersonation ended
+        if let Some(res) = deny_impersonation(ctx) {
+            return res;
+        }
+        ctx.database.insert_user_identity(UserIdentity {
+            user_id: auth_user.id,
+            issuer: metadata.issuer.clone(),
+            subject: claims.sub.clone(),
+            ..Default::default()
+        });
+        record_security_event(req, ctx, auth_user.id, SecurityEventType::IdentityLinked);
+        auth_user.clone()
+    } else if ctx.settings.oidc_auto_provision {
+        // Create new user, existing accounts must link the identity themselves
+        let email = match claims.email.as_deref() {
+            Some(email) if claims.email_verified == Some(true) => email,
+            _ => return oidc_error(Status::Forbidden, "verified email address required"),
+        };
+        if is_unique_email(email, ctx).is_err() {
+            return oidc_error(
+                Status::Forbidden,
+                "email address is already used, sign in and link the identity instead",
+            );
+        }
+        let user = User {
+            username: provision_username(ctx, &claims),
+            email: email.to_string(),
+            // Random password, the user can set a password with the forgot password flow
+            password: password_hash(&generate_random_token()),
+            firstname: claims.given_name.clone(),
+            lastname: claims.family_name.clone(),
+            role: UserRole::Normal,
+            email_verified_at: Some(Utc::now()),
+            ..Default::default()
+        };
+        ctx.database.insert_user(user.clone());
+        ctx.database.insert_user_identity(UserIdentity {
+            user_id: user.id,
+            issuer: metadata.issuer.clone(),
+            subject: claims.sub.clone(),
+            ..Default::default()
+        });
+        user
+    } else {
+        return oidc_error(
+            Status::Forbidden,
+            "no PlaatBook account is linked to this identity",
+        );
+    };
+
+    // Check if user is suspended
+    if let Some(user_suspension) = find_active_user_suspension(ctx, user.id) {
+        return oidc_error(Status::Forbidden, &user_suspension.message());
+    }
+
+    complete_login(req, ctx, user, true, body.cookie.unwrap_or(false))
+}
+
+#[cfg(test)]
+mod test {
+    use small_http::Method;
+
+    use super::*;
+    use crate::controllers::auth::generate_session_token;
+    use crate::models::Session;
+    use crate::router;
+    use crate::test_utils::{create_user, create_user_session, MockIdentityProvider};
+
+    fn oidc_login(
+        router: &small_router::Router<Context>,
+        idp: &MockIdentityProvider,
+        claims: serde_json::Value,
+        token: Option<&str>,
+    ) -> Response {
+        let res = router.handle(&Request::with_url("http://localhost/auth/oidc/authorize"));
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::AuthOidcAuthorizeResponse>(&res.body).unwrap();
+        assert!(res.authorization_url.starts_with(&idp.issuer));
+        let (code, state) = idp.authorize(&res.authorization_url, claims);
+
+        let mut req = Request::with_url("http://localhost/auth/oidc/callback")
+            .method(Method::Post)
+            .body(serde_urlencoded::to_string([("code", code), ("state", state)]).unwrap());
+        if let Some(token) = token {
+            req = req.header("Authorization", format!("Bearer {}", token));
+        }
+        router.handle(&req)
+    }
+
+    // MARK: Test Auth OIDC authorize
+    #[test]
+    fn test_auth_oidc_authorize_disabled() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+
+        let res = router.handle(&Request::with_url("http://localhost/auth/oidc/authorize"));
+        assert_eq!(res.status, Status::NotFound);
+    }
+
+    // MARK: Test Auth OIDC callback
+    #[test]
+    fn test_auth_oidc_callback_auto_provision() {
+        let idp = MockIdentityProvider::start();
+        let mut ctx = Context::with_test_database();
+        idp.configure(&mut ctx.settings);
+        ctx.settings.oidc_auto_provision = true;
+        let router = router(ctx.clone());
+        let claims = serde_json::json!({
+            "sub": "1234",
+            "email": "alice@example.com",
+            "email_verified": true,
+            "preferred_username": "alice",
+        });
+
+        // First login creates a verified user
+        let res = oidc_login(&router, &idp, claims.clone(), None);
+        assert_eq!(res.status, Status::Ok);
+        let first = serde_json::from_slice::<api::AuthLoginResponse>(&res.body).unwrap();
+        assert_eq!(first.user.username, "alice");
+        assert!(first.user.email_verified_at.is_some());
+
+        // Next login finds the same user by subject, even when the email changed
+        let mut changed_claims = claims.clone();
+        changed_claims["email"] = "alice@other.com".into();
+        let res = oidc_login(&router, &idp, changed_claims, None);
+        assert_eq!(res.status, Status::Ok);
+        let second = serde_json::from_slice::<api::AuthLoginResponse>(&res.body).unwrap();
+        assert_eq!(second.user.id, first.user.id);
+
+        // Other subject with an email of an existing account is not linked to it
+        let res = oidc_login(
+            &router,
+            &idp,
+            serde_json::json!({
+                "sub": "5678",
+                "email": "alice@example.com",
+                "email_verified": true,
+            }),
+            None,
+        );
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Unverified emails are not provisioned
+        let res = oidc_login(
+            &router,
+            &idp,
+            serde_json::json!({ "sub": "9012", "email": "bob@example.com" }),
+            None,
+        );
+        assert_eq!(res.status, Status::Forbidden);
+    }
+
+    #[test]
+    fn test_auth_oidc_callback_link() {
+        let idp = MockIdentityProvider::start();
+        let mut ctx = Context::with_test_database();
+        idp.configure(&mut ctx.settings);
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let claims = serde_json::json!({ "sub": "1234" });
+
+        // Unlinked identity can't login without auto provisioning
+        let res = oidc_login(&router, &idp, claims.clone(), None);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Authed user links the identity
+        let res = oidc_login(&router, &idp, claims.clone(), Some(&session.token));
+        assert_eq!(res.status, Status::Ok);
+        let count = ctx
+            .database
+            .query::<i64>(
+                "SELECT COUNT(id) FROM security_events WHERE user_id = ? AND type = ?",
+                (user.id, SecurityEventType::IdentityLinked),
+            )
+            .next()
+            .unwrap();
+        assert_eq!(count, 1);
+
+        // Identity can now login
+        let res = oidc_login(&router, &idp, claims, None);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::AuthLoginResponse>(&res.body).unwrap();
+        assert_eq!(res.user.id, user.id);
+
+        // Identity can't be linked to an other user
+        let (_, other_session) = create_user_session(&ctx, UserRole::Normal);
+        let res = oidc_login(
+            &router,
+            &idp,
+            serde_json::json!({ "sub": "1234" }),
+            Some(&other_session.token),
+        );
+        assert_eq!(res.status, Status::Forbidden);
+    }
+
+    #[test]
+    fn test_auth_oidc_callback_link_impersonation() {
+        let idp = MockIdentityProvider::start();
+        let mut ctx = Context::with_test_database();
+        idp.configure(&mut ctx.settings);
+        let router = router(ctx.clone());
+        let admin = create_user(&ctx, UserRole::Admin);
+        let user = create_user(&ctx, UserRole::Normal);
+        let token = generate_session_token();
+        ctx.database.insert_session(Session {
+            user_id: user.id,
+            token: hash_token(&token),
+            impersonator_user_id: Some(admin.id),
+            ..Default::default()
+        });
+
+        // Impersonator can't link an identity to the user
+        let res = oidc_login(
+            &router,
+            &idp,
+            serde_json::json!({ "sub": "1234" }),
+            Some(&token),
+        );
+        assert_eq!(res.status, Status::Forbidden);
+        assert!(find_user_identity(&ctx, &idp.issuer, "1234").is_none());
+    }
+
+    #[test]
+    fn test_auth_oidc_callback_invalid_state() {
+        let idp = MockIdentityProvider::start();
+        let mut ctx = Context::with_test_database();
+        idp.configure(&mut ctx.settings);
+        let router = router(ctx.clone());
+
+        let req = Request::with_url("http://localhost/auth/oidc/callback")
+            .method(Method::Post)
+            .body("code=code&state=unknown");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+    }
+}
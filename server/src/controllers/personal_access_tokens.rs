@@ -0,0 +1,229 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use small_http::{Request, Response, Status};
+use uuid::Uuid;
+use validate::Validate;
+
+use crate::controllers::auth::{generate_random_token, hash_token};
+use crate::controllers::not_found;
+use crate::database::Extension;
+use crate::models::personal_access_token::PERSONAL_ACCESS_TOKEN_PREFIX;
+use crate::models::security_event::record_security_event;
+use crate::models::{PersonalAccessToken, SecurityEventType, TokenScope};
+use crate::permissions::{authorize, deny_impersonation, Ability};
+use crate::{api, Context};
+
+// MARK: Helpers
+fn find_personal_access_token(req: &Request, ctx: &Context) -> Option<PersonalAccessToken> {
+    let personal_access_token_id = match req
+        .params
+        .get("personal_access_token_id")
+        .expect("Should be some")
+        .parse::<Uuid>()
+    {
+        Ok(id) => id,
+        Err(_) => return None,
+    };
+    ctx.database
+        .query::<PersonalAccessToken>(
+            formatcp!(
+                "SELECT {} FROM personal_access_tokens WHERE id = ? LIMIT 1",
+                PersonalAccessToken::columns()
+            ),
+            personal_access_token_id,
+        )
+        .next()
+}
+
+fn is_personal_access_token_scopes(value: &str, _: &Context) -> validate::Result {
+    if TokenScope::parse_list(value).is_none() {
+        return Err(validate::Error::new("unknown scope"));
+    }
+    Ok(())
+}
+
+// MARK: Personal access tokens create
+#[derive(Validate)]
+#[validate(context(Context))]
+struct PersonalAccessTokenCreateBody {
+    #[validate(length(min = 1, max = 64))]
+    name: String,
+    #[validate(custom(is_personal_access_token_scopes))]
+    scopes: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<api::PersonalAccessTokenCreateBody> for PersonalAccessTokenCreateBody {
+    fn from(body: api::PersonalAccessTokenCreateBody) -> Self {
+        Self {
+            name: body.name,
+            scopes: body.scopes,
+            expires_at: body.expires_at,
+        }
+    }
+}
+
+pub fn personal_access_tokens_create(req: &Request, ctx: &Context) -> Response {
+    // Authorization
+    if let Some(res) = deny_impersonation(ctx) {
+        return res;
+    }
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+
+    // Parse and validate body
+    let body = match serde_urlencoded::from_bytes::<api::PersonalAccessTokenCreateBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => Into::<PersonalAccessTokenCreateBody>::into(body),
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    if let Err(errors) = body.validate_with(ctx) {
+        return Response::new().status(Status::BadRequest).json(errors);
+    }
+
+    // Create new personal access token, the plain token is only returned once
+    let token = format!(
+        "{}{}",
+        PERSONAL_ACCESS_TOKEN_PREFIX,
+        generate_random_token()
+    );
+    let personal_access_token = PersonalAccessToken {
+        user_id: auth_user.id,
+        name: body.name,
+        token: hash_token(&token),
+        scopes: TokenScope::parse_list(&body.scopes).expect("Should be valid"),
+        expires_at: body.expires_at,
+        ..Default::default()
+    };
+    ctx.database
+        .insert_personal_access_token(personal_access_token.clone());
+    record_security_event(req, ctx, auth_user.id, SecurityEventType::TokenCreated);
+
+    Response::new().json(api::PersonalAccessTokenCreateResponse {
+        token,
+        personal_access_token: personal_access_token.into(),
+    })
+}
+
+// MARK: Personal access tokens delete
+pub fn personal_access_tokens_delete(req: &Request, ctx: &Context) -> Response {
+    let personal_access_token = match find_personal_access_token(req, ctx) {
+        Some(personal_access_token) => personal_access_token,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(
+        ctx,
+        Ability::PersonalAccessTokenDelete,
+        Some(personal_access_token.user_id),
+    ) {
+        return res;
+    }
+
+    ctx.database.execute(
+        "DELETE FROM personal_access_tokens WHERE id = ?",
+        personal_access_token.id,
+    );
+    Response::new()
+}
+
+#[cfg(test)]
+mod test {
+    use small_http::Method;
+
+    use super::*;
+    use crate::models::UserRole;
+    use crate::router;
+    use crate::test_utils::create_user_session;
+
+    // MARK: Test Personal access tokens create
+    #[test]
+    fn test_personal_access_tokens_create() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, session) = create_user_session(&ctx, UserRole::Normal);
+
+        let req = Request::with_url("http://localhost/personal_access_tokens")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("name=Bot&scopes=posts:read,posts:write");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res =
+            serde_json::from_slice::<api::PersonalAccessTokenCreateResponse>(&res.body).unwrap();
+        assert!(res.token.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX));
+        assert_eq!(
+            res.personal_access_token.scopes,
+            ["posts:read", "posts:write"]
+        );
+
+        // Only the token hash is stored
+        let stored_token = ctx
+            .database
+            .query::<String>("SELECT token FROM personal_access_tokens", ())
+            .next()
+            .unwrap();
+        assert_eq!(stored_token, hash_token(&res.token));
+
+        // Unknown scope
+        let req = Request::with_url("http://localhost/personal_access_tokens")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("name=Bot&scopes=admin");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+    }
+
+    // MARK: Test Personal access tokens delete
+    #[test]
+    fn test_personal_access_tokens_delete() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let personal_access_token = PersonalAccessToken {
+            user_id: user.id,
+            name: "Bot".to_string(),
+            token: hash_token("pbp_test"),
+            ..Default::default()
+        };
+        ctx.database
+            .insert_personal_access_token(personal_access_token.clone());
+
+        // Other user can't delete personal access token
+        let (_, other_session) = create_user_session(&ctx, UserRole::Normal);
+        let req = Request::with_url(format!(
+            "http://localhost/personal_access_tokens/{}",
+            personal_access_token.id
+        ))
+        .method(Method::Delete)
+        .header("Authorization", format!("Bearer {}", other_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        let req = Request::with_url(format!(
+            "http://localhost/personal_access_tokens/{}",
+            personal_access_token.id
+        ))
+        .method(Method::Delete)
+        .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // Revoked token can't be used anymore
+        let req =
+            Request::with_url("http://localhost/users").header("Authorization", "Bearer pbp_test");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+    }
+}
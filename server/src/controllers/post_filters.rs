@@ -0,0 +1,334 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use small_http::{Request, Response, Status};
+use uuid::Uuid;
+use validate::Validate;
+
+use crate::controllers::not_found;
+use crate::database::Extension;
+use crate::models::{PostFilter, PostFilterAction, PostFilterContext};
+use crate::permissions::{authorize, Ability};
+use crate::{api, Context};
+
+// MARK: Helpers
+fn find_post_filter(req: &Request, ctx: &Context) -> Option<PostFilter> {
+    let post_filter_id = match req
+        .params
+        .get("post_filter_id")
+        .expect("Should be some")
+        .parse::<Uuid>()
+    {
+        Ok(id) => id,
+        Err(_) => return None,
+    };
+    ctx.database
+        .query::<PostFilter>(
+            formatcp!(
+                "SELECT {} FROM post_filters WHERE id = ? LIMIT 1",
+                PostFilter::columns()
+            ),
+            post_filter_id,
+        )
+        .next()
+}
+
+fn is_post_filter_contexts(value: &str, _: &Context) -> validate::Result {
+    if PostFilterContext::parse_list(value).is_none() {
+        return Err(validate::Error::new("unknown context"));
+    }
+    Ok(())
+}
+
+// MARK: Post filters create
+#[derive(Validate)]
+#[validate(context(Context))]
+struct PostFilterCreateUpdateBody {
+    #[validate(length(min = 1, max = 64))]
+    name: String,
+    #[validate(length(min = 1, max = 128))]
+    phrase: String,
+    #[validate(custom(is_post_filter_contexts))]
+    contexts: String,
+    action: PostFilterAction,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<api::PostFilterCreateUpdateBody> for PostFilterCreateUpdateBody {
+    fn from(body: api::PostFilterCreateUpdateBody) -> Self {
+        Self {
+            name: body.name,
+            phrase: body.phrase,
+            contexts: body.contexts,
+            action: match body.action {
+                api::PostFilterAction::Hide => PostFilterAction::Hide,
+                api::PostFilterAction::Warn => PostFilterAction::Warn,
+            },
+            expires_at: body.expires_at,
+        }
+    }
+}
+
+pub fn post_filters_create(req: &Request, ctx: &Context) -> Response {
+    // Authorization
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+
+    // Parse and validate body
+    let body = match serde_urlencoded::from_bytes::<api::PostFilterCreateUpdateBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => Into::<PostFilterCreateUpdateBody>::into(body),
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    if let Err(errors) = body.validate_with(ctx) {
+        return Response::new().status(Status::BadRequest).json(errors);
+    }
+
+    // Create new post filter
+    let post_filter = PostFilter {
+        user_id: auth_user.id,
+        name: body.name,
+        phrase: body.phrase,
+        contexts: PostFilterContext::parse_list(&body.contexts).expect("Should be valid"),
+        action: body.action,
+        expires_at: body.expires_at,
+        ..Default::default()
+    };
+    ctx.database.insert_post_filter(post_filter.clone());
+
+    Response::new().json(Into::<api::PostFilter>::into(post_filter))
+}
+
+// MARK: Post filters update
+pub fn post_filters_update(req: &Request, ctx: &Context) -> Response {
+    let mut post_filter = match find_post_filter(req, ctx) {
+        Some(post_filter) => post_filter,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::PostFilterUpdate, Some(post_filter.user_id)) {
+        return res;
+    }
+
+    // Parse and validate body
+    let body = match serde_urlencoded::from_bytes::<api::PostFilterCreateUpdateBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => Into::<PostFilterCreateUpdateBody>::into(body),
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    if let Err(errors) = body.validate_with(ctx) {
+        return Response::new().status(Status::BadRequest).json(errors);
+    }
+
+    // Update post filter
+    post_filter.name = body.name;
+    post_filter.phrase = body.phrase;
+    post_filter.contexts = PostFilterContext::parse_list(&body.contexts).expect("Should be valid");
+    post_filter.action = body.action;
+    post_filter.expires_at = body.expires_at;
+    post_filter.updated_at = Utc::now();
+    ctx.database.execute(
+        "UPDATE post_filters SET name = ?, phrase = ?, contexts = ?, action = ?, expires_at = ?, updated_at = ? WHERE id = ?",
+        (
+            post_filter.name.clone(),
+            post_filter.phrase.clone(),
+            post_filter.contexts,
+            post_filter.action,
+            post_filter.expires_at,
+            post_filter.updated_at,
+            post_filter.id,
+        ),
+    );
+
+    Response::new().json(Into::<api::PostFilter>::into(post_filter))
+}
+
+// MARK: Post filters delete
+pub fn post_filters_delete(req: &Request, ctx: &Context) -> Response {
+    let post_filter = match find_post_filter(req, ctx) {
+        Some(post_filter) => post_filter,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::PostFilterDelete, Some(post_filter.user_id)) {
+        return res;
+    }
+
+    ctx.database
+        .execute("DELETE FROM post_filters WHERE id = ?", post_filter.id);
+    Response::new()
+}
+
+#[cfg(test)]
+mod test {
+    use small_http::Method;
+
+    use super::*;
+    use crate::models::{Post, UserRole};
+    use crate::router;
+    use crate::test_utils::create_user_session;
+
+    // MARK: Test Post filters create
+    #[test]
+    fn test_post_filters_create() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, session) = create_user_session(&ctx, UserRole::Normal);
+
+        let req = Request::with_url("http://localhost/post_filters")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("name=Spoilers&phrase=%23spoilers&contexts=home,search&action=warn");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::PostFilter>(&res.body).unwrap();
+        assert_eq!(res.phrase, "#spoilers");
+        assert_eq!(res.contexts.len(), 2);
+
+        // Unknown context
+        let req = Request::with_url("http://localhost/post_filters")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("name=Spoilers&phrase=spoilers&contexts=everywhere&action=hide");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+    }
+
+    // MARK: Test Post filters update
+    #[test]
+    fn test_post_filters_update() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let post_filter = PostFilter {
+            user_id: user.id,
+            name: "Spoilers".to_string(),
+            phrase: "spoilers".to_string(),
+            ..Default::default()
+        };
+        ctx.database.insert_post_filter(post_filter.clone());
+
+        let req = Request::with_url(format!("http://localhost/post_filters/{}", post_filter.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("name=Politics&phrase=politics&contexts=home&action=hide");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::PostFilter>(&res.body).unwrap();
+        assert_eq!(res.name, "Politics");
+
+        // Other user can't update post filter
+        let (_, other_session) = create_user_session(&ctx, UserRole::Normal);
+        let req = Request::with_url(format!("http://localhost/post_filters/{}", post_filter.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", other_session.token))
+            .body("name=Politics&phrase=politics&contexts=home&action=hide");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+    }
+
+    // MARK: Test Post filters delete
+    #[test]
+    fn test_post_filters_delete() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let post_filter = PostFilter {
+            user_id: user.id,
+            name: "Spoilers".to_string(),
+            phrase: "spoilers".to_string(),
+            ..Default::default()
+        };
+        ctx.database.insert_post_filter(post_filter.clone());
+
+        let req = Request::with_url(format!("http://localhost/post_filters/{}", post_filter.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        let count = ctx
+            .database
+            .query::<i64>("SELECT COUNT(id) FROM post_filters", ())
+            .next()
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    // MARK: Test Post filters listings
+    #[test]
+    fn test_post_filters_listings() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        ctx.database.insert_post_filter(PostFilter {
+            user_id: user.id,
+            name: "Spoilers".to_string(),
+            phrase: "#spoilers".to_string(),
+            action: PostFilterAction::Warn,
+            ..Default::default()
+        });
+        ctx.database.insert_post_filter(PostFilter {
+            user_id: user.id,
+            name: "Politics".to_string(),
+            phrase: "politics".to_string(),
+            contexts: PostFilterContext::Home as i64,
+            ..Default::default()
+        });
+        ctx.database.insert_post_filter(PostFilter {
+            user_id: user.id,
+            name: "Expired".to_string(),
+            phrase: "hello".to_string(),
+            expires_at: Some(Utc::now()),
+            ..Default::default()
+        });
+        for text in ["Hello world", "The ending #spoilers", "Talking politics"] {
+            ctx.database.insert_post(Post {
+                user_id: user.id,
+                text: text.to_string(),
+                ..Default::default()
+            });
+        }
+
+        // Home hides politics and warns for spoilers
+        let req = Request::with_url("http://localhost/posts")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 2);
+        assert_eq!(res.data.len(), 2);
+        let filtered_post = res.data.iter().find(|post| post.filtered).unwrap();
+        assert_eq!(filtered_post.filter_name.as_deref(), Some("Spoilers"));
+
+        // Hidden posts don't take up space on a page
+        let req = Request::with_url("http://localhost/posts?limit=2")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.data.len(), 2);
+        assert!(res.data.iter().all(|post| post.text != "Talking politics"));
+
+        // Search context doesn't hide politics
+        let req = Request::with_url("http://localhost/posts?q=politics")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.data.len(), 1);
+    }
+}
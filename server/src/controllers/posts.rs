@@ -8,16 +8,29 @@ use chrono::Utc;
 use const_format::formatcp;
 use small_http::{Request, Response, Status};
 use uuid::Uuid;
-use validate::Validate;
+use validate::{Report, Validate};
 
 use crate::controllers::not_found;
 use crate::database::Extension;
+use crate::models::post::POST_NOT_HIDDEN_CONDITION;
+use crate::models::post_filter::{fetch_auth_user_post_filters, POST_NOT_FILTERED_CONDITION};
+use crate::models::user_relation::BLOCKED_OR_MUTED_USERS_CTE;
 use crate::models::{
-    IndexQuery, Post, PostInteraction, PostInteractionType, PostType, User, UserRole,
+    IndexQuery, Post, PostFilterContext, PostInteraction, PostInteractionType, PostType, User,
 };
+use crate::permissions::{authorize, Ability};
 use crate::{api, Context};
 
 // MARK: Helpers
+fn check_email_verified(ctx: &Context, auth_user: &User) -> Option<Response> {
+    if ctx.settings.require_verified_email_to_post && auth_user.email_verified_at.is_none() {
+        let mut report = Report::new();
+        report.insert_error("email", "Verify your email address before posting");
+        return Some(Response::new().status(Status::Forbidden).json(report));
+    }
+    None
+}
+
 fn find_post(req: &Request, ctx: &Context) -> Option<Post> {
     let post_id = match req
         .params
@@ -80,12 +93,32 @@ pub fn posts_index(req: &Request, ctx: &Context) -> Response {
     }
 
     // Get posts
+    let auth_user_id = ctx.auth_user.as_ref().map_or(Uuid::nil(), |user| user.id);
+    let post_filter_context = if query.query.is_empty() {
+        PostFilterContext::Home
+    } else {
+        PostFilterContext::Search
+    };
+    let post_filters = fetch_auth_user_post_filters(ctx, post_filter_context);
     let search_query = format!("%{}%", query.query.replace("%", "\\%"));
     let total = ctx
         .database
         .query::<i64>(
-            "SELECT COUNT(id) FROM posts WHERE text LIKE ?",
-            search_query.clone(),
+            formatcp!(
+                "{} SELECT COUNT(id) FROM posts WHERE {} AND text LIKE ? AND {}",
+                BLOCKED_OR_MUTED_USERS_CTE,
+                POST_NOT_HIDDEN_CONDITION,
+                POST_NOT_FILTERED_CONDITION
+            ),
+            (
+                auth_user_id,
+                auth_user_id,
+                Utc::now(),
+                search_query.clone(),
+                auth_user_id,
+                post_filter_context as i64,
+                Utc::now(),
+            ),
         )
         .next()
         .expect("Can't count posts");
@@ -93,11 +126,25 @@ pub fn posts_index(req: &Request, ctx: &Context) -> Response {
         .database
         .query::<Post>(
             formatcp!(
-                "SELECT {} FROM posts WHERE text LIKE ? ORDER BY created_at DESC LIMIT ? OFFSET ?",
-                Post::columns()
+                "{} SELECT {} FROM posts WHERE {} AND text LIKE ? AND {} ORDER BY created_at DESC LIMIT ? OFFSET ?",
+                BLOCKED_OR_MUTED_USERS_CTE,
+                Post::columns(),
+                POST_NOT_HIDDEN_CONDITION,
+                POST_NOT_FILTERED_CONDITION
+            ),
+            (
+                auth_user_id,
+                auth_user_id,
+                Utc::now(),
+                search_query,
+                auth_user_id,
+                post_filter_context as i64,
+                Utc::now(),
+                query.limit,
+                query.limit * (query.page - 1),
             ),
-            (search_query, query.limit, query.limit * (query.page - 1)),
         )
+        .map(|post| post.apply_filters(&post_filters))
         .map(|mut post| {
             post.fetch_relationships(ctx);
             post
@@ -138,6 +185,9 @@ pub fn posts_create(req: &Request, ctx: &Context) -> Response {
                 .body("401 Unauthorized")
         }
     };
+    if let Some(res) = check_email_verified(ctx, auth_user) {
+        return res;
+    }
 
     // Parse and validate body
     let body = match serde_urlencoded::from_bytes::<api::PostCreateUpdateBody>(
@@ -175,21 +225,35 @@ pub fn posts_show(req: &Request, ctx: &Context) -> Response {
     };
 
     // Authorization
-    // -
+    if post.is_hidden_for_auth_user(ctx) {
+        return not_found(req, ctx);
+    }
 
     // Fetch post replies
+    let auth_user_id = ctx.auth_user.as_ref().map_or(Uuid::nil(), |user| user.id);
+    let post_filters = fetch_auth_user_post_filters(ctx, PostFilterContext::Replies);
     let replies = ctx
         .database
         .query::<Post>(
             formatcp!(
-                "SELECT {} FROM posts WHERE parent_post_id = ? AND type = ? ORDER BY created_at DESC",
-                Post::columns()
+                "{} SELECT {} FROM posts WHERE parent_post_id = ? AND type = ? AND {} AND {} ORDER BY created_at DESC",
+                BLOCKED_OR_MUTED_USERS_CTE,
+                Post::columns(),
+                POST_NOT_HIDDEN_CONDITION,
+                POST_NOT_FILTERED_CONDITION
             ),
             (
+                auth_user_id,
+                auth_user_id,
+                Utc::now(),
                 post.id,
                 PostType::Reply,
+                auth_user_id,
+                PostFilterContext::Replies as i64,
+                Utc::now(),
             )
         )
+        .map(|reply| reply.apply_filters(&post_filters))
         .map(|mut reply| {
             reply.fetch_relationships(ctx);
             reply
@@ -209,11 +273,8 @@ pub fn posts_update(req: &Request, ctx: &Context) -> Response {
     };
 
     // Authorization
-    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
-    if !(post.user_id == auth_user.id || auth_user.role == UserRole::Admin) {
-        return Response::new()
-            .status(Status::Unauthorized)
-            .body("401 Unauthorized");
+    if let Some(res) = authorize(ctx, Ability::PostUpdate, Some(post.user_id)) {
+        return res;
     }
 
     // Parse and validate body
@@ -252,11 +313,8 @@ pub fn posts_delete(req: &Request, ctx: &Context) -> Response {
     };
 
     // Authorization
-    let auth_post = ctx.auth_user.as_ref().expect("Not authed");
-    if !(post.user_id == auth_post.id || auth_post.role == UserRole::Admin) {
-        return Response::new()
-            .status(Status::Unauthorized)
-            .body("401 Unauthorized");
+    if let Some(res) = authorize(ctx, Ability::PostDelete, Some(post.user_id)) {
+        return res;
     }
 
     // Update parent post counters
@@ -288,7 +346,9 @@ pub fn posts_replies(req: &Request, ctx: &Context) -> Response {
     };
 
     // Authorization
-    // -
+    if post.is_hidden_for_auth_user(ctx) {
+        return not_found(req, ctx);
+    }
 
     // Parse request query
     let query = match req.url.query() {
@@ -303,12 +363,28 @@ pub fn posts_replies(req: &Request, ctx: &Context) -> Response {
     }
 
     // Get post replies
+    let auth_user_id = ctx.auth_user.as_ref().map_or(Uuid::nil(), |user| user.id);
+    let post_filters = fetch_auth_user_post_filters(ctx, PostFilterContext::Replies);
     let search_query = format!("%{}%", query.query.replace("%", "\\%"));
     let total = ctx
         .database
         .query::<i64>(
-            "SELECT COUNT(id) FROM posts WHERE parent_post_id = ? AND text LIKE ?",
-            (post.id, search_query.clone()),
+            formatcp!(
+                "{} SELECT COUNT(id) FROM posts WHERE parent_post_id = ? AND {} AND text LIKE ? AND {}",
+                BLOCKED_OR_MUTED_USERS_CTE,
+                POST_NOT_HIDDEN_CONDITION,
+                POST_NOT_FILTERED_CONDITION
+            ),
+            (
+                auth_user_id,
+                auth_user_id,
+                Utc::now(),
+                post.id,
+                search_query.clone(),
+                auth_user_id,
+                PostFilterContext::Replies as i64,
+                Utc::now(),
+            ),
         )
         .next()
         .expect("Can't count posts");
@@ -316,16 +392,26 @@ pub fn posts_replies(req: &Request, ctx: &Context) -> Response {
         .database
         .query::<Post>(
             formatcp!(
-                "SELECT {} FROM posts WHERE parent_post_id = ? AND text LIKE ? ORDER BY created_at DESC LIMIT ? OFFSET ?",
-                Post::columns()
+                "{} SELECT {} FROM posts WHERE parent_post_id = ? AND {} AND text LIKE ? AND {} ORDER BY created_at DESC LIMIT ? OFFSET ?",
+                BLOCKED_OR_MUTED_USERS_CTE,
+                Post::columns(),
+                POST_NOT_HIDDEN_CONDITION,
+                POST_NOT_FILTERED_CONDITION
             ),
             (
+                auth_user_id,
+                auth_user_id,
+                Utc::now(),
                 post.id,
                 search_query,
+                auth_user_id,
+                PostFilterContext::Replies as i64,
+                Utc::now(),
                 query.limit,
-                query. limit * (query.page - 1),
+                query.limit * (query.page - 1),
             ),
         )
+        .map(|post| post.apply_filters(&post_filters))
         .map(|mut post| {
             post.fetch_relationships(ctx);
             post
@@ -359,6 +445,13 @@ pub fn posts_create_reply(req: &Request, ctx: &Context) -> Response {
                 .body("401 Unauthorized")
         }
     };
+    if let Some(res) = check_email_verified(ctx, auth_user) {
+        return res;
+    }
+
+    if post.is_hidden_for_auth_user(ctx) {
+        return not_found(req, ctx);
+    }
 
     // Parse and validate body
     let body = match serde_urlencoded::from_bytes::<api::PostCreateUpdateBody>(
@@ -412,6 +505,13 @@ pub fn posts_repost(req: &Request, ctx: &Context) -> Response {
                 .body("401 Unauthorized")
         }
     };
+    if let Some(res) = check_email_verified(ctx, auth_user) {
+        return res;
+    }
+
+    if post.is_hidden_for_auth_user(ctx) {
+        return not_found(req, ctx);
+    }
 
     // Create new repost
     let mut repost = Post {
@@ -451,6 +551,10 @@ pub fn posts_like(req: &Request, ctx: &Context) -> Response {
         }
     };
 
+    if post.is_hidden_for_auth_user(ctx) {
+        return not_found(req, ctx);
+    }
+
     // Remove possible old post interaction
     remove_post_like(&ctx.database, post.content_post_id(), auth_user);
     remove_post_dislike(&ctx.database, post.content_post_id(), auth_user);
@@ -517,6 +621,10 @@ pub fn posts_dislike(req: &Request, ctx: &Context) -> Response {
         }
     };
 
+    if post.is_hidden_for_auth_user(ctx) {
+        return not_found(req, ctx);
+    }
+
     // Remove possible old post interaction
     remove_post_like(&ctx.database, post.content_post_id(), auth_user);
     remove_post_dislike(&ctx.database, post.content_post_id(), auth_user);
@@ -571,7 +679,9 @@ mod test {
     use small_http::Method;
 
     use super::*;
+    use crate::models::{UserRelation, UserRelationType, UserRole};
     use crate::router;
+    use crate::settings::Settings;
     use crate::test_utils::create_user_session;
 
     // MARK: Test Posts index
@@ -597,6 +707,83 @@ mod test {
         assert_eq!(res.pagination.total, 10);
     }
 
+    #[test]
+    fn test_posts_index_blocked_and_muted() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let (blocked_user, blocked_session) = create_user_session(&ctx, UserRole::Normal);
+        let (muted_user, muted_session) = create_user_session(&ctx, UserRole::Normal);
+
+        ctx.database.insert_user_relation(UserRelation {
+            user_id: user.id,
+            target_user_id: blocked_user.id,
+            r#type: UserRelationType::Block,
+            ..Default::default()
+        });
+        ctx.database.insert_user_relation(UserRelation {
+            user_id: user.id,
+            target_user_id: muted_user.id,
+            r#type: UserRelationType::Mute,
+            ..Default::default()
+        });
+        let blocked_post = Post {
+            user_id: blocked_user.id,
+            text: "Blocked post".to_string(),
+            ..Default::default()
+        };
+        ctx.database.insert_post(blocked_post.clone());
+        ctx.database.insert_post(Post {
+            r#type: PostType::Repost,
+            parent_post_id: Some(blocked_post.id),
+            user_id: muted_user.id,
+            text: "Blocked post".to_string(),
+            ..Default::default()
+        });
+        ctx.database.insert_post(Post {
+            user_id: user.id,
+            text: "User post".to_string(),
+            ..Default::default()
+        });
+
+        // User sees only own post
+        let req = Request::with_url("http://localhost/posts")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 1);
+
+        // Blocked user doesn't see the post of user
+        let req = Request::with_url("http://localhost/posts")
+            .header("Authorization", format!("Bearer {}", blocked_session.token));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 2);
+
+        // Muted user sees everything
+        let req = Request::with_url("http://localhost/posts")
+            .header("Authorization", format!("Bearer {}", muted_session.token));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 3);
+
+        // Guests see everything
+        let res = router.handle(&Request::with_url("http://localhost/posts"));
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 3);
+
+        // Blocked post can't be shown or liked
+        let req = Request::with_url(format!("http://localhost/posts/{}", blocked_post.id))
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::NotFound);
+        let req = Request::with_url(format!("http://localhost/posts/{}/like", blocked_post.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::NotFound);
+    }
+
     // MARK: Test Posts create
     #[test]
     fn test_posts_create() {
@@ -614,6 +801,34 @@ mod test {
         assert_eq!(&res.text, "Hello world");
     }
 
+    // MARK: Test Posts create unverified email
+    #[test]
+    fn test_posts_create_unverified_email() {
+        let ctx = Context {
+            settings: Settings {
+                require_verified_email_to_post: true,
+                ..Default::default()
+            },
+            ..Context::with_test_database()
+        };
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+
+        let req = Request::with_url("http://localhost/posts")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("text=Hello%20world");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        ctx.database.execute(
+            "UPDATE users SET email_verified_at = ? WHERE id = ?",
+            (Utc::now(), user.id),
+        );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+
     // MARK: Test Posts show
     #[test]
     fn test_posts_show() {
@@ -686,6 +901,39 @@ mod test {
         assert_eq!(res.status, Status::NotFound);
     }
 
+    // MARK: Test Posts delete moderation
+    #[test]
+    fn test_posts_delete_moderation() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, _) = create_user_session(&ctx, UserRole::Normal);
+        let post = Post {
+            user_id: user.id,
+            text: "Hello world".to_string(),
+            ..Default::default()
+        };
+        ctx.database.insert_post(post.clone());
+
+        // Other normal user can't delete post
+        let (_, other_session) = create_user_session(&ctx, UserRole::Normal);
+        let req = Request::with_url(format!("http://localhost/posts/{}", post.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", other_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Moderator can delete post
+        let (_, moderator_session) = create_user_session(&ctx, UserRole::Moderator);
+        let req = Request::with_url(format!("http://localhost/posts/{}", post.id))
+            .method(Method::Delete)
+            .header(
+                "Authorization",
+                format!("Bearer {}", moderator_session.token),
+            );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+
     // MARK: Test Posts replies
     #[test]
     fn test_posts_replies() {
@@ -4,14 +4,16 @@
  * SPDX-License-Identifier: MIT
  */
 
-use chrono::Utc;
 use const_format::formatcp;
 use small_http::{Request, Response, Status};
 use uuid::Uuid;
 use validate::Validate;
 
 use crate::controllers::not_found;
-use crate::models::{IndexQuery, Session, User, UserRole};
+use crate::models::security_event::record_security_event;
+use crate::models::session::revoke_session;
+use crate::models::{IndexQuery, SecurityEventType, Session, User};
+use crate::permissions::{authorize, Ability};
 use crate::{api, Context};
 
 // MARK: Helpers
@@ -39,11 +41,8 @@ fn find_session(req: &Request, ctx: &Context) -> Option<Session> {
 // MARK: Sessions index
 pub fn sessions_index(req: &Request, ctx: &Context) -> Response {
     // Authorization
-    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
-    if !(auth_user.role == UserRole::Admin) {
-        return Response::new()
-            .status(Status::Unauthorized)
-            .body("401 Unauthorized");
+    if let Some(res) = authorize(ctx, Ability::SessionViewAny, None) {
+        return res;
     }
 
     // Parse index query
@@ -102,11 +101,8 @@ pub fn sessions_show(req: &Request, ctx: &Context) -> Response {
     };
 
     // Authorization
-    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
-    if !(session.user_id == auth_user.id || auth_user.role == UserRole::Admin) {
-        return Response::new()
-            .status(Status::Unauthorized)
-            .body("401 Unauthorized");
+    if let Some(res) = authorize(ctx, Ability::SessionViewAny, Some(session.user_id)) {
+        return res;
     }
 
     // Return session
@@ -122,17 +118,13 @@ pub fn sessions_revoke(req: &Request, ctx: &Context) -> Response {
     };
 
     // Authorization
-    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
-    if !(session.user_id == auth_user.id || auth_user.role == UserRole::Admin) {
-        return Response::new()
-            .status(Status::Unauthorized)
-            .body("401 Unauthorized");
+    if let Some(res) = authorize(ctx, Ability::SessionRevoke, Some(session.user_id)) {
+        return res;
     }
 
-    ctx.database.execute(
-        "UPDATE sessions SET expires_at = ? WHERE id = ?",
-        (Utc::now(), session.id),
-    );
+    let user_id = session.user_id;
+    revoke_session(ctx, session);
+    record_security_event(req, ctx, user_id, SecurityEventType::SessionRevoked);
     Response::new()
 }
 
@@ -141,10 +133,9 @@ mod test {
     use small_http::Method;
 
     use super::*;
-    use crate::controllers::auth::generate_random_token;
-    use crate::database::Extension;
+    use crate::models::UserRole;
     use crate::router;
-    use crate::test_utils::create_user_session;
+    use crate::test_utils::{create_session, create_user_session};
 
     // MARK: Test Sessions index
     #[test]
@@ -154,11 +145,7 @@ mod test {
         let (user, session) = create_user_session(&ctx, UserRole::Admin);
 
         for _ in 0..10 {
-            ctx.database.insert_session(Session {
-                user_id: user.id,
-                token: generate_random_token(),
-                ..Default::default()
-            });
+            create_session(&ctx, &user);
         }
 
         let req = Request::with_url("http://localhost/sessions")
@@ -201,7 +188,7 @@ mod test {
                 format!("Bearer {}", other_user_session.token),
             );
         let res = router.handle(&req);
-        assert_eq!(res.status, Status::Unauthorized);
+        assert_eq!(res.status, Status::Forbidden);
     }
 
     // MARK: Test Sessions revoke
@@ -0,0 +1,288 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use chrono::Utc;
+use small_http::{Request, Response, Status};
+use validate::{Report, Validate};
+
+use crate::database::Extension;
+use crate::models::security_event::record_security_event;
+use crate::models::two_factor::{
+    find_enabled_two_factor, find_two_factor, generate_recovery_codes, generate_two_factor_secret,
+};
+use crate::models::user::is_auth_user_current_password;
+use crate::models::{SecurityEventType, TwoFactor};
+use crate::permissions::deny_impersonation;
+use crate::totp::otpauth_uri;
+use crate::{api, Context};
+
+// MARK: Two factor enroll
+pub fn auth_two_factor_enroll(_: &Request, ctx: &Context) -> Response {
+    // Authorization
+    if let Some(res) = deny_impersonation(ctx) {
+        return res;
+    }
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+
+    // Check if two factor is not already enabled
+    if find_enabled_two_factor(ctx, auth_user.id).is_some() {
+        let mut report = Report::new();
+        report.insert_error("twoFactor", "already enabled");
+        return Response::new().status(Status::BadRequest).json(report);
+    }
+
+    // Replace possible unconfirmed enrollment
+    ctx.database
+        .execute("DELETE FROM two_factors WHERE user_id = ?", auth_user.id);
+    let two_factor = TwoFactor {
+        user_id: auth_user.id,
+        secret: generate_two_factor_secret(),
+        ..Default::default()
+    };
+    ctx.database.insert_two_factor(two_factor.clone());
+
+    Response::new().json(api::AuthTwoFactorEnrollResponse {
+        otpauth_uri: otpauth_uri(&two_factor.secret_bytes(), "PlaatBook", &auth_user.username),
+        secret: two_factor.secret,
+    })
+}
+
+// MARK: Two factor confirm
+pub fn auth_two_factor_confirm(req: &Request, ctx: &Context) -> Response {
+    // Authorization
+    if let Some(res) = deny_impersonation(ctx) {
+        return res;
+    }
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+
+    // Parse body
+    let body = match serde_urlencoded::from_bytes::<api::AuthTwoFactorConfirmBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => body,
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+
+    // Check code against the unconfirmed enrollment
+    let mut two_factor = match find_two_factor(ctx, auth_user.id) {
+        Some(two_factor) if two_factor.enabled_at.is_none() => two_factor,
+        _ => {
+            let mut report = Report::new();
+            report.insert_error("twoFactor", "not enrolled");
+            return Response::new().status(Status::BadRequest).json(report);
+        }
+    };
+    if !two_factor.verify_code(ctx, &body.code) {
+        let mut report = Report::new();
+        report.insert_error("code", "incorrect");
+        return Response::new().status(Status::BadRequest).json(report);
+    }
+
+    // Enable two factor and issue recovery codes
+    two_factor.enabled_at = Some(Utc::now());
+    two_factor.updated_at = Utc::now();
+    ctx.database.execute(
+        "UPDATE two_factors SET enabled_at = ?, updated_at = ? WHERE id = ?",
+        (two_factor.enabled_at, two_factor.updated_at, two_factor.id),
+    );
+    let recovery_codes = generate_recovery_codes(ctx, auth_user.id);
+    record_security_event(req, ctx, auth_user.id, SecurityEventType::TwoFactorEnabled);
+
+    Response::new().json(api::AuthTwoFactorRecoveryCodesResponse { recovery_codes })
+}
+
+// MARK: Two factor disable
+#[derive(Validate)]
+#[validate(context(Context))]
+struct TwoFactorDisableBody {
+    #[validate(ascii, custom(is_auth_user_current_password))]
+    current_password: String,
+}
+
+impl From<api::AuthTwoFactorDisableBody> for TwoFactorDisableBody {
+    fn from(body: api::AuthTwoFactorDisableBody) -> Self {
+        Self {
+            current_password: body.current_password,
+        }
+    }
+}
+
+pub fn auth_two_factor_disable(req: &Request, ctx: &Context) -> Response {
+    // Authorization
+    if let Some(res) = deny_impersonation(ctx) {
+        return res;
+    }
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+
+    // Parse and validate body
+    let body = match serde_urlencoded::from_bytes::<api::AuthTwoFactorDisableBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => Into::<TwoFactorDisableBody>::into(body),
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    if let Err(errors) = body.validate_with(ctx) {
+        return Response::new().status(Status::BadRequest).json(errors);
+    }
+
+    // Remove two factor and recovery codes
+    ctx.database
+        .execute("DELETE FROM two_factors WHERE user_id = ?", auth_user.id);
+    ctx.database.execute(
+        "DELETE FROM two_factor_recovery_codes WHERE user_id = ?",
+        auth_user.id,
+    );
+    record_security_event(req, ctx, auth_user.id, SecurityEventType::TwoFactorDisabled);
+    Response::new()
+}
+
+#[cfg(test)]
+mod test {
+    use small_http::Method;
+
+    use super::*;
+    use crate::models::User;
+    use crate::password::password_hash;
+    use crate::router;
+    use crate::test_utils::create_session;
+    use crate::totp::{base32_decode, hotp, totp_counter, TOTP_DIGITS};
+
+    fn current_code(secret: &str) -> String {
+        hotp(
+            &base32_decode(secret).unwrap(),
+            totp_counter(Utc::now().timestamp() as u64),
+            TOTP_DIGITS,
+        )
+    }
+
+    // MARK: Test Two factor flow
+    #[test]
+    fn test_two_factor_flow() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let user = User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        };
+        ctx.database.insert_user(user.clone());
+        let session = create_session(&ctx, &user);
+
+        // Enroll
+        let req = Request::with_url("http://localhost/auth/two_factor/enroll")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let enroll = serde_json::from_slice::<api::AuthTwoFactorEnrollResponse>(&res.body).unwrap();
+        assert!(enroll
+            .otpauth_uri
+            .starts_with("otpauth://totp/PlaatBook:test?"));
+
+        // Confirm with wrong code
+        let req = Request::with_url("http://localhost/auth/two_factor/confirm")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("code=abcdef");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Confirm with first code
+        let first_code = current_code(&enroll.secret);
+        let req = Request::with_url("http://localhost/auth/two_factor/confirm")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body(format!("code={}", first_code));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let recovery_codes =
+            serde_json::from_slice::<api::AuthTwoFactorRecoveryCodesResponse>(&res.body)
+                .unwrap()
+                .recovery_codes;
+        assert_eq!(recovery_codes.len(), 10);
+
+        // Login returns a challenge
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Accepted);
+        let challenge = serde_json::from_slice::<api::AuthLoginChallengeResponse>(&res.body)
+            .unwrap()
+            .challenge;
+
+        // Already used TOTP code is rejected
+        let req = Request::with_url("http://localhost/auth/login/two_factor")
+            .method(Method::Post)
+            .body(
+                serde_urlencoded::to_string(api::AuthLoginTwoFactorBody {
+                    challenge: challenge.clone(),
+                    code: first_code,
+                })
+                .unwrap(),
+            );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+
+        // Exchange challenge with recovery code
+        let req = Request::with_url("http://localhost/auth/login/two_factor")
+            .method(Method::Post)
+            .body(
+                serde_urlencoded::to_string(api::AuthLoginTwoFactorBody {
+                    challenge: challenge.clone(),
+                    code: recovery_codes[0].clone(),
+                })
+                .unwrap(),
+            );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let login = serde_json::from_slice::<api::AuthLoginResponse>(&res.body).unwrap();
+        assert_eq!(login.user.username, "test");
+
+        // Challenge can't be reused
+        let req = Request::with_url("http://localhost/auth/login/two_factor")
+            .method(Method::Post)
+            .body(
+                serde_urlencoded::to_string(api::AuthLoginTwoFactorBody {
+                    challenge,
+                    code: recovery_codes[1].clone(),
+                })
+                .unwrap(),
+            );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+
+        // Disable requires password
+        let req = Request::with_url("http://localhost/auth/two_factor/disable")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("currentPassword=wrong");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+        let req = Request::with_url("http://localhost/auth/two_factor/disable")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("currentPassword=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // Login returns a session again
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+}
@@ -6,21 +6,41 @@
 
 use std::str::FromStr;
 
-use chrono::{NaiveDate, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use const_format::formatcp;
-use pbkdf2::password_hash;
 use serde::{Deserialize, Deserializer};
 use small_http::{Request, Response, Status};
 use uuid::Uuid;
-use validate::Validate;
+use validate::{Report, Validate};
 
+use crate::controllers::auth::{
+    check_pow_challenge, generate_session_token, hash_token, request_session,
+};
 use crate::controllers::not_found;
 use crate::database::Extension;
+use crate::models::email_verification::{find_pending_email_verification, send_email_verification};
+use crate::models::invite_code::find_usable_invite_code;
+use crate::models::login_attempt::{clear_failed_logins, normalize_logon};
+use crate::models::post::POST_NOT_HIDDEN_CONDITION;
+use crate::models::post_filter::{fetch_auth_user_post_filters, POST_NOT_FILTERED_CONDITION};
+use crate::models::security_event::record_security_event;
+use crate::models::session::{
+    revoke_user_sessions, revoke_user_tokens, IMPERSONATION_SESSION_DURATION,
+};
 use crate::models::user::{
     is_auth_user_current_password, is_unique_email, is_unique_email_or_auth_user_email,
     is_unique_username, is_unique_username_or_auth_user_username,
 };
-use crate::models::{IndexQuery, Post, Session, User, UserRole};
+use crate::models::user_deletion::find_user_deletion;
+use crate::models::user_relation::BLOCKED_USERS_CTE;
+use crate::models::{
+    IndexQuery, InviteCode, OAuthApp, OAuthGrant, PersonalAccessToken, Post, PostFilter,
+    PostFilterContext, SecurityEvent, SecurityEventType, Session, User, UserDeletion, UserRelation,
+    UserRelationType, UserRole, UserSuspension,
+};
+use crate::password::password_hash;
+use crate::permissions::{authorize, deny_impersonation, outranks, Ability};
+use crate::settings::RegistrationMode;
 use crate::{api, Context};
 
 // MARK: Helpers
@@ -42,14 +62,19 @@ fn find_user(req: &Request, ctx: &Context) -> Option<User> {
         .next()
 }
 
+// Returns the current session id when the auth user is the given user
+fn current_session_id(ctx: &Context, user_id: Uuid) -> Option<Uuid> {
+    ctx.auth_session
+        .as_ref()
+        .filter(|session| session.user_id == user_id)
+        .map(|session| session.id)
+}
+
 // MARK: Users index
 pub fn users_index(req: &Request, ctx: &Context) -> Response {
     // Authorization
-    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
-    if !(auth_user.role == UserRole::Admin) {
-        return Response::new()
-            .status(Status::Unauthorized)
-            .body("401 Unauthorized");
+    if let Some(res) = authorize(ctx, Ability::UserViewAny, None) {
+        return res;
     }
 
     // Parse request query
@@ -105,6 +130,9 @@ struct UserCreateBody {
     email: String,
     #[validate(ascii, length(min = 6, max = 128))]
     password: String,
+    invite_code: Option<String>,
+    pow_challenge: Option<String>,
+    pow_nonce: Option<String>,
 }
 
 impl From<api::UserCreateBody> for UserCreateBody {
@@ -113,13 +141,20 @@ impl From<api::UserCreateBody> for UserCreateBody {
             username: body.username,
             email: body.email,
             password: body.password,
+            invite_code: body.invite_code,
+            pow_challenge: body.pow_challenge,
+            pow_nonce: body.pow_nonce,
         }
     }
 }
 
 pub fn users_create(req: &Request, ctx: &Context) -> Response {
     // Authorization
-    // -
+    if ctx.settings.registration_mode == RegistrationMode::Closed {
+        let mut report = Report::new();
+        report.insert_error("username", "registration is closed");
+        return Response::new().status(Status::Forbidden).json(report);
+    }
 
     // Parse and validate body
     let body = match serde_urlencoded::from_bytes::<api::UserCreateBody>(
@@ -136,15 +171,47 @@ pub fn users_create(req: &Request, ctx: &Context) -> Response {
         return Response::new().status(Status::BadRequest).json(errors);
     }
 
+    // Check proof of work against bot signups
+    if let Some(res) = check_pow_challenge(
+        ctx,
+        body.pow_challenge.as_deref(),
+        body.pow_nonce.as_deref(),
+    ) {
+        return res;
+    }
+
+    // Check invite code, it's required when registration is invite only
+    let invite_code = match body.invite_code.as_deref().filter(|code| !code.is_empty()) {
+        Some(code) => match find_usable_invite_code(ctx, code) {
+            Some(invite_code) => Some(invite_code),
+            None => {
+                let mut report = Report::new();
+                report.insert_error("inviteCode", "invalid or expired invite code");
+                return Response::new().status(Status::BadRequest).json(report);
+            }
+        },
+        None if ctx.settings.registration_mode == RegistrationMode::InviteOnly => {
+            let mut report = Report::new();
+            report.insert_error("inviteCode", "invite code is required");
+            return Response::new().status(Status::BadRequest).json(report);
+        }
+        None => None,
+    };
+
     // Create new user
     let user = User {
         username: body.username,
         email: body.email,
         password: password_hash(&body.password),
         role: UserRole::Normal,
+        invited_by_user_id: invite_code.as_ref().map(|invite_code| invite_code.user_id),
         ..Default::default()
     };
     ctx.database.insert_user(user.clone());
+    if let Some(mut invite_code) = invite_code {
+        invite_code.record_use(ctx);
+    }
+    send_email_verification(ctx, &user, &user.email);
 
     Response::new().json(Into::<api::User>::into(user))
 }
@@ -159,6 +226,11 @@ pub fn users_show(req: &Request, ctx: &Context) -> Response {
     // Authorization
     // -
 
+    // Hide deactivated users
+    if find_user_deletion(ctx, user.id).is_some() {
+        return not_found(req, ctx);
+    }
+
     Response::new().json(Into::<api::User>::into(user))
 }
 
@@ -181,11 +253,8 @@ pub fn users_update(req: &Request, ctx: &Context) -> Response {
     };
 
     // Authorization
-    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
-    if !(user.id == auth_user.id || auth_user.role == UserRole::Admin) {
-        return Response::new()
-            .status(Status::Unauthorized)
-            .body("401 Unauthorized");
+    if let Some(res) = authorize(ctx, Ability::UserUpdate, Some(user.id)) {
+        return res;
     }
 
     // Parse and validate body
@@ -230,9 +299,17 @@ pub fn users_update(req: &Request, ctx: &Context) -> Response {
         return Response::new().status(Status::BadRequest).json(errors);
     }
 
+    // Changed email stays pending until it is verified, impersonators can't change it as
+    // it can be used to take over the account with a password reset
+    if body.email != user.email {
+        if let Some(res) = deny_impersonation(ctx) {
+            return res;
+        }
+        send_email_verification(ctx, &user, &body.email);
+    }
+
     // Update user
     user.username = body.username;
-    user.email = body.email;
     user.firstname = body.firstname;
     user.lastname = body.lastname;
     user.birthdate = body
@@ -243,10 +320,9 @@ pub fn users_update(req: &Request, ctx: &Context) -> Response {
     user.website = body.website;
     user.updated_at = Utc::now();
     ctx.database.execute(
-        "UPDATE users SET username = ?, email = ?, firstname = ?, lastname = ?, birthdate = ?, bio = ?, location = ?, website = ?, updated_at = ? WHERE id = ?",
+        "UPDATE users SET username = ?, firstname = ?, lastname = ?, birthdate = ?, bio = ?, location = ?, website = ?, updated_at = ? WHERE id = ?",
         (
             user.username.clone(),
-            user.email.clone(),
             user.firstname.clone(),
             user.lastname.clone(),
             user.birthdate,
@@ -261,6 +337,32 @@ pub fn users_update(req: &Request, ctx: &Context) -> Response {
     Response::new().json(Into::<api::User>::into(user))
 }
 
+// MARK: Users resend email verification
+pub fn users_resend_email_verification(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserUpdate, Some(user.id)) {
+        return res;
+    }
+
+    // Resend for the pending email or else the unverified current email
+    let email = match find_pending_email_verification(ctx, user.id) {
+        Some(email_verification) => email_verification.email,
+        None if user.email_verified_at.is_none() => user.email.clone(),
+        None => {
+            let mut report = Report::new();
+            report.insert_error("email", "already verified");
+            return Response::new().status(Status::BadRequest).json(report);
+        }
+    };
+    send_email_verification(ctx, &user, &email);
+    Response::new()
+}
+
 // MARK: Users change password
 
 #[derive(Validate)]
@@ -270,6 +372,7 @@ struct UserUpdatePasswordBody {
     current_password: String,
     #[validate(ascii, length(min = 6, max = 128))]
     password: String,
+    revoke_tokens: bool,
 }
 
 impl From<api::UserUpdatePasswordBody> for UserUpdatePasswordBody {
@@ -277,6 +380,7 @@ impl From<api::UserUpdatePasswordBody> for UserUpdatePasswordBody {
         Self {
             current_password: body.current_password,
             password: body.password,
+            revoke_tokens: body.revoke_tokens.unwrap_or(true),
         }
     }
 }
@@ -288,11 +392,11 @@ pub fn users_change_password(req: &Request, ctx: &Context) -> Response {
     };
 
     // Authorization
-    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
-    if !(user.id == auth_user.id || auth_user.role == UserRole::Admin) {
-        return Response::new()
-            .status(Status::Unauthorized)
-            .body("401 Unauthorized");
+    if let Some(res) = authorize(ctx, Ability::UserChangePassword, Some(user.id)) {
+        return res;
+    }
+    if let Some(res) = deny_impersonation(ctx) {
+        return res;
     }
 
     // Parse and validate body
@@ -317,10 +421,135 @@ pub fn users_change_password(req: &Request, ctx: &Context) -> Response {
         "UPDATE users SET password = ?, updated_at = ? WHERE id = ?",
         (user.password.clone(), user.updated_at, user.id),
     );
+    record_security_event(req, ctx, user.id, SecurityEventType::PasswordChanged);
+
+    // Revoke all other sessions, so a stolen session doesn't survive the password change
+    revoke_user_sessions(ctx, user.id, current_session_id(ctx, user.id));
+    if body.revoke_tokens {
+        revoke_user_tokens(ctx, user.id);
+    }
+
+    Response::new().json(Into::<api::User>::into(user))
+}
+
+// MARK: Users change role
+pub fn users_change_role(req: &Request, ctx: &Context) -> Response {
+    let mut user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserChangeRole, None) {
+        return res;
+    }
+
+    // Parse body
+    let body = match serde_urlencoded::from_bytes::<api::UserChangeRoleBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => body,
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    let role = match body.role {
+        api::UserRole::Normal => UserRole::Normal,
+        api::UserRole::Moderator => UserRole::Moderator,
+        api::UserRole::Admin => UserRole::Admin,
+    };
+
+    // Check if the last admin is not demoted
+    if role != UserRole::Admin && user.is_last_admin(ctx) {
+        let mut report = Report::new();
+        report.insert_error("role", "can't demote the last admin");
+        return Response::new().status(Status::BadRequest).json(report);
+    }
+
+    // Update user
+    user.role = role;
+    user.updated_at = Utc::now();
+    ctx.database.execute(
+        "UPDATE users SET role = ?, updated_at = ? WHERE id = ?",
+        (user.role, user.updated_at, user.id),
+    );
 
     Response::new().json(Into::<api::User>::into(user))
 }
 
+// MARK: Users delete
+#[derive(Validate)]
+#[validate(context(Context))]
+struct UserDeleteBody {
+    #[validate(ascii, custom(is_auth_user_current_password))]
+    current_password: String,
+}
+
+impl From<api::UserDeleteBody> for UserDeleteBody {
+    fn from(body: api::UserDeleteBody) -> Self {
+        Self {
+            current_password: body.current_password,
+        }
+    }
+}
+
+pub fn users_delete(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserDelete, Some(user.id)) {
+        return res;
+    }
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+
+    // Check if the last admin is not deleted
+    if user.is_last_admin(ctx) {
+        let mut report = Report::new();
+        report.insert_error("role", "can't delete the last admin");
+        return Response::new().status(Status::BadRequest).json(report);
+    }
+
+    // Admins delete other users directly
+    if user.id != auth_user.id {
+        user.delete(ctx);
+        return Response::new();
+    }
+
+    // Parse and validate body
+    let body = match serde_urlencoded::from_bytes::<api::UserDeleteBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => Into::<UserDeleteBody>::into(body),
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    if let Err(errors) = body.validate_with(ctx) {
+        return Response::new().status(Status::BadRequest).json(errors);
+    }
+
+    // Deactivate user, the account is purged after the grace period
+    ctx.database
+        .execute("DELETE FROM user_deletions WHERE user_id = ?", user.id);
+    let user_deletion = UserDeletion {
+        user_id: user.id,
+        ..Default::default()
+    };
+    ctx.database.insert_user_deletion(user_deletion.clone());
+
+    // Revoke all user sessions
+    revoke_user_sessions(ctx, user.id, None);
+
+    Response::new().json(Into::<api::UserDeletion>::into(user_deletion))
+}
+
 // MARK: Users sessions
 pub fn users_sessions(req: &Request, ctx: &Context) -> Response {
     let user = match find_user(req, ctx) {
@@ -329,11 +558,8 @@ pub fn users_sessions(req: &Request, ctx: &Context) -> Response {
     };
 
     // Authorization
-    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
-    if !(user.id == auth_user.id || auth_user.role == UserRole::Admin) {
-        return Response::new()
-            .status(Status::Unauthorized)
-            .body("401 Unauthorized");
+    if let Some(res) = authorize(ctx, Ability::SessionViewAny, Some(user.id)) {
+        return res;
     }
 
     // Parse index query
@@ -348,10 +574,11 @@ pub fn users_sessions(req: &Request, ctx: &Context) -> Response {
         return Response::with_status(Status::BadRequest).json(report);
     }
 
+    // Past impersonation sessions stay visible so the user can see when their account was accessed
     let total = ctx
         .database
         .query::<i64>(
-            "SELECT COUNT(id) FROM sessions WHERE user_id = ? AND expires_at > ?",
+            "SELECT COUNT(id) FROM sessions WHERE user_id = ? AND (expires_at > ? OR impersonator_user_id IS NOT NULL)",
             (user.id, Utc::now()),
         )
         .next()
@@ -360,11 +587,12 @@ pub fn users_sessions(req: &Request, ctx: &Context) -> Response {
             .database
             .query::<Session>(
                 formatcp!(
-                    "SELECT {} FROM sessions WHERE user_id = ? AND expires_at > ? ORDER BY expires_at DESC LIMIT ? OFFSET ?",
+                    "SELECT {} FROM sessions WHERE user_id = ? AND (expires_at > ? OR impersonator_user_id IS NOT NULL) ORDER BY expires_at DESC LIMIT ? OFFSET ?",
                     Session::columns()
                 ),
                 (user.id, Utc::now(), query.limit, query.limit * (query.page - 1)),
             )
+            .map(|session| session.with_impersonator(ctx))
             .map(Into::<api::Session>::into)
             .collect::<Vec<_>>();
     Response::new().json(api::SessionIndexResponse {
@@ -377,6 +605,44 @@ pub fn users_sessions(req: &Request, ctx: &Context) -> Response {
     })
 }
 
+// MARK: Users sessions revoke
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct UserSessionsRevokeQuery {
+    keep_current: bool,
+}
+
+pub fn users_sessions_revoke(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::SessionRevoke, Some(user.id)) {
+        return res;
+    }
+
+    // Parse query
+    let query = match req.url.query() {
+        Some(query) => match serde_urlencoded::from_str::<UserSessionsRevokeQuery>(query) {
+            Ok(query) => query,
+            Err(_) => return Response::with_status(Status::BadRequest),
+        },
+        None => UserSessionsRevokeQuery::default(),
+    };
+
+    // Revoke sessions
+    let except_session_id = if query.keep_current {
+        current_session_id(ctx, user.id)
+    } else {
+        None
+    };
+    revoke_user_sessions(ctx, user.id, except_session_id);
+    record_security_event(req, ctx, user.id, SecurityEventType::SessionRevoked);
+    Response::new()
+}
+
 // MARK: Users posts
 pub fn users_posts(req: &Request, ctx: &Context) -> Response {
     let user = match find_user(req, ctx) {
@@ -400,12 +666,28 @@ pub fn users_posts(req: &Request, ctx: &Context) -> Response {
     }
 
     // Get user posts
+    let auth_user_id = ctx.auth_user.as_ref().map_or(Uuid::nil(), |user| user.id);
+    let post_filters = fetch_auth_user_post_filters(ctx, PostFilterContext::Profile);
     let search_query = format!("%{}%", query.query.replace("%", "\\%"));
     let total = ctx
         .database
         .query::<i64>(
-            "SELECT COUNT(id) FROM posts WHERE user_id = ? AND text LIKE ?",
-            (user.id, search_query.clone()),
+            formatcp!(
+                "{} SELECT COUNT(id) FROM posts WHERE user_id = ? AND {} AND text LIKE ? AND {}",
+                BLOCKED_USERS_CTE,
+                POST_NOT_HIDDEN_CONDITION,
+                POST_NOT_FILTERED_CONDITION
+            ),
+            (
+                auth_user_id,
+                auth_user_id,
+                Utc::now(),
+                user.id,
+                search_query.clone(),
+                auth_user_id,
+                PostFilterContext::Profile as i64,
+                Utc::now(),
+            ),
         )
         .next()
         .expect("Can't count posts");
@@ -413,16 +695,26 @@ pub fn users_posts(req: &Request, ctx: &Context) -> Response {
         .database
         .query::<Post>(
             formatcp!(
-                "SELECT {} FROM posts WHERE user_id = ? AND text LIKE ? ORDER BY created_at DESC LIMIT ? OFFSET ?",
-                Post::columns()
+                "{} SELECT {} FROM posts WHERE user_id = ? AND {} AND text LIKE ? AND {} ORDER BY created_at DESC LIMIT ? OFFSET ?",
+                BLOCKED_USERS_CTE,
+                Post::columns(),
+                POST_NOT_HIDDEN_CONDITION,
+                POST_NOT_FILTERED_CONDITION
             ),
             (
+                auth_user_id,
+                auth_user_id,
+                Utc::now(),
                 user.id,
-               search_query,
-               query. limit,
-               query. limit * (query.page - 1),
-            )
+                search_query,
+                auth_user_id,
+                PostFilterContext::Profile as i64,
+                Utc::now(),
+                query.limit,
+                query.limit * (query.page - 1),
+            ),
         )
+        .map(|post| post.apply_filters(&post_filters))
         .map(|mut post| {
             post.fetch_relationships(ctx);
             post
@@ -439,80 +731,863 @@ pub fn users_posts(req: &Request, ctx: &Context) -> Response {
     })
 }
 
-#[cfg(test)]
-mod test {
-    use small_http::Method;
-
-    use super::*;
-    use crate::controllers::auth::generate_random_token;
-    use crate::router;
-    use crate::test_utils::{create_user, create_user_session};
-
-    // MARK: Test Users index
-    #[test]
-    fn test_users_index() {
-        let ctx = Context::with_test_database();
-        let router = router(ctx.clone());
-        let (_, session) = create_user_session(&ctx, UserRole::Admin);
+// MARK: Users post filters
+pub fn users_post_filters(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
 
-        for _ in 0..10 {
-            create_user(&ctx, UserRole::Normal);
-        }
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserViewPrivate, Some(user.id)) {
+        return res;
+    }
 
-        let req = Request::with_url("http://localhost/users")
-            .header("Authorization", format!("Bearer {}", session.token));
-        let res = router.handle(&req);
-        assert_eq!(res.status, Status::Ok);
-        let res = serde_json::from_slice::<api::UserIndexResponse>(&res.body).unwrap();
-        assert_eq!(res.pagination.total, 10 + 1);
+    // Parse index query
+    let query = match req.url.query() {
+        Some(query) => match serde_urlencoded::from_str::<IndexQuery>(query) {
+            Ok(query) => query,
+            Err(_) => return Response::with_status(Status::BadRequest),
+        },
+        None => IndexQuery::default(),
+    };
+    if let Err(report) = query.validate() {
+        return Response::with_status(Status::BadRequest).json(report);
     }
 
-    // MARK: Test Users create
-    #[test]
-    fn test_users_create() {
-        let ctx = Context::with_test_database();
-        let router = router(ctx.clone());
-        let (_, session) = create_user_session(&ctx, UserRole::Admin);
+    // Get user post filters
+    let total = ctx
+        .database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM post_filters WHERE user_id = ?",
+            user.id,
+        )
+        .next()
+        .expect("Can't count post filters");
+    let post_filters = ctx
+        .database
+        .query::<PostFilter>(
+            formatcp!(
+                "SELECT {} FROM post_filters WHERE user_id = ? ORDER BY created_at DESC LIMIT ? OFFSET ?",
+                PostFilter::columns()
+            ),
+            (user.id, query.limit, query.limit * (query.page - 1)),
+        )
+        .map(Into::<api::PostFilter>::into)
+        .collect::<Vec<_>>();
+    Response::new().json(api::PostFilterIndexResponse {
+        pagination: api::Pagination {
+            total,
+            page: query.page,
+            limit: query.limit,
+        },
+        data: post_filters,
+    })
+}
 
-        let req = Request::with_url("http://localhost/users")
-            .method(Method::Post)
-            .header("Authorization", format!("Bearer {}", session.token))
-            .body(
-                serde_urlencoded::to_string(api::UserCreateBody {
-                    username: "newuser".to_string(),
-                    email: "newuser@example.com".to_string(),
-                    password: "password123".to_string(),
-                })
-                .unwrap(),
-            );
-        let res = router.handle(&req);
-        assert_eq!(res.status, Status::Ok);
-        let res = serde_json::from_slice::<api::User>(&res.body).unwrap();
-        assert_eq!(res.username, "newuser");
-        assert_eq!(res.email, "newuser@example.com");
-    }
+// MARK: Users invite codes
+pub fn users_invite_codes(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
 
-    // MARK: Test Users show
-    #[test]
-    fn test_users_show() {
-        let ctx = Context::with_test_database();
-        let router = router(ctx.clone());
-        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserViewPrivate, Some(user.id)) {
+        return res;
+    }
 
-        let req = Request::with_url(format!("http://localhost/users/{}", user.id))
-            .header("Authorization", format!("Bearer {}", session.token));
-        let res = router.handle(&req);
-        assert_eq!(res.status, Status::Ok);
-        let res = serde_json::from_slice::<api::User>(&res.body).unwrap();
-        assert_eq!(res.id, user.id);
+    // Parse index query
+    let query = match req.url.query() {
+        Some(query) => match serde_urlencoded::from_str::<IndexQuery>(query) {
+            Ok(query) => query,
+            Err(_) => return Response::with_status(Status::BadRequest),
+        },
+        None => IndexQuery::default(),
+    };
+    if let Err(report) = query.validate() {
+        return Response::with_status(Status::BadRequest).json(report);
     }
 
-    // MARK: Test Users update
-    #[test]
-    fn test_users_update() {
-        let ctx = Context::with_test_database();
-        let router = router(ctx.clone());
-        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+    // Get user invite codes
+    let total = ctx
+        .database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM invite_codes WHERE user_id = ?",
+            user.id,
+        )
+        .next()
+        .expect("Can't count invite codes");
+    let invite_codes = ctx
+        .database
+        .query::<InviteCode>(
+            formatcp!(
+                "SELECT {} FROM invite_codes WHERE user_id = ? ORDER BY created_at DESC LIMIT ? OFFSET ?",
+                InviteCode::columns()
+            ),
+            (user.id, query.limit, query.limit * (query.page - 1)),
+        )
+        .map(Into::<api::InviteCode>::into)
+        .collect::<Vec<_>>();
+    Response::new().json(api::InviteCodeIndexResponse {
+        pagination: api::Pagination {
+            total,
+            page: query.page,
+            limit: query.limit,
+        },
+        data: invite_codes,
+    })
+}
+
+// MARK: Users personal access tokens
+pub fn users_personal_access_tokens(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserViewPrivate, Some(user.id)) {
+        return res;
+    }
+
+    // Parse index query
+    let query = match req.url.query() {
+        Some(query) => match serde_urlencoded::from_str::<IndexQuery>(query) {
+            Ok(query) => query,
+            Err(_) => return Response::with_status(Status::BadRequest),
+        },
+        None => IndexQuery::default(),
+    };
+    if let Err(report) = query.validate() {
+        return Response::with_status(Status::BadRequest).json(report);
+    }
+
+    // Get user personal access tokens
+    let total = ctx
+        .database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM personal_access_tokens WHERE user_id = ?",
+            user.id,
+        )
+        .next()
+        .expect("Can't count personal access tokens");
+    let personal_access_tokens = ctx
+        .database
+        .query::<PersonalAccessToken>(
+            formatcp!(
+                "SELECT {} FROM personal_access_tokens WHERE user_id = ? ORDER BY created_at DESC LIMIT ? OFFSET ?",
+                PersonalAccessToken::columns()
+            ),
+            (user.id, query.limit, query.limit * (query.page - 1)),
+        )
+        .map(Into::<api::PersonalAccessToken>::into)
+        .collect::<Vec<_>>();
+    Response::new().json(api::PersonalAccessTokenIndexResponse {
+        pagination: api::Pagination {
+            total,
+            page: query.page,
+            limit: query.limit,
+        },
+        data: personal_access_tokens,
+    })
+}
+
+// MARK: Users OAuth apps
+pub fn users_oauth_apps(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserViewPrivate, Some(user.id)) {
+        return res;
+    }
+
+    // Parse index query
+    let query = match req.url.query() {
+        Some(query) => match serde_urlencoded::from_str::<IndexQuery>(query) {
+            Ok(query) => query,
+            Err(_) => return Response::with_status(Status::BadRequest),
+        },
+        None => IndexQuery::default(),
+    };
+    if let Err(report) = query.validate() {
+        return Response::with_status(Status::BadRequest).json(report);
+    }
+
+    // Get user OAuth apps
+    let total = ctx
+        .database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM oauth_apps WHERE user_id = ?",
+            user.id,
+        )
+        .next()
+        .expect("Can't count OAuth apps");
+    let oauth_apps = ctx
+        .database
+        .query::<OAuthApp>(
+            formatcp!(
+                "SELECT {} FROM oauth_apps WHERE user_id = ? ORDER BY created_at DESC LIMIT ? OFFSET ?",
+                OAuthApp::columns()
+            ),
+            (user.id, query.limit, query.limit * (query.page - 1)),
+        )
+        .map(Into::<api::OAuthApp>::into)
+        .collect::<Vec<_>>();
+    Response::new().json(api::OAuthAppIndexResponse {
+        pagination: api::Pagination {
+            total,
+            page: query.page,
+            limit: query.limit,
+        },
+        data: oauth_apps,
+    })
+}
+
+// MARK: Users OAuth grants
+pub fn users_oauth_grants(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserViewPrivate, Some(user.id)) {
+        return res;
+    }
+
+    // Parse index query
+    let query = match req.url.query() {
+        Some(query) => match serde_urlencoded::from_str::<IndexQuery>(query) {
+            Ok(query) => query,
+            Err(_) => return Response::with_status(Status::BadRequest),
+        },
+        None => IndexQuery::default(),
+    };
+    if let Err(report) = query.validate() {
+        return Response::with_status(Status::BadRequest).json(report);
+    }
+
+    // Get user OAuth grants
+    let total = ctx
+        .database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM oauth_grants WHERE user_id = ?",
+            user.id,
+        )
+        .next()
+        .expect("Can't count OAuth grants");
+    let oauth_grants = ctx
+        .database
+        .query::<OAuthGrant>(
+            formatcp!(
+                "SELECT {} FROM oauth_grants WHERE user_id = ? ORDER BY created_at DESC LIMIT ? OFFSET ?",
+                OAuthGrant::columns()
+            ),
+            (user.id, query.limit, query.limit * (query.page - 1)),
+        )
+        .map(|mut oauth_grant| {
+            oauth_grant.fetch_relationships(ctx);
+            oauth_grant
+        })
+        .map(Into::<api::OAuthGrant>::into)
+        .collect::<Vec<_>>();
+    Response::new().json(api::OAuthGrantIndexResponse {
+        pagination: api::Pagination {
+            total,
+            page: query.page,
+            limit: query.limit,
+        },
+        data: oauth_grants,
+    })
+}
+
+// MARK: Users security events
+pub fn users_security_events(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserViewPrivate, Some(user.id)) {
+        return res;
+    }
+
+    // Parse index query
+    let query = match req.url.query() {
+        Some(query) => match serde_urlencoded::from_str::<IndexQuery>(query) {
+            Ok(query) => query,
+            Err(_) => return Response::with_status(Status::BadRequest),
+        },
+        None => IndexQuery::default(),
+    };
+    if let Err(report) = query.validate() {
+        return Response::with_status(Status::BadRequest).json(report);
+    }
+
+    // Get user security events
+    let total = ctx
+        .database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM security_events WHERE user_id = ?",
+            user.id,
+        )
+        .next()
+        .expect("Can't count security events");
+    let security_events = ctx
+        .database
+        .query::<SecurityEvent>(
+            formatcp!(
+                "SELECT {} FROM security_events WHERE user_id = ? ORDER BY created_at DESC LIMIT ? OFFSET ?",
+                SecurityEvent::columns()
+            ),
+            (user.id, query.limit, query.limit * (query.page - 1)),
+        )
+        .map(Into::<api::SecurityEvent>::into)
+        .collect::<Vec<_>>();
+    Response::new().json(api::SecurityEventIndexResponse {
+        pagination: api::Pagination {
+            total,
+            page: query.page,
+            limit: query.limit,
+        },
+        data: security_events,
+    })
+}
+
+// MARK: Users impersonate
+pub fn users_impersonate(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserImpersonate, None) {
+        return res;
+    }
+    if let Some(res) = deny_impersonation(ctx) {
+        return res;
+    }
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+    if user.id == auth_user.id || user.role == UserRole::Admin {
+        let mut report = Report::new();
+        report.insert_error("user", "can't impersonate yourself or another admin");
+        return Response::new().status(Status::BadRequest).json(report);
+    }
+
+    // Create time limited impersonation session
+    let token = generate_session_token();
+    let session = Session {
+        token: hash_token(&token),
+        remember: false,
+        impersonator_user_id: Some(auth_user.id),
+        expires_at: Utc::now() + IMPERSONATION_SESSION_DURATION,
+        ..request_session(req, ctx, user.id)
+    };
+    ctx.database.insert_session(session.clone());
+    record_security_event(req, ctx, user.id, SecurityEventType::ImpersonationStarted);
+
+    Response::new().json(api::AuthLoginResponse {
+        token,
+        csrf_token: None,
+        session: session.with_impersonator(ctx).into(),
+        user: user.into(),
+    })
+}
+
+// MARK: Users suspensions
+pub fn users_suspensions(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserSuspend, None) {
+        return res;
+    }
+
+    // Parse index query
+    let query = match req.url.query() {
+        Some(query) => match serde_urlencoded::from_str::<IndexQuery>(query) {
+            Ok(query) => query,
+            Err(_) => return Response::with_status(Status::BadRequest),
+        },
+        None => IndexQuery::default(),
+    };
+    if let Err(report) = query.validate() {
+        return Response::with_status(Status::BadRequest).json(report);
+    }
+
+    // Get user suspensions
+    let total = ctx
+        .database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM user_suspensions WHERE user_id = ?",
+            user.id,
+        )
+        .next()
+        .expect("Can't count user suspensions");
+    let user_suspensions = ctx
+        .database
+        .query::<UserSuspension>(
+            formatcp!(
+                "SELECT {} FROM user_suspensions WHERE user_id = ? ORDER BY created_at DESC LIMIT ? OFFSET ?",
+                UserSuspension::columns()
+            ),
+            (user.id, query.limit, query.limit * (query.page - 1)),
+        )
+        .map(Into::<api::UserSuspension>::into)
+        .collect::<Vec<_>>();
+    Response::new().json(api::UserSuspensionIndexResponse {
+        pagination: api::Pagination {
+            total,
+            page: query.page,
+            limit: query.limit,
+        },
+        data: user_suspensions,
+    })
+}
+
+// MARK: Users suspend
+#[derive(Validate)]
+struct UserSuspendBody {
+    #[validate(length(min = 1, max = 512))]
+    reason: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl From<api::UserSuspendBody> for UserSuspendBody {
+    fn from(body: api::UserSuspendBody) -> Self {
+        Self {
+            reason: body.reason,
+            expires_at: body.expires_at,
+        }
+    }
+}
+
+pub fn users_suspend(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserSuspend, None) {
+        return res;
+    }
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+    if user.id == auth_user.id {
+        return Response::new()
+            .status(Status::BadRequest)
+            .body("400 Bad Request");
+    }
+    if !outranks(auth_user, &user) {
+        return Response::new()
+            .status(Status::Forbidden)
+            .body("403 Forbidden: can't suspend user with equal or higher role");
+    }
+
+    // Parse and validate body
+    let body = match serde_urlencoded::from_bytes::<api::UserSuspendBody>(
+        req.body.as_deref().unwrap_or(&[]),
+    ) {
+        Ok(body) => Into::<UserSuspendBody>::into(body),
+        Err(_) => {
+            return Response::new()
+                .status(Status::BadRequest)
+                .body("400 Bad Request");
+        }
+    };
+    if let Err(errors) = body.validate() {
+        return Response::new().status(Status::BadRequest).json(errors);
+    }
+    if body
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        let mut report = Report::new();
+        report.insert_error("expiresAt", "must be in the future");
+        return Response::new().status(Status::BadRequest).json(report);
+    }
+
+    // Lift possible old suspension
+    let now = Utc::now();
+    ctx.database.execute(
+        "UPDATE user_suspensions SET lifted_at = ?, updated_at = ? WHERE user_id = ? AND lifted_at IS NULL",
+        (now, now, user.id),
+    );
+
+    // Create new user suspension
+    let user_suspension = UserSuspension {
+        user_id: user.id,
+        suspended_by_user_id: Some(auth_user.id),
+        reason: body.reason,
+        expires_at: body.expires_at,
+        ..Default::default()
+    };
+    ctx.database.insert_user_suspension(user_suspension.clone());
+
+    // Revoke all user sessions
+    revoke_user_sessions(ctx, user.id, None);
+
+    Response::new().json(Into::<api::UserSuspension>::into(user_suspension))
+}
+
+// MARK: Users suspend delete
+pub fn users_suspend_delete(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserSuspend, None) {
+        return res;
+    }
+    if !outranks(ctx.auth_user.as_ref().expect("Not authed"), &user) {
+        return Response::new()
+            .status(Status::Forbidden)
+            .body("403 Forbidden: can't lift suspension of user with equal or higher role");
+    }
+
+    // Lift user suspension
+    let now = Utc::now();
+    ctx.database.execute(
+        "UPDATE user_suspensions SET lifted_at = ?, updated_at = ? WHERE user_id = ? AND lifted_at IS NULL",
+        (now, now, user.id),
+    );
+    Response::new()
+}
+
+// MARK: Users lockout delete
+pub fn users_lockout_delete(req: &Request, ctx: &Context) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserUnlock, None) {
+        return res;
+    }
+
+    // Clear failed login attempts
+    clear_failed_logins(
+        ctx,
+        &[
+            &normalize_logon(&user.username),
+            &normalize_logon(&user.email),
+        ],
+    );
+    Response::new()
+}
+
+// MARK: Users relations
+fn users_relations_index(req: &Request, ctx: &Context, r#type: UserRelationType) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    if let Some(res) = authorize(ctx, Ability::UserViewPrivate, Some(user.id)) {
+        return res;
+    }
+
+    // Parse index query
+    let query = match req.url.query() {
+        Some(query) => match serde_urlencoded::from_str::<IndexQuery>(query) {
+            Ok(query) => query,
+            Err(_) => return Response::with_status(Status::BadRequest),
+        },
+        None => IndexQuery::default(),
+    };
+    if let Err(report) = query.validate() {
+        return Response::with_status(Status::BadRequest).json(report);
+    }
+
+    // Get related users
+    let total = ctx
+        .database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM user_relations WHERE user_id = ? AND type = ?",
+            (user.id, r#type),
+        )
+        .next()
+        .expect("Can't count user relations");
+    let related_users = ctx
+        .database
+        .query::<User>(
+            formatcp!(
+                "SELECT {} FROM users WHERE id IN (SELECT target_user_id FROM user_relations WHERE user_id = ? AND type = ?) ORDER BY username LIMIT ? OFFSET ?",
+                User::columns()
+            ),
+            (user.id, r#type, query.limit, query.limit * (query.page - 1)),
+        )
+        .map(Into::<api::User>::into)
+        .collect::<Vec<_>>();
+    Response::new().json(api::UserIndexResponse {
+        pagination: api::Pagination {
+            total,
+            page: query.page,
+            limit: query.limit,
+        },
+        data: related_users,
+    })
+}
+
+fn users_relations_create(req: &Request, ctx: &Context, r#type: UserRelationType) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+    if user.id == auth_user.id {
+        return Response::new()
+            .status(Status::BadRequest)
+            .body("400 Bad Request");
+    }
+
+    // Remove possible old user relation
+    ctx.database.execute(
+        "DELETE FROM user_relations WHERE user_id = ? AND target_user_id = ? AND type = ?",
+        (auth_user.id, user.id, r#type),
+    );
+
+    // Create new user relation
+    ctx.database.insert_user_relation(UserRelation {
+        user_id: auth_user.id,
+        target_user_id: user.id,
+        r#type,
+        ..Default::default()
+    });
+    Response::new()
+}
+
+fn users_relations_delete(req: &Request, ctx: &Context, r#type: UserRelationType) -> Response {
+    let user = match find_user(req, ctx) {
+        Some(user) => user,
+        None => return not_found(req, ctx),
+    };
+
+    // Authorization
+    let auth_user = ctx.auth_user.as_ref().expect("Not authed");
+
+    // Remove user relation
+    ctx.database.execute(
+        "DELETE FROM user_relations WHERE user_id = ? AND target_user_id = ? AND type = ?",
+        (auth_user.id, user.id, r#type),
+    );
+    Response::new()
+}
+
+// MARK: Users blocks
+pub fn users_blocks(req: &Request, ctx: &Context) -> Response {
+    users_relations_index(req, ctx, UserRelationType::Block)
+}
+
+pub fn users_block(req: &Request, ctx: &Context) -> Response {
+    users_relations_create(req, ctx, UserRelationType::Block)
+}
+
+pub fn users_block_delete(req: &Request, ctx: &Context) -> Response {
+    users_relations_delete(req, ctx, UserRelationType::Block)
+}
+
+// MARK: Users mutes
+pub fn users_mutes(req: &Request, ctx: &Context) -> Response {
+    users_relations_index(req, ctx, UserRelationType::Mute)
+}
+
+pub fn users_mute(req: &Request, ctx: &Context) -> Response {
+    users_relations_create(req, ctx, UserRelationType::Mute)
+}
+
+pub fn users_mute_delete(req: &Request, ctx: &Context) -> Response {
+    users_relations_delete(req, ctx, UserRelationType::Mute)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use small_http::Method;
+
+    use super::*;
+    use crate::mail::OutboxTransport;
+    use crate::models::pow_challenge::solve_pow_challenge;
+    use crate::models::session::record_expired_impersonations;
+    use crate::models::user_suspension::find_active_user_suspension;
+    use crate::models::TokenScope;
+    use crate::router;
+    use crate::test_utils::{create_session, create_user, create_user_session};
+
+    // MARK: Test Users index
+    #[test]
+    fn test_users_index() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, session) = create_user_session(&ctx, UserRole::Admin);
+
+        for _ in 0..10 {
+            create_user(&ctx, UserRole::Normal);
+        }
+
+        let req = Request::with_url("http://localhost/users")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::UserIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 10 + 1);
+    }
+
+    // MARK: Test Users create
+    #[test]
+    fn test_users_create() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, session) = create_user_session(&ctx, UserRole::Admin);
+
+        let req = Request::with_url("http://localhost/users")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body(
+                serde_urlencoded::to_string(api::UserCreateBody {
+                    username: "newuser".to_string(),
+                    email: "newuser@example.com".to_string(),
+                    password: "password123".to_string(),
+                    invite_code: None,
+                    pow_challenge: None,
+                    pow_nonce: None,
+                })
+                .unwrap(),
+            );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::User>(&res.body).unwrap();
+        assert_eq!(res.username, "newuser");
+        assert_eq!(res.email, "newuser@example.com");
+    }
+
+    #[test]
+    fn test_users_create_pow() {
+        let mut ctx = Context::with_test_database();
+        ctx.settings.pow_difficulty = 8;
+        let router = router(ctx.clone());
+
+        // Registration without proof of work is rejected
+        let req = Request::with_url("http://localhost/users")
+            .method(Method::Post)
+            .body("username=newuser&email=newuser@example.com&password=password123");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Registration with solved challenge is accepted
+        let req = Request::with_url("http://localhost/auth/pow_challenge").method(Method::Post);
+        let res = router.handle(&req);
+        let pow_challenge = serde_json::from_slice::<api::PowChallenge>(&res.body).unwrap();
+        assert_eq!(pow_challenge.difficulty, 8);
+        let nonce = solve_pow_challenge(&pow_challenge.challenge, pow_challenge.difficulty);
+        let req = Request::with_url("http://localhost/users")
+            .method(Method::Post)
+            .body(format!(
+                "username=newuser&email=newuser@example.com&password=password123&powChallenge={}&powNonce={}",
+                pow_challenge.challenge, nonce
+            ));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+
+    #[test]
+    fn test_users_create_registration_mode() {
+        let mut ctx = Context::with_test_database();
+        ctx.settings.registration_mode = RegistrationMode::InviteOnly;
+        let router = router(ctx.clone());
+        let inviter = create_user(&ctx, UserRole::Admin);
+        let invite_code = InviteCode {
+            user_id: inviter.id,
+            ..Default::default()
+        };
+        ctx.database.insert_invite_code(invite_code.clone());
+
+        // Registration without invite code is rejected
+        let req = Request::with_url("http://localhost/users")
+            .method(Method::Post)
+            .body("username=newuser&email=newuser@example.com&password=password123");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Registration with invite code records the inviter
+        let req = Request::with_url("http://localhost/users")
+            .method(Method::Post)
+            .body(format!(
+                "username=newuser&email=newuser@example.com&password=password123&inviteCode={}",
+                invite_code.code
+            ));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::User>(&res.body).unwrap();
+        let user = ctx
+            .database
+            .query::<User>(
+                formatcp!("SELECT {} FROM users WHERE id = ? LIMIT 1", User::columns()),
+                res.id,
+            )
+            .next()
+            .unwrap();
+        assert_eq!(user.invited_by_user_id, Some(inviter.id));
+
+        // Used up invite code is rejected
+        let req = Request::with_url("http://localhost/users")
+            .method(Method::Post)
+            .body(format!(
+                "username=otheruser&email=otheruser@example.com&password=password123&inviteCode={}",
+                invite_code.code
+            ));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Closed registration is rejected
+        let mut ctx = Context::with_test_database();
+        ctx.settings.registration_mode = RegistrationMode::Closed;
+        let router = crate::router(ctx.clone());
+        let req = Request::with_url("http://localhost/users")
+            .method(Method::Post)
+            .body("username=newuser&email=newuser@example.com&password=password123");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+    }
+
+    // MARK: Test Users show
+    #[test]
+    fn test_users_show() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+
+        let req = Request::with_url(format!("http://localhost/users/{}", user.id))
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::User>(&res.body).unwrap();
+        assert_eq!(res.id, user.id);
+    }
+
+    // MARK: Test Users update
+    #[test]
+    fn test_users_update() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
 
         let req = Request::with_url(format!("http://localhost/users/{}", user.id))
             .method(Method::Put)
@@ -532,17 +1607,485 @@ mod test {
             );
         let res = router.handle(&req);
         assert_eq!(res.status, Status::Ok);
-        let res = serde_json::from_slice::<api::User>(&res.body).unwrap();
-        assert_eq!(res.username, "updateduser");
-        assert_eq!(res.email, "updateduser@example.com");
+        let res = serde_json::from_slice::<api::User>(&res.body).unwrap();
+        assert_eq!(res.username, "updateduser");
+        assert_eq!(res.email, user.email);
+    }
+
+    // MARK: Test Users email verification
+    #[test]
+    fn test_users_email_verification() {
+        let outbox = Arc::new(OutboxTransport::default());
+        let ctx = Context {
+            mailer: outbox.clone(),
+            ..Context::with_test_database()
+        };
+        let router = router(ctx.clone());
+        let last_mail_token = || {
+            outbox
+                .mails()
+                .last()
+                .unwrap()
+                .body
+                .split("token=")
+                .nth(1)
+                .and_then(|rest| rest.split_whitespace().next())
+                .unwrap()
+                .to_string()
+        };
+
+        // Signup sends verification mail
+        let req = Request::with_url("http://localhost/users")
+            .method(Method::Post)
+            .body("username=test&email=test@example.com&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let user = serde_json::from_slice::<api::User>(&res.body).unwrap();
+        assert!(user.email_verified_at.is_none());
+        assert_eq!(outbox.mails().len(), 1);
+        assert_eq!(outbox.mails()[0].to, "test@example.com");
+
+        // Verify email
+        let req = Request::with_url("http://localhost/auth/verify_email")
+            .method(Method::Post)
+            .body(format!("token={}", last_mail_token()));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let user = serde_json::from_slice::<api::User>(&res.body).unwrap();
+        assert!(user.email_verified_at.is_some());
+
+        // Resend is not possible when verified
+        let session = create_session(&ctx, &user);
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/resend_email_verification",
+            user.id
+        ))
+        .method(Method::Post)
+        .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Changed email is pending
+        let req = Request::with_url(format!("http://localhost/users/{}", user.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("username=test&email=new@example.com");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::User>(&res.body).unwrap();
+        assert_eq!(res.email, "test@example.com");
+        assert_eq!(outbox.mails().last().unwrap().to, "new@example.com");
+
+        // Resend invalidates the previous token
+        let old_token = last_mail_token();
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/resend_email_verification",
+            user.id
+        ))
+        .method(Method::Post)
+        .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        assert_eq!(outbox.mails().last().unwrap().to, "new@example.com");
+        let req = Request::with_url("http://localhost/auth/verify_email")
+            .method(Method::Post)
+            .body(format!("token={}", old_token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Confirm pending email
+        let req = Request::with_url("http://localhost/auth/verify_email")
+            .method(Method::Post)
+            .body(format!("token={}", last_mail_token()));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::User>(&res.body).unwrap();
+        assert_eq!(res.email, "new@example.com");
+    }
+
+    // MARK: Test Users change password
+    #[test]
+    fn test_users_change_password() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+
+        let user = User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        };
+        ctx.database.insert_user(user.clone());
+        let session = create_session(&ctx, &user);
+        let other_session = create_session(&ctx, &user);
+        let insert_personal_access_token = || {
+            ctx.database
+                .insert_personal_access_token(PersonalAccessToken {
+                    user_id: user.id,
+                    token: hash_token(&format!("pbp_{}", Uuid::now_v7())),
+                    ..Default::default()
+                });
+        };
+        let count_personal_access_tokens = || {
+            ctx.database
+                .query::<i64>(
+                    "SELECT COUNT(id) FROM personal_access_tokens WHERE user_id = ?",
+                    user.id,
+                )
+                .next()
+                .unwrap()
+        };
+        insert_personal_access_token();
+
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/change_password",
+            user.id
+        ))
+        .method(Method::Put)
+        .header("Authorization", format!("Bearer {}", session.token))
+        .body(
+            serde_urlencoded::to_string(api::UserUpdatePasswordBody {
+                current_password: "password".to_string(),
+                password: "password123".to_string(),
+                revoke_tokens: None,
+            })
+            .unwrap(),
+        );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // Tokens are revoked by default
+        assert_eq!(count_personal_access_tokens(), 0);
+
+        // Current session stays active, other sessions are revoked
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", other_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+
+        // Tokens can be kept explicitly
+        insert_personal_access_token();
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/change_password",
+            user.id
+        ))
+        .method(Method::Put)
+        .header("Authorization", format!("Bearer {}", session.token))
+        .body(
+            serde_urlencoded::to_string(api::UserUpdatePasswordBody {
+                current_password: "password123".to_string(),
+                password: "password456".to_string(),
+                revoke_tokens: Some(false),
+            })
+            .unwrap(),
+        );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        assert_eq!(count_personal_access_tokens(), 1);
+    }
+
+    // MARK: Test Users sessions revoke
+    #[test]
+    fn test_users_sessions_revoke() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let other_session = create_session(&ctx, &user);
+        let (_, stranger_session) = create_user_session(&ctx, UserRole::Normal);
+
+        // Other users can't revoke sessions
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .method(Method::Delete)
+            .header(
+                "Authorization",
+                format!("Bearer {}", stranger_session.token),
+            );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Revoke other sessions and keep current
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/sessions?keepCurrent=true",
+            user.id
+        ))
+        .method(Method::Delete)
+        .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", other_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+
+        // Revoke all sessions
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+    }
+
+    // MARK: Test Users change role
+    #[test]
+    fn test_users_change_role() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (admin, admin_session) = create_user_session(&ctx, UserRole::Admin);
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+
+        // Normal user can't change roles
+        let req = Request::with_url(format!("http://localhost/users/{}/role", user.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("role=admin");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Last admin can't be demoted
+        let req = Request::with_url(format!("http://localhost/users/{}/role", admin.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", admin_session.token))
+            .body("role=normal");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Admin promotes user
+        let req = Request::with_url(format!("http://localhost/users/{}/role", user.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", admin_session.token))
+            .body("role=admin");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::User>(&res.body).unwrap();
+        assert!(matches!(res.role, api::UserRole::Admin));
+
+        // Admin can now be demoted
+        let req = Request::with_url(format!("http://localhost/users/{}/role", admin.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", admin_session.token))
+            .body("role=moderator");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+
+    // MARK: Test Users delete
+    #[test]
+    fn test_users_delete() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (admin, admin_session) = create_user_session(&ctx, UserRole::Admin);
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let post = Post {
+            user_id: admin.id,
+            text: "Hello world".to_string(),
+            ..Default::default()
+        };
+        ctx.database.insert_post(post.clone());
+
+        // User likes, replies and reposts the post
+        for (path, method) in [
+            ("like", Method::Put),
+            ("reply", Method::Post),
+            ("repost", Method::Post),
+        ] {
+            let req = Request::with_url(format!("http://localhost/posts/{}/{}", post.id, path))
+                .method(method)
+                .header("Authorization", format!("Bearer {}", session.token))
+                .body("text=Nice");
+            let res = router.handle(&req);
+            assert_eq!(res.status, Status::Ok);
+        }
+
+        // Last admin can't be deleted
+        let req = Request::with_url(format!("http://localhost/users/{}", admin.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", admin_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Admin deletes user
+        let req = Request::with_url(format!("http://localhost/users/{}", user.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", admin_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // User data is removed and counters are repaired
+        let req = Request::with_url(format!("http://localhost/users/{}", user.id));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::NotFound);
+        let count = ctx
+            .database
+            .query::<i64>("SELECT COUNT(id) FROM sessions WHERE user_id = ?", user.id)
+            .next()
+            .unwrap();
+        assert_eq!(count, 0);
+        let req = Request::with_url(format!("http://localhost/posts/{}", post.id));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::Post>(&res.body).unwrap();
+        assert_eq!(res.likes_count, 0);
+        assert_eq!(res.replies_count, 0);
+        assert_eq!(res.reposts_count, 0);
+    }
+
+    // MARK: Test Users delete self
+    #[test]
+    fn test_users_delete_self() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let user = User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        };
+        ctx.database.insert_user(user.clone());
+        let session = create_session(&ctx, &user);
+
+        // Wrong current password
+        let req = Request::with_url(format!("http://localhost/users/{}", user.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("currentPassword=wrong");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Deactivate account
+        let req = Request::with_url(format!("http://localhost/users/{}", user.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("currentPassword=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // Session is revoked and profile is hidden
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+        let req = Request::with_url(format!("http://localhost/users/{}", user.id));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::NotFound);
+
+        // Login cancels deletion
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let req = Request::with_url(format!("http://localhost/users/{}", user.id));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+
+    // MARK: Test Users sessions
+    #[test]
+    fn test_users_sessions() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::SessionIndexResponse>(&res.body).unwrap();
+        assert!(!res.data.is_empty());
+    }
+
+    // MARK: Test Users posts
+    #[test]
+    fn test_users_posts() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+
+        ctx.database.insert_post(Post {
+            user_id: user.id,
+            text: "This is a test post".to_string(),
+            ..Default::default()
+        });
+
+        let req = Request::with_url(format!("http://localhost/users/{}/posts", user.id))
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert!(!res.data.is_empty());
+    }
+
+    // MARK: Test Users post filters
+    #[test]
+    fn test_users_post_filters() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+
+        ctx.database.insert_post_filter(PostFilter {
+            user_id: user.id,
+            name: "Spoilers".to_string(),
+            phrase: "spoilers".to_string(),
+            ..Default::default()
+        });
+
+        let req = Request::with_url(format!("http://localhost/users/{}/post_filters", user.id))
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::PostFilterIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 1);
     }
 
-    // MARK: Test Users change password
+    // MARK: Test Users personal access tokens
     #[test]
-    fn test_users_change_password() {
+    fn test_users_personal_access_tokens() {
         let ctx = Context::with_test_database();
         let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+
+        ctx.database
+            .insert_personal_access_token(PersonalAccessToken {
+                user_id: user.id,
+                name: "Bot".to_string(),
+                ..Default::default()
+            });
+
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/personal_access_tokens",
+            user.id
+        ))
+        .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res =
+            serde_json::from_slice::<api::PersonalAccessTokenIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 1);
+        assert_eq!(res.data[0].name, "Bot");
+
+        // Other user can't see personal access tokens
+        let (_, other_session) = create_user_session(&ctx, UserRole::Normal);
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/personal_access_tokens",
+            user.id
+        ))
+        .header("Authorization", format!("Bearer {}", other_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+    }
 
+    // MARK: Test Users security events
+    #[test]
+    fn test_users_security_events() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
         let user = User {
             username: "test".to_string(),
             email: "test@example.com".to_string(),
@@ -550,63 +2093,493 @@ mod test {
             ..Default::default()
         };
         ctx.database.insert_user(user.clone());
-        let session = Session {
-            user_id: user.id,
-            token: generate_random_token(),
-            ..Default::default()
-        };
-        ctx.database.insert_session(session.clone());
 
+        // Failed and successful login are recorded
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=wrong_password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64; rv:133.0) Gecko/20100101 Firefox/133.0",
+            )
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let token = serde_json::from_slice::<api::AuthLoginResponse>(&res.body)
+            .unwrap()
+            .token;
+
+        // Password change is recorded
         let req = Request::with_url(format!(
             "http://localhost/users/{}/change_password",
             user.id
         ))
         .method(Method::Put)
-        .header("Authorization", format!("Bearer {}", session.token))
-        .body(
-            serde_urlencoded::to_string(api::UserUpdatePasswordBody {
-                current_password: "password".to_string(),
-                password: "password123".to_string(),
-            })
-            .unwrap(),
+        .header("Authorization", format!("Bearer {}", token))
+        .body("currentPassword=password&password=new_password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/security_events",
+            user.id
+        ))
+        .header("Authorization", format!("Bearer {}", token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::SecurityEventIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 3);
+        assert_eq!(res.data[1].client_name, Some("Firefox".to_string()));
+        let types = ctx
+            .database
+            .query::<SecurityEventType>(
+                "SELECT type FROM security_events WHERE user_id = ? ORDER BY created_at",
+                user.id,
+            )
+            .collect::<Vec<_>>();
+        assert!(
+            types
+                == [
+                    SecurityEventType::LoginFailed,
+                    SecurityEventType::LoginSucceeded,
+                    SecurityEventType::PasswordChanged
+                ]
         );
+
+        // Other user can't see security events
+        let (_, other_session) = create_user_session(&ctx, UserRole::Normal);
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/security_events",
+            user.id
+        ))
+        .header("Authorization", format!("Bearer {}", other_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+    }
+
+    // MARK: Test Users OAuth grants
+    #[test]
+    fn test_users_oauth_grants() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let developer = create_user(&ctx, UserRole::Normal);
+        let oauth_app = OAuthApp {
+            user_id: developer.id,
+            name: "Client".to_string(),
+            redirect_uri: "https://client.example.com/callback".to_string(),
+            ..Default::default()
+        };
+        ctx.database.insert_oauth_app(oauth_app.clone());
+        ctx.database.insert_oauth_grant(OAuthGrant {
+            app_id: oauth_app.id,
+            user_id: user.id,
+            scopes: TokenScope::PostsRead as i64,
+            ..Default::default()
+        });
+
+        let req = Request::with_url(format!("http://localhost/users/{}/oauth_grants", user.id))
+            .header("Authorization", format!("Bearer {}", session.token));
         let res = router.handle(&req);
         assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::OAuthGrantIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 1);
+        assert_eq!(res.data[0].app.as_ref().unwrap().name, "Client");
+        assert_eq!(res.data[0].scopes, ["posts:read"]);
     }
 
-    // MARK: Test Users sessions
+    // MARK: Test Users suspend
     #[test]
-    fn test_users_sessions() {
+    fn test_users_suspend() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, admin_session) = create_user_session(&ctx, UserRole::Admin);
+
+        let user = User {
+            username: "test".to_string(),
+            email: "test@example.com".to_string(),
+            password: password_hash("password"),
+            ..Default::default()
+        };
+        ctx.database.insert_user(user.clone());
+        let session = create_session(&ctx, &user);
+        ctx.database.insert_post(Post {
+            user_id: user.id,
+            text: "This is a test post".to_string(),
+            ..Default::default()
+        });
+
+        // Normal user can't suspend
+        let req = Request::with_url(format!("http://localhost/users/{}/suspend", user.id))
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", session.token))
+            .body("reason=Spam");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Suspension can't expire in the past
+        let req = Request::with_url(format!("http://localhost/users/{}/suspend", user.id))
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", admin_session.token))
+            .body(
+                serde_urlencoded::to_string(api::UserSuspendBody {
+                    reason: "Spam".to_string(),
+                    expires_at: Some(Utc::now() - Duration::from_secs(60)),
+                })
+                .unwrap(),
+            );
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+        let report = serde_json::from_slice::<validate::Report>(&res.body).unwrap();
+        assert!(report.get_errors("expiresAt").is_some());
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // Admin suspends user permanently
+        let req = Request::with_url(format!("http://localhost/users/{}/suspend", user.id))
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", admin_session.token))
+            .body("reason=Spam");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // Session is revoked
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+
+        // Login is rejected
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+        let report = serde_json::from_slice::<validate::Report>(&res.body).unwrap();
+        assert_eq!(
+            report.get_errors("logon").unwrap().as_slice(),
+            &["Your account is permanently suspended: Spam".to_string()]
+        );
+
+        // Posts are hidden
+        let res = router.handle(&Request::with_url("http://localhost/posts"));
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 0);
+
+        // Admin lifts suspension
+        let req = Request::with_url(format!("http://localhost/users/{}/suspend", user.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", admin_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        let req = Request::with_url("http://localhost/auth/login")
+            .method(Method::Post)
+            .body("logon=test&password=password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = router.handle(&Request::with_url("http://localhost/posts"));
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 1);
+
+        // Suspension history is kept
+        let req = Request::with_url(format!("http://localhost/users/{}/suspensions", user.id))
+            .header("Authorization", format!("Bearer {}", admin_session.token));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::UserSuspensionIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 1);
+        assert!(res.data[0].lifted_at.is_some());
+    }
+
+    #[test]
+    fn test_users_suspend_role() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, moderator_session) = create_user_session(&ctx, UserRole::Moderator);
+        let (_, admin_session) = create_user_session(&ctx, UserRole::Admin);
+        let admin = create_user(&ctx, UserRole::Admin);
+        let moderator = create_user(&ctx, UserRole::Moderator);
+        let user = create_user(&ctx, UserRole::Normal);
+        let suspend = |user_id: Uuid, session_token: &str| {
+            let req = Request::with_url(format!("http://localhost/users/{}/suspend", user_id))
+                .method(Method::Post)
+                .header("Authorization", format!("Bearer {}", session_token))
+                .body("reason=Spam");
+            router.handle(&req).status
+        };
+
+        // Moderator can't suspend admins or other moderators
+        assert_eq!(
+            suspend(admin.id, &moderator_session.token),
+            Status::Forbidden
+        );
+        assert_eq!(
+            suspend(moderator.id, &moderator_session.token),
+            Status::Forbidden
+        );
+        assert!(find_active_user_suspension(&ctx, admin.id).is_none());
+        assert_eq!(suspend(user.id, &moderator_session.token), Status::Ok);
+
+        // Admin can suspend moderators, but not other admins
+        assert_eq!(suspend(moderator.id, &admin_session.token), Status::Ok);
+        assert_eq!(suspend(admin.id, &admin_session.token), Status::Forbidden);
+    }
+
+    #[test]
+    fn test_users_suspend_issuer_deleted() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (admin, admin_session) = create_user_session(&ctx, UserRole::Admin);
+        let user = create_user(&ctx, UserRole::Normal);
+
+        let req = Request::with_url(format!("http://localhost/users/{}/suspend", user.id))
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", admin_session.token))
+            .body("reason=Spam");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // Suspension stays active when the admin that issued it is deleted
+        admin.delete(&ctx);
+        let user_suspension = find_active_user_suspension(&ctx, user.id).unwrap();
+        assert_eq!(user_suspension.reason, "Spam");
+        assert!(user_suspension.suspended_by_user_id.is_none());
+    }
+
+    // MARK: Test Users impersonate
+    #[test]
+    fn test_users_impersonate() {
         let ctx = Context::with_test_database();
         let router = router(ctx.clone());
+        let (admin, admin_session) = create_user_session(&ctx, UserRole::Admin);
         let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let other_admin = create_user(&ctx, UserRole::Admin);
 
-        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+        // Normal user can't impersonate
+        let req = Request::with_url(format!("http://localhost/users/{}/impersonate", admin.id))
+            .method(Method::Post)
             .header("Authorization", format!("Bearer {}", session.token));
         let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Admin can't impersonate another admin
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/impersonate",
+            other_admin.id
+        ))
+        .method(Method::Post)
+        .header("Authorization", format!("Bearer {}", admin_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Admin impersonates user
+        let req = Request::with_url(format!("http://localhost/users/{}/impersonate", user.id))
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", admin_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::AuthLoginResponse>(&res.body).unwrap();
+        let token = res.token;
+        assert!(res.session.expires_at <= Utc::now() + IMPERSONATION_SESSION_DURATION);
+
+        // Impersonation session sees the user
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::AuthValidateResponse>(&res.body).unwrap();
+        assert_eq!(res.user.id, user.id);
+        assert_eq!(res.session.impersonator.unwrap().id, admin.id);
+
+        // But can't change the password, two factor or create tokens
+        let req = Request::with_url(format!(
+            "http://localhost/users/{}/change_password",
+            user.id
+        ))
+        .method(Method::Put)
+        .header("Authorization", format!("Bearer {}", token))
+        .body("currentPassword=password&password=new_password");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+        let req = Request::with_url("http://localhost/auth/two_factor/enroll")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+        let req = Request::with_url("http://localhost/personal_access_tokens")
+            .method(Method::Post)
+            .header("Authorization", format!("Bearer {}", token))
+            .body("name=Test&scopes=posts:read");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Or change the email address, other profile fields can be changed
+        let req = Request::with_url(format!("http://localhost/users/{}", user.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", token))
+            .body("username=test&email=other@example.com");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+        assert!(find_pending_email_verification(&ctx, user.id).is_none());
+        let req = Request::with_url(format!("http://localhost/users/{}", user.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", token))
+            .body(format!("username=test&email={}&bio=Hello", user.email));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // End impersonation
+        let req = Request::with_url("http://localhost/auth/logout")
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", token));
+        let res = router.handle(&req);
         assert_eq!(res.status, Status::Ok);
+
+        // User sees the past impersonation in the session list
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
         let res = serde_json::from_slice::<api::SessionIndexResponse>(&res.body).unwrap();
-        assert!(!res.data.is_empty());
+        assert_eq!(res.pagination.total, 2);
+        assert!(res
+            .data
+            .iter()
+            .any(|session| session.impersonator.as_ref().map(|user| user.id) == Some(admin.id)));
+
+        // Start and end are audited with the admin as actor
+        let security_events = ctx
+            .database
+            .query::<SecurityEvent>(
+                formatcp!(
+                    "SELECT {} FROM security_events WHERE user_id = ? ORDER BY created_at, id",
+                    SecurityEvent::columns()
+                ),
+                user.id,
+            )
+            .collect::<Vec<_>>();
+        assert_eq!(security_events.len(), 2);
+        assert!(security_events[0].r#type == SecurityEventType::ImpersonationStarted);
+        assert!(security_events[1].r#type == SecurityEventType::ImpersonationEnded);
+        assert!(security_events
+            .iter()
+            .all(|security_event| security_event.actor_user_id == Some(admin.id)));
+
+        // Ended impersonation is not recorded again as timed out
+        record_expired_impersonations(&ctx);
+        let count = ctx
+            .database
+            .query::<i64>(
+                "SELECT COUNT(id) FROM security_events WHERE user_id = ?",
+                user.id,
+            )
+            .next()
+            .unwrap();
+        assert_eq!(count, 2);
     }
 
-    // MARK: Test Users posts
+    // MARK: Test Users block
     #[test]
-    fn test_users_posts() {
+    fn test_users_block() {
         let ctx = Context::with_test_database();
         let router = router(ctx.clone());
         let (user, session) = create_user_session(&ctx, UserRole::Normal);
-
+        let (other_user, other_session) = create_user_session(&ctx, UserRole::Normal);
         ctx.database.insert_post(Post {
-            user_id: user.id,
+            user_id: other_user.id,
             text: "This is a test post".to_string(),
             ..Default::default()
         });
 
-        let req = Request::with_url(format!("http://localhost/users/{}/posts", user.id))
+        // Block other user
+        let req = Request::with_url(format!("http://localhost/users/{}/block", other_user.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // Blocked user should be listed
+        let req = Request::with_url(format!("http://localhost/users/{}/blocks", user.id))
             .header("Authorization", format!("Bearer {}", session.token));
         let res = router.handle(&req);
         assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::UserIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 1);
+        assert_eq!(res.data[0].id, other_user.id);
+
+        // Posts of blocked user should be hidden
+        let req = Request::with_url(format!("http://localhost/users/{}/posts", other_user.id))
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
         let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
-        assert!(!res.data.is_empty());
+        assert!(res.data.is_empty());
+
+        // Other user can't see the blocks of user
+        let req = Request::with_url(format!("http://localhost/users/{}/blocks", user.id))
+            .header("Authorization", format!("Bearer {}", other_session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Can't block yourself
+        let req = Request::with_url(format!("http://localhost/users/{}/block", user.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::BadRequest);
+
+        // Remove block
+        let req = Request::with_url(format!("http://localhost/users/{}/block", other_user.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        let req = Request::with_url(format!("http://localhost/users/{}/posts", other_user.id))
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::PostIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.data.len(), 1);
+    }
+
+    // MARK: Test Users mute
+    #[test]
+    fn test_users_mute() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (user, session) = create_user_session(&ctx, UserRole::Normal);
+        let other_user = create_user(&ctx, UserRole::Normal);
+
+        // Mute other user
+        let req = Request::with_url(format!("http://localhost/users/{}/mute", other_user.id))
+            .method(Method::Put)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        // Muted user should be listed
+        let req = Request::with_url(format!("http://localhost/users/{}/mutes", user.id))
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::UserIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 1);
+
+        // Remove mute
+        let req = Request::with_url(format!("http://localhost/users/{}/mute", other_user.id))
+            .method(Method::Delete)
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+
+        let req = Request::with_url(format!("http://localhost/users/{}/mutes", user.id))
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        let res = serde_json::from_slice::<api::UserIndexResponse>(&res.body).unwrap();
+        assert_eq!(res.pagination.total, 0);
     }
 }
@@ -4,17 +4,46 @@
  * SPDX-License-Identifier: MIT
  */
 
-use chrono::NaiveDate;
+use chrono::{NaiveDate, Utc};
 use const_format::formatcp;
-use pbkdf2::password_hash;
 
-use crate::models::{Post, Session, User, UserRole};
+use crate::controllers::auth::hash_token;
+use crate::models::{
+    EmailVerification, InviteCode, LoginAttempt, MagicLink, MagicLinkRequest, OAuthApp,
+    OAuthAuthorizationCode, OAuthGrant, OAuthToken, OidcLoginRequest, PasswordReset,
+    PersonalAccessToken, Post, PostFilter, PowChallenge, SecurityEvent, Session, TwoFactor,
+    TwoFactorChallenge, TwoFactorRecoveryCode, User, UserDeletion, UserIdentity, UserRelation,
+    UserRole, UserSuspension,
+};
+use crate::password::password_hash;
 
 // MARK: Database extension
 pub trait Extension {
     fn insert_user(&self, user: User);
     fn insert_session(&self, session: Session);
     fn insert_post(&self, post: Post);
+    fn insert_user_relation(&self, user_relation: UserRelation);
+    fn insert_post_filter(&self, post_filter: PostFilter);
+    fn insert_user_suspension(&self, user_suspension: UserSuspension);
+    fn insert_user_deletion(&self, user_deletion: UserDeletion);
+    fn insert_login_attempt(&self, login_attempt: LoginAttempt);
+    fn insert_two_factor(&self, two_factor: TwoFactor);
+    fn insert_two_factor_recovery_code(&self, two_factor_recovery_code: TwoFactorRecoveryCode);
+    fn insert_two_factor_challenge(&self, two_factor_challenge: TwoFactorChallenge);
+    fn insert_password_reset(&self, password_reset: PasswordReset);
+    fn insert_email_verification(&self, email_verification: EmailVerification);
+    fn insert_personal_access_token(&self, personal_access_token: PersonalAccessToken);
+    fn insert_oauth_app(&self, oauth_app: OAuthApp);
+    fn insert_oauth_authorization_code(&self, oauth_authorization_code: OAuthAuthorizationCode);
+    fn insert_oauth_grant(&self, oauth_grant: OAuthGrant);
+    fn insert_oauth_token(&self, oauth_token: OAuthToken);
+    fn insert_user_identity(&self, user_identity: UserIdentity);
+    fn insert_oidc_login_request(&self, oidc_login_request: OidcLoginRequest);
+    fn insert_magic_link(&self, magic_link: MagicLink);
+    fn insert_magic_link_request(&self, magic_link_request: MagicLinkRequest);
+    fn insert_security_event(&self, security_event: SecurityEvent);
+    fn insert_invite_code(&self, invite_code: InviteCode);
+    fn insert_pow_challenge(&self, pow_challenge: PowChallenge);
 }
 
 impl Extension for bsqlite::Connection {
@@ -50,10 +79,254 @@ impl Extension for bsqlite::Connection {
             post,
         );
     }
+
+    fn insert_user_relation(&self, user_relation: UserRelation) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO user_relations ({}) VALUES ({})",
+                UserRelation::columns(),
+                UserRelation::values()
+            ),
+            user_relation,
+        );
+    }
+
+    fn insert_post_filter(&self, post_filter: PostFilter) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO post_filters ({}) VALUES ({})",
+                PostFilter::columns(),
+                PostFilter::values()
+            ),
+            post_filter,
+        );
+    }
+
+    fn insert_user_suspension(&self, user_suspension: UserSuspension) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO user_suspensions ({}) VALUES ({})",
+                UserSuspension::columns(),
+                UserSuspension::values()
+            ),
+            user_suspension,
+        );
+    }
+
+    fn insert_user_deletion(&self, user_deletion: UserDeletion) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO user_deletions ({}) VALUES ({})",
+                UserDeletion::columns(),
+                UserDeletion::values()
+            ),
+            user_deletion,
+        );
+    }
+
+    fn insert_login_attempt(&self, login_attempt: LoginAttempt) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO login_attempts ({}) VALUES ({})",
+                LoginAttempt::columns(),
+                LoginAttempt::values()
+            ),
+            login_attempt,
+        );
+    }
+
+    fn insert_two_factor(&self, two_factor: TwoFactor) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO two_factors ({}) VALUES ({})",
+                TwoFactor::columns(),
+                TwoFactor::values()
+            ),
+            two_factor,
+        );
+    }
+
+    fn insert_two_factor_recovery_code(&self, two_factor_recovery_code: TwoFactorRecoveryCode) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO two_factor_recovery_codes ({}) VALUES ({})",
+                TwoFactorRecoveryCode::columns(),
+                TwoFactorRecoveryCode::values()
+            ),
+            two_factor_recovery_code,
+        );
+    }
+
+    fn insert_two_factor_challenge(&self, two_factor_challenge: TwoFactorChallenge) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO two_factor_challenges ({}) VALUES ({})",
+                TwoFactorChallenge::columns(),
+                TwoFactorChallenge::values()
+            ),
+            two_factor_challenge,
+        );
+    }
+
+    fn insert_password_reset(&self, password_reset: PasswordReset) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO password_resets ({}) VALUES ({})",
+                PasswordReset::columns(),
+                PasswordReset::values()
+            ),
+            password_reset,
+        );
+    }
+
+    fn insert_email_verification(&self, email_verification: EmailVerification) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO email_verifications ({}) VALUES ({})",
+                EmailVerification::columns(),
+                EmailVerification::values()
+            ),
+            email_verification,
+        );
+    }
+
+    fn insert_personal_access_token(&self, personal_access_token: PersonalAccessToken) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO personal_access_tokens ({}) VALUES ({})",
+                PersonalAccessToken::columns(),
+                PersonalAccessToken::values()
+            ),
+            personal_access_token,
+        );
+    }
+
+    fn insert_oauth_app(&self, oauth_app: OAuthApp) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO oauth_apps ({}) VALUES ({})",
+                OAuthApp::columns(),
+                OAuthApp::values()
+            ),
+            oauth_app,
+        );
+    }
+
+    fn insert_oauth_authorization_code(&self, oauth_authorization_code: OAuthAuthorizationCode) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO oauth_authorization_codes ({}) VALUES ({})",
+                OAuthAuthorizationCode::columns(),
+                OAuthAuthorizationCode::values()
+            ),
+            oauth_authorization_code,
+        );
+    }
+
+    fn insert_oauth_grant(&self, oauth_grant: OAuthGrant) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO oauth_grants ({}) VALUES ({})",
+                OAuthGrant::columns(),
+                OAuthGrant::values()
+            ),
+            oauth_grant,
+        );
+    }
+
+    fn insert_oauth_token(&self, oauth_token: OAuthToken) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO oauth_tokens ({}) VALUES ({})",
+                OAuthToken::columns(),
+                OAuthToken::values()
+            ),
+            oauth_token,
+        );
+    }
+
+    fn insert_user_identity(&self, user_identity: UserIdentity) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO user_identities ({}) VALUES ({})",
+                UserIdentity::columns(),
+                UserIdentity::values()
+            ),
+            user_identity,
+        );
+    }
+
+    fn insert_oidc_login_request(&self, oidc_login_request: OidcLoginRequest) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO oidc_login_requests ({}) VALUES ({})",
+                OidcLoginRequest::columns(),
+                OidcLoginRequest::values()
+            ),
+            oidc_login_request,
+        );
+    }
+
+    fn insert_magic_link(&self, magic_link: MagicLink) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO magic_links ({}) VALUES ({})",
+                MagicLink::columns(),
+                MagicLink::values()
+            ),
+            magic_link,
+        );
+    }
+
+    fn insert_magic_link_request(&self, magic_link_request: MagicLinkRequest) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO magic_link_requests ({}) VALUES ({})",
+                MagicLinkRequest::columns(),
+                MagicLinkRequest::values()
+            ),
+            magic_link_request,
+        );
+    }
+
+    fn insert_security_event(&self, security_event: SecurityEvent) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO security_events ({}) VALUES ({})",
+                SecurityEvent::columns(),
+                SecurityEvent::values()
+            ),
+            security_event,
+        );
+    }
+
+    fn insert_invite_code(&self, invite_code: InviteCode) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO invite_codes ({}) VALUES ({})",
+                InviteCode::columns(),
+                InviteCode::values()
+            ),
+            invite_code,
+        );
+    }
+
+    fn insert_pow_challenge(&self, pow_challenge: PowChallenge) {
+        self.execute(
+            formatcp!(
+                "INSERT INTO pow_challenges ({}) VALUES ({})",
+                PowChallenge::columns(),
+                PowChallenge::values()
+            ),
+            pow_challenge,
+        );
+    }
 }
 
 // MARK: Create tables
 pub fn create_tables(database: &bsqlite::Connection) {
+    // Enforce foreign keys so deletes cascade
+    database.execute("PRAGMA foreign_keys = ON", ());
     database.execute(
         "CREATE TABLE IF NOT EXISTS users (
             id BLOB PRIMARY KEY,
@@ -67,11 +340,27 @@ pub fn create_tables(database: &bsqlite::Connection) {
             location TEXT NULL,
             website TEXT NULL,
             role INTEGER NOT NULL,
+            email_verified_at INTEGER NULL,
+            invited_by_user_id BLOB NULL,
             created_at INTEGER NOT NULL,
             updated_at INTEGER NOT NULL
         )",
         (),
     );
+    // Users from before email verification are trusted as verified
+    if !column_exists(database, "users", "email_verified_at") {
+        database.execute(
+            "ALTER TABLE users ADD COLUMN email_verified_at INTEGER NULL",
+            (),
+        );
+        database.execute("UPDATE users SET email_verified_at = created_at", ());
+    }
+    if !column_exists(database, "users", "invited_by_user_id") {
+        database.execute(
+            "ALTER TABLE users ADD COLUMN invited_by_user_id BLOB NULL",
+            (),
+        );
+    }
     database.execute(
         "CREATE TABLE IF NOT EXISTS sessions (
             id BLOB PRIMARY KEY,
@@ -85,6 +374,10 @@ pub fn create_tables(database: &bsqlite::Connection) {
             client_name TEXT NULL,
             client_version TEXT NULL,
             client_os TEXT NULL,
+            remember INTEGER NOT NULL,
+            impersonator_user_id BLOB NULL,
+            last_seen_at INTEGER NOT NULL,
+            last_ip_address TEXT NOT NULL,
             expires_at INTEGER NOT NULL,
             created_at INTEGER NOT NULL,
             updated_at INTEGER NOT NULL,
@@ -92,6 +385,31 @@ pub fn create_tables(database: &bsqlite::Connection) {
         )",
         (),
     );
+    // Sessions from before last seen tracking
+    if !column_exists(database, "sessions", "last_seen_at") {
+        database.execute(
+            "ALTER TABLE sessions ADD COLUMN remember INTEGER NOT NULL DEFAULT 1",
+            (),
+        );
+        database.execute(
+            "ALTER TABLE sessions ADD COLUMN last_seen_at INTEGER NOT NULL DEFAULT 0",
+            (),
+        );
+        database.execute(
+            "ALTER TABLE sessions ADD COLUMN last_ip_address TEXT NOT NULL DEFAULT ''",
+            (),
+        );
+        database.execute(
+            "UPDATE sessions SET last_seen_at = updated_at, last_ip_address = ip_address",
+            (),
+        );
+    }
+    if !column_exists(database, "sessions", "impersonator_user_id") {
+        database.execute(
+            "ALTER TABLE sessions ADD COLUMN impersonator_user_id BLOB NULL",
+            (),
+        );
+    }
     database.execute(
         "CREATE TABLE IF NOT EXISTS posts (
             id BLOB PRIMARY KEY,
@@ -124,6 +442,416 @@ pub fn create_tables(database: &bsqlite::Connection) {
         )",
         (),
     );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS user_relations (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            target_user_id BLOB NOT NULL,
+            type INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            FOREIGN KEY (target_user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS post_filters (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            name TEXT NOT NULL,
+            phrase TEXT NOT NULL,
+            contexts INTEGER NOT NULL,
+            action INTEGER NOT NULL,
+            expires_at INTEGER NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS user_suspensions (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            suspended_by_user_id BLOB NULL,
+            reason TEXT NOT NULL,
+            expires_at INTEGER NULL,
+            lifted_at INTEGER NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            FOREIGN KEY (suspended_by_user_id) REFERENCES users(id) ON DELETE SET NULL
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS user_deletions (
+            id BLOB PRIMARY KEY,
+            user_id BLOB UNIQUE NOT NULL,
+            purge_at INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS login_attempts (
+            id BLOB PRIMARY KEY,
+            logon TEXT NOT NULL,
+            ip_address TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS two_factors (
+            id BLOB PRIMARY KEY,
+            user_id BLOB UNIQUE NOT NULL,
+            secret TEXT NOT NULL,
+            last_used_counter INTEGER NULL,
+            enabled_at INTEGER NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS two_factor_recovery_codes (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            code TEXT NOT NULL,
+            used_at INTEGER NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS two_factor_challenges (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            token TEXT UNIQUE NOT NULL,
+            remember INTEGER NOT NULL,
+            cookie INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    if !column_exists(database, "two_factor_challenges", "remember") {
+        database.execute(
+            "ALTER TABLE two_factor_challenges ADD COLUMN remember INTEGER NOT NULL DEFAULT 1",
+            (),
+        );
+    }
+    if !column_exists(database, "two_factor_challenges", "cookie") {
+        database.execute(
+            "ALTER TABLE two_factor_challenges ADD COLUMN cookie INTEGER NOT NULL DEFAULT 0",
+            (),
+        );
+    }
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS password_resets (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            token TEXT UNIQUE NOT NULL,
+            expires_at INTEGER NOT NULL,
+            used_at INTEGER NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS magic_links (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            token TEXT UNIQUE NOT NULL,
+            expires_at INTEGER NOT NULL,
+            used_at INTEGER NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS magic_link_requests (
+            id BLOB PRIMARY KEY,
+            email TEXT NOT NULL,
+            ip_address TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE INDEX IF NOT EXISTS magic_link_requests_email ON magic_link_requests (email, created_at)",
+        (),
+    );
+    database.execute(
+        "CREATE INDEX IF NOT EXISTS magic_link_requests_ip_address ON magic_link_requests (ip_address, created_at)",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS email_verifications (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            email TEXT NOT NULL,
+            token TEXT UNIQUE NOT NULL,
+            expires_at INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS personal_access_tokens (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            name TEXT NOT NULL,
+            token TEXT UNIQUE NOT NULL,
+            scopes INTEGER NOT NULL,
+            expires_at INTEGER NULL,
+            last_used_at INTEGER NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS oauth_apps (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            name TEXT NOT NULL,
+            redirect_uri TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS oauth_authorization_codes (
+            id BLOB PRIMARY KEY,
+            app_id BLOB NOT NULL,
+            user_id BLOB NOT NULL,
+            code TEXT UNIQUE NOT NULL,
+            redirect_uri TEXT NOT NULL,
+            scopes INTEGER NOT NULL,
+            code_challenge TEXT NOT NULL,
+            expires_at INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY (app_id) REFERENCES oauth_apps(id) ON DELETE CASCADE,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS oauth_grants (
+            id BLOB PRIMARY KEY,
+            app_id BLOB NOT NULL,
+            user_id BLOB NOT NULL,
+            scopes INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            UNIQUE (app_id, user_id),
+            FOREIGN KEY (app_id) REFERENCES oauth_apps(id) ON DELETE CASCADE,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS oauth_tokens (
+            id BLOB PRIMARY KEY,
+            grant_id BLOB NOT NULL,
+            app_id BLOB NOT NULL,
+            user_id BLOB NOT NULL,
+            access_token TEXT UNIQUE NOT NULL,
+            refresh_token TEXT UNIQUE NOT NULL,
+            scopes INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            refresh_expires_at INTEGER NOT NULL,
+            last_used_at INTEGER NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (grant_id) REFERENCES oauth_grants(id) ON DELETE CASCADE,
+            FOREIGN KEY (app_id) REFERENCES oauth_apps(id) ON DELETE CASCADE,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS user_identities (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            issuer TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            UNIQUE (issuer, subject),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS oidc_login_requests (
+            id BLOB PRIMARY KEY,
+            state TEXT UNIQUE NOT NULL,
+            nonce TEXT NOT NULL,
+            code_verifier TEXT NOT NULL,
+            expires_at INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS security_events (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            type INTEGER NOT NULL,
+            actor_user_id BLOB NULL,
+            ip_address TEXT NOT NULL,
+            ip_country TEXT NULL,
+            client_name TEXT NULL,
+            client_version TEXT NULL,
+            client_os TEXT NULL,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    if !column_exists(database, "security_events", "actor_user_id") {
+        database.execute(
+            "ALTER TABLE security_events ADD COLUMN actor_user_id BLOB NULL",
+            (),
+        );
+    }
+    database.execute(
+        "CREATE INDEX IF NOT EXISTS security_events_user_id ON security_events (user_id, created_at)",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS invite_codes (
+            id BLOB PRIMARY KEY,
+            user_id BLOB NOT NULL,
+            code TEXT UNIQUE NOT NULL,
+            max_uses INTEGER NOT NULL,
+            uses INTEGER NOT NULL,
+            expires_at INTEGER NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE TABLE IF NOT EXISTS pow_challenges (
+            id BLOB PRIMARY KEY,
+            challenge TEXT UNIQUE NOT NULL,
+            difficulty INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        (),
+    );
+    database.execute(
+        "CREATE INDEX IF NOT EXISTS login_attempts_logon ON login_attempts (logon, created_at)",
+        (),
+    );
+    database.execute(
+        "CREATE INDEX IF NOT EXISTS login_attempts_ip_address ON login_attempts (ip_address, created_at)",
+        (),
+    );
+    run_migrations(database);
+}
+
+// MARK: Migrations
+// There are two kinds of schema changes:
+// - New columns are added with `column_exists` checks right after the CREATE TABLE of their
+//   table, so the table definition and its upgrade path stay together. These checks are
+//   idempotent and need no version.
+// - Changes that can't be detected from the schema or must run exactly once, like rewriting
+//   data or rebuilding a table, are numbered migrations below tracked with PRAGMA user_version.
+//   They run after all tables and columns exist, add new ones with the next version number.
+fn run_migrations(database: &bsqlite::Connection) {
+    let user_version = database
+        .query::<i64>("PRAGMA user_version", ())
+        .next()
+        .expect("Should be some");
+
+    // Hash plaintext session tokens from before only token hashes were stored
+    if user_version < 1 {
+        let tokens = database
+            .query::<String>("SELECT token FROM sessions", ())
+            .collect::<Vec<_>>();
+        for token in tokens {
+            database.execute(
+                "UPDATE sessions SET token = ? WHERE token = ?",
+                (hash_token(&token), token),
+            );
+        }
+        database.execute("PRAGMA user_version = 1", ());
+    }
+
+    // Keep suspensions when the user that issued them is deleted, SQLite can't alter foreign
+    // keys so the table is rebuilt
+    if user_version < 2 {
+        let is_not_null = database
+            .query::<i64>(
+                "SELECT \"notnull\" FROM pragma_table_info('user_suspensions') WHERE name = 'suspended_by_user_id'",
+                (),
+            )
+            .next()
+            .expect("Should be some")
+            != 0;
+        if is_not_null {
+            database.execute(
+                "CREATE TABLE user_suspensions_new (
+                    id BLOB PRIMARY KEY,
+                    user_id BLOB NOT NULL,
+                    suspended_by_user_id BLOB NULL,
+                    reason TEXT NOT NULL,
+                    expires_at INTEGER NULL,
+                    lifted_at INTEGER NULL,
+                    created_at INTEGER NOT NULL,
+                    updated_at INTEGER NOT NULL,
+                    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+                    FOREIGN KEY (suspended_by_user_id) REFERENCES users(id) ON DELETE SET NULL
+                )",
+                (),
+            );
+            database.execute(
+                "INSERT INTO user_suspensions_new SELECT id, user_id, suspended_by_user_id, reason, expires_at, lifted_at, created_at, updated_at FROM user_suspensions",
+                (),
+            );
+            database.execute("DROP TABLE user_suspensions", ());
+            database.execute(
+                "ALTER TABLE user_suspensions_new RENAME TO user_suspensions",
+                (),
+            );
+        }
+        database.execute("PRAGMA user_version = 2", ());
+    }
+}
+
+// Used for adding columns in `create_tables`, see the comment above `run_migrations`
+fn column_exists(database: &bsqlite::Connection, table: &str, column: &str) -> bool {
+    database
+        .query::<i64>(
+            "SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?",
+            (table.to_string(), column.to_string()),
+        )
+        .next()
+        .expect("Should be some")
+        > 0
 }
 
 // MARK: Seed database
@@ -143,7 +871,87 @@ pub fn seed(database: &bsqlite::Connection) {
             location: Some("Gouda, Netherlands".to_string()),
             website: Some("https://www.plaatsoft.nl/".to_string()),
             role: UserRole::Admin,
+            email_verified_at: Some(Utc::now()),
+            ..Default::default()
+        });
+    }
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_migrate_session_tokens() {
+        let database = bsqlite::Connection::open_memory().unwrap();
+        create_tables(&database);
+        let user = User::default();
+        database.insert_user(user.clone());
+        database.insert_session(Session {
+            user_id: user.id,
+            token: "plain".to_string(),
+            ..Default::default()
+        });
+
+        // Tables are created again on every start, tokens are only hashed once
+        database.execute("PRAGMA user_version = 0", ());
+        create_tables(&database);
+        create_tables(&database);
+
+        let token = database
+            .query::<String>("SELECT token FROM sessions", ())
+            .next()
+            .unwrap();
+        assert_eq!(token, hash_token("plain"));
+    }
+
+    #[test]
+    fn test_migrate_user_suspensions_issuer() {
+        let database = bsqlite::Connection::open_memory().unwrap();
+        database.execute(
+            "CREATE TABLE user_suspensions (
+                id BLOB PRIMARY KEY,
+                user_id BLOB NOT NULL,
+                suspended_by_user_id BLOB NOT NULL,
+                reason TEXT NOT NULL,
+                expires_at INTEGER NULL,
+                lifted_at INTEGER NULL,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL,
+                FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+                FOREIGN KEY (suspended_by_user_id) REFERENCES users(id) ON DELETE CASCADE
+            )",
+            (),
+        );
+        create_tables(&database);
+        let admin = User {
+            username: "admin".to_string(),
+            email: "admin@example.com".to_string(),
+            ..Default::default()
+        };
+        database.insert_user(admin.clone());
+        let user = User {
+            username: "user".to_string(),
+            email: "user@example.com".to_string(),
+            ..Default::default()
+        };
+        database.insert_user(user.clone());
+        database.insert_user_suspension(UserSuspension {
+            user_id: user.id,
+            suspended_by_user_id: Some(admin.id),
             ..Default::default()
         });
+
+        // Suspension is kept when the issuer is deleted
+        database.execute("DELETE FROM users WHERE id = ?", admin.id);
+        let count = database
+            .query::<i64>(
+                "SELECT COUNT(id) FROM user_suspensions WHERE suspended_by_user_id IS NULL",
+                (),
+            )
+            .next()
+            .unwrap();
+        assert_eq!(count, 1);
     }
 }
@@ -0,0 +1,411 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use small_http::Request;
+
+use crate::settings::{GeoIpProviderType, Settings};
+
+const GEOIP_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const GEOIP_CACHE_CAPACITY: usize = 10_000;
+
+// MARK: GeoIP provider
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GeoIpInfo {
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+pub trait GeoIpProvider: Send + Sync {
+    fn lookup(&self, ip_address: IpAddr) -> Option<GeoIpInfo>;
+}
+
+pub fn geoip_provider_from_settings(settings: &Settings) -> Arc<dyn GeoIpProvider> {
+    let provider: Box<dyn GeoIpProvider> = match settings.geoip_provider {
+        GeoIpProviderType::Csv => match CsvGeoIpProvider::open(&settings.geoip_database_path) {
+            Ok(provider) => Box::new(provider),
+            Err(err) => {
+                eprintln!("Can't open GeoIP CSV database: {}", err);
+                Box::new(NoopGeoIpProvider)
+            }
+        },
+        GeoIpProviderType::Mmdb => match MmdbGeoIpProvider::open(&settings.geoip_database_path) {
+            Ok(provider) => Box::new(provider),
+            Err(err) => {
+                eprintln!("Can't open GeoIP MMDB database: {}", err);
+                Box::new(NoopGeoIpProvider)
+            }
+        },
+        GeoIpProviderType::Http => Box::new(HttpGeoIpProvider::new(
+            settings.geoip_http_url.clone(),
+            settings.geoip_http_timeout,
+        )),
+        GeoIpProviderType::None => Box::new(NoopGeoIpProvider),
+    };
+    Arc::new(CachedGeoIpProvider::new(provider))
+}
+
+fn is_public_ip_address(ip_address: IpAddr) -> bool {
+    match ip_address {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // Carrier-grade NAT 100.64.0.0/10
+                || (ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_ip_address(IpAddr::V4(ip));
+            }
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local fc00::/7 and link local fe80::/10
+                || (ip.segments()[0] & 0xfe00) == 0xfc00
+                || (ip.segments()[0] & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+// MARK: No-op provider
+pub struct NoopGeoIpProvider;
+
+impl GeoIpProvider for NoopGeoIpProvider {
+    fn lookup(&self, _: IpAddr) -> Option<GeoIpInfo> {
+        None
+    }
+}
+
+// MARK: Cached provider
+// Skips non public addresses and caches lookups of the inner provider
+pub struct CachedGeoIpProvider {
+    inner: Box<dyn GeoIpProvider>,
+    cache: Mutex<HashMap<IpAddr, (Instant, Option<GeoIpInfo>)>>,
+}
+
+impl CachedGeoIpProvider {
+    pub fn new(inner: Box<dyn GeoIpProvider>) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl GeoIpProvider for CachedGeoIpProvider {
+    fn lookup(&self, ip_address: IpAddr) -> Option<GeoIpInfo> {
+        if !is_public_ip_address(ip_address) {
+            return None;
+        }
+        if let Some((cached_at, info)) = self
+            .cache
+            .lock()
+            .expect("Can't lock cache")
+            .get(&ip_address)
+        {
+            if cached_at.elapsed() < GEOIP_CACHE_TTL {
+                return info.clone();
+            }
+        }
+
+        // Lookup without holding the lock, so a slow provider doesn't block other lookups
+        let info = self.inner.lookup(ip_address);
+        let mut cache = self.cache.lock().expect("Can't lock cache");
+        if cache.len() >= GEOIP_CACHE_CAPACITY {
+            cache.retain(|_, (cached_at, _)| cached_at.elapsed() < GEOIP_CACHE_TTL);
+            if cache.len() >= GEOIP_CACHE_CAPACITY {
+                cache.clear();
+            }
+        }
+        cache.insert(ip_address, (Instant::now(), info.clone()));
+        info
+    }
+}
+
+// MARK: CSV provider
+// Reads IP ranges from a CSV file with lines: start_ip,end_ip,country,city,latitude,longitude
+pub struct CsvGeoIpProvider {
+    ranges: Vec<(u128, u128, GeoIpInfo)>,
+}
+
+fn ip_address_to_u128(ip_address: IpAddr) -> u128 {
+    match ip_address {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().into(),
+        IpAddr::V6(ip) => ip.into(),
+    }
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value
+        .map(|value| value.trim().trim_matches('"'))
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string())
+}
+
+impl CsvGeoIpProvider {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut ranges = text
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split(',');
+                let start = non_empty(parts.next())?.parse::<IpAddr>().ok()?;
+                let end = non_empty(parts.next())?.parse::<IpAddr>().ok()?;
+                let info = GeoIpInfo {
+                    country: non_empty(parts.next()),
+                    city: non_empty(parts.next()),
+                    latitude: non_empty(parts.next()).and_then(|value| value.parse().ok()),
+                    longitude: non_empty(parts.next()).and_then(|value| value.parse().ok()),
+                };
+                Some((ip_address_to_u128(start), ip_address_to_u128(end), info))
+            })
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|(start, _, _)| *start);
+        Self { ranges }
+    }
+}
+
+impl GeoIpProvider for CsvGeoIpProvider {
+    fn lookup(&self, ip_address: IpAddr) -> Option<GeoIpInfo> {
+        let ip = ip_address_to_u128(ip_address);
+        let index = self.ranges.partition_point(|(start, _, _)| *start <= ip);
+        let (_, end, info) = self.ranges.get(index.checked_sub(1)?)?;
+        if ip <= *end {
+            Some(info.clone())
+        } else {
+            None
+        }
+    }
+}
+
+// MARK: MMDB provider
+pub struct MmdbGeoIpProvider {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl MmdbGeoIpProvider {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, maxminddb::MaxMindDBError> {
+        Ok(Self {
+            reader: maxminddb::Reader::open_readfile(path)?,
+        })
+    }
+}
+
+impl GeoIpProvider for MmdbGeoIpProvider {
+    fn lookup(&self, ip_address: IpAddr) -> Option<GeoIpInfo> {
+        let city = self
+            .reader
+            .lookup::<maxminddb::geoip2::City>(ip_address)
+            .ok()?;
+        Some(GeoIpInfo {
+            country: city
+                .country
+                .as_ref()
+                .and_then(|country| country.iso_code)
+                .map(|iso_code| iso_code.to_string()),
+            city: city
+                .city
+                .as_ref()
+                .and_then(|city| city.names.as_ref())
+                .and_then(|names| names.get("en"))
+                .map(|name| name.to_string()),
+            latitude: city
+                .location
+                .as_ref()
+                .and_then(|location| location.latitude),
+            longitude: city
+                .location
+                .as_ref()
+                .and_then(|location| location.longitude),
+        })
+    }
+}
+
+// MARK: HTTP provider
+// The HTTP client has no socket timeout, so lookups that hang keep their thread, limit how many
+// of these threads can exist at the same time
+const HTTP_GEOIP_MAX_IN_FLIGHT: usize = 4;
+
+// Fetches ipinfo.io compatible JSON, the url contains {ip} as placeholder
+pub struct HttpGeoIpProvider {
+    url: String,
+    timeout: Duration,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl HttpGeoIpProvider {
+    pub fn new(url: String, timeout: Duration) -> Self {
+        Self {
+            url,
+            timeout,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IpInfoResponse {
+    city: Option<String>,
+    country: Option<String>,
+    loc: Option<String>,
+}
+
+fn parse_ipinfo_response(body: &[u8]) -> Option<GeoIpInfo> {
+    let response = serde_json::from_slice::<IpInfoResponse>(body).ok()?;
+    let (latitude, longitude) = match response.loc.as_deref().and_then(|loc| loc.split_once(',')) {
+        Some((latitude, longitude)) => {
+            (latitude.trim().parse().ok(), longitude.trim().parse().ok())
+        }
+        None => (None, None),
+    };
+    Some(GeoIpInfo {
+        country: response.country,
+        city: response.city,
+        latitude,
+        longitude,
+    })
+}
+
+impl GeoIpProvider for HttpGeoIpProvider {
+    fn lookup(&self, ip_address: IpAddr) -> Option<GeoIpInfo> {
+        // Skip the lookup when too many earlier lookups are still hanging
+        if self.in_flight.fetch_add(1, Ordering::SeqCst) >= HTTP_GEOIP_MAX_IN_FLIGHT {
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        // Fetch on a separate thread so a slow provider can't stall the login
+        let url = self.url.replace("{ip}", &ip_address.to_string());
+        let in_flight = self.in_flight.clone();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(Request::with_url(url).fetch().ok());
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+        });
+        let res = receiver.recv_timeout(self.timeout).ok()??;
+        parse_ipinfo_response(&res.body)
+    }
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_is_public_ip_address() {
+        assert!(is_public_ip_address("8.8.8.8".parse().unwrap()));
+        assert!(is_public_ip_address("2001:4860::8888".parse().unwrap()));
+        assert!(!is_public_ip_address("127.0.0.1".parse().unwrap()));
+        assert!(!is_public_ip_address("192.168.1.1".parse().unwrap()));
+        assert!(!is_public_ip_address("100.64.0.1".parse().unwrap()));
+        assert!(!is_public_ip_address("::1".parse().unwrap()));
+        assert!(!is_public_ip_address("fd00::1".parse().unwrap()));
+        assert!(!is_public_ip_address("::ffff:10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_csv_provider() {
+        let provider = CsvGeoIpProvider::parse(
+            "1.0.0.0,1.0.0.255,AU,Brisbane,-27.4679,153.0281\n\
+             8.8.8.0,8.8.8.255,US,,37.751,-97.822\n\
+             invalid line\n\
+             2001:4860::,2001:4860:ffff:ffff:ffff:ffff:ffff:ffff,US,Mountain View,,\n",
+        );
+        assert_eq!(
+            provider.lookup("1.0.0.1".parse().unwrap()),
+            Some(GeoIpInfo {
+                country: Some("AU".to_string()),
+                city: Some("Brisbane".to_string()),
+                latitude: Some(-27.4679),
+                longitude: Some(153.0281),
+            })
+        );
+        let info = provider.lookup("8.8.8.8".parse().unwrap()).unwrap();
+        assert_eq!(info.city, None);
+        let info = provider.lookup("2001:4860::8888".parse().unwrap()).unwrap();
+        assert_eq!(info.city, Some("Mountain View".to_string()));
+        assert_eq!(info.latitude, None);
+        assert!(provider.lookup("1.0.1.0".parse().unwrap()).is_none());
+        assert!(provider.lookup("0.0.0.1".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_parse_ipinfo_response() {
+        let info =
+            parse_ipinfo_response(br#"{"city":"Gouda","country":"NL","loc":"52.0167,4.7083"}"#)
+                .unwrap();
+        assert_eq!(info.latitude, Some(52.0167));
+        assert_eq!(info.longitude, Some(4.7083));
+
+        // Malformed location doesn't panic
+        let info = parse_ipinfo_response(br#"{"country":"NL","loc":"invalid"}"#).unwrap();
+        assert_eq!(info.country, Some("NL".to_string()));
+        assert_eq!(info.latitude, None);
+        assert!(parse_ipinfo_response(b"not json").is_none());
+    }
+
+    #[test]
+    fn test_http_provider_in_flight_limit() {
+        // Server that accepts connections but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let provider = HttpGeoIpProvider::new(
+            format!("http://{}/{{ip}}/json", listener.local_addr().unwrap()),
+            Duration::from_millis(50),
+        );
+
+        for _ in 0..HTTP_GEOIP_MAX_IN_FLIGHT + 2 {
+            assert!(provider.lookup("8.8.8.8".parse().unwrap()).is_none());
+        }
+        assert_eq!(
+            provider.in_flight.load(Ordering::SeqCst),
+            HTTP_GEOIP_MAX_IN_FLIGHT
+        );
+    }
+
+    struct CountingProvider(Arc<AtomicUsize>);
+
+    impl GeoIpProvider for CountingProvider {
+        fn lookup(&self, _: IpAddr) -> Option<GeoIpInfo> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Some(GeoIpInfo::default())
+        }
+    }
+
+    #[test]
+    fn test_cached_provider() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let provider = CachedGeoIpProvider::new(Box::new(CountingProvider(lookups.clone())));
+
+        assert!(provider.lookup("8.8.8.8".parse().unwrap()).is_some());
+        assert!(provider.lookup("8.8.8.8".parse().unwrap()).is_some());
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        // Private addresses are never looked up
+        assert!(provider.lookup("127.0.0.1".parse().unwrap()).is_none());
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+    }
+}
@@ -4,34 +4,336 @@
  * SPDX-License-Identifier: MIT
  */
 
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
 use chrono::Utc;
 use const_format::formatcp;
-use small_http::{Request, Response, Status};
+use small_http::{Method, Request, Response, Status};
 
-use crate::models::{self, Session};
+use crate::controllers::auth::{generate_csrf_token, hash_token, session_cookie};
+use crate::models::login_attempt::{
+    clear_failed_logins, count_failed_logins, login_retry_after, normalize_logon,
+    record_failed_login, LOGIN_ATTEMPTS_BEFORE_POW,
+};
+use crate::models::oauth::{find_valid_oauth_access_token, OAUTH_ACCESS_TOKEN_PREFIX};
+use crate::models::personal_access_token::{
+    find_valid_personal_access_token, PERSONAL_ACCESS_TOKEN_PREFIX,
+};
+use crate::models::security_event::record_security_event;
+use crate::models::two_factor::find_enabled_two_factor;
+use crate::models::user_deletion::find_user_deletion;
+use crate::models::user_suspension::find_active_user_suspension;
+use crate::models::{self, SecurityEventType, Session, TokenScope};
+use crate::password::{password_verify, password_verify_dummy};
 use crate::Context;
 
-// MARK: Auth optional
-pub fn auth_optional_pre_layer(req: &Request, ctx: &mut Context) -> Option<Response> {
-    // Get token from Authorization header
-    let authorization = req
+// MARK: Request credentials
+#[derive(Debug, PartialEq, Eq)]
+enum Credentials {
+    Bearer(String),
+    Basic { logon: String, password: String },
+    Cookie(String),
+}
+
+// Parses an Authorization header value, none when it is malformed or uses an unknown scheme
+fn parse_authorization(authorization: &str) -> Option<Credentials> {
+    let (scheme, param) = authorization.trim().split_once(' ')?;
+    let param = param.trim();
+    if param.is_empty() || param.contains(' ') {
+        return None;
+    }
+    if scheme.eq_ignore_ascii_case("Bearer") {
+        Some(Credentials::Bearer(param.to_string()))
+    } else if scheme.eq_ignore_ascii_case("Basic") {
+        let decoded = String::from_utf8(BASE64.decode(param).ok()?).ok()?;
+        let (logon, password) = decoded.split_once(':')?;
+        if logon.is_empty() {
+            return None;
+        }
+        Some(Credentials::Basic {
+            logon: logon.to_string(),
+            password: password.to_string(),
+        })
+    } else {
+        None
+    }
+}
+
+// Get credentials from Authorization header, or else from the session cookie of the web client
+fn request_credentials(req: &Request) -> Result<Option<Credentials>, Response> {
+    if let Some(authorization) = req
         .headers
         .get("Authorization")
-        .or(req.headers.get("authorization"))?;
-    let token = authorization[7..].trim().to_string();
+        .or(req.headers.get("authorization"))
+    {
+        return match parse_authorization(authorization) {
+            Some(credentials) => Ok(Some(credentials)),
+            None => Err(Response::new()
+                .status(Status::BadRequest)
+                .header("WWW-Authenticate", WWW_AUTHENTICATE)
+                .body("400 Bad Request: malformed Authorization header")),
+        };
+    }
+    Ok(session_cookie(req).map(Credentials::Cookie))
+}
+
+const WWW_AUTHENTICATE: &str = "Bearer realm=\"PlaatBook\", Basic realm=\"PlaatBook\"";
 
-    // Get active session by token
+fn unauthorized() -> Response {
+    Response::new()
+        .status(Status::Unauthorized)
+        .header("WWW-Authenticate", WWW_AUTHENTICATE)
+        .body("401 Unauthorized")
+}
+
+// Cookies are sent by the browser automatically, so mutating requests must also send the CSRF token
+fn is_valid_csrf_token(req: &Request, token: &str) -> bool {
+    if matches!(req.method, Method::Get | Method::Options) {
+        return true;
+    }
+    req.headers
+        .get("X-CSRF-Token")
+        .or(req.headers.get("x-csrf-token"))
+        .is_some_and(|csrf_token| constant_time_eq(csrf_token, &generate_csrf_token(token)))
+}
+
+// Compares without returning early on the first different byte, so the token can't be guessed
+// byte by byte from the response time
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// MARK: Scoped tokens
+// Personal access tokens and OAuth access tokens only grant access to the routes of their scopes
+fn is_scoped_token(token: &str) -> bool {
+    token.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX) || token.starts_with(OAUTH_ACCESS_TOKEN_PREFIX)
+}
+
+// Returns the scope a scoped token needs for the request, none when these tokens can't be used
+fn required_token_scope(req: &Request) -> Option<TokenScope> {
+    let path = req.url.path();
+
+    // Tokens can't be used for login, two factor or to manage tokens and apps
+    if path.starts_with("/auth/")
+        || path.starts_with("/oauth/")
+        || path.starts_with("/personal_access_tokens")
+        || path.ends_with("/personal_access_tokens")
+        || path.ends_with("/oauth_apps")
+        || path.ends_with("/oauth_grants")
+    {
+        return None;
+    }
+
+    if path == "/posts"
+        || path.starts_with("/posts/")
+        || (path.starts_with("/users/") && path.ends_with("/posts"))
+    {
+        if req.method == Method::Get {
+            Some(TokenScope::PostsRead)
+        } else {
+            Some(TokenScope::PostsWrite)
+        }
+    } else {
+        Some(TokenScope::Account)
+    }
+}
+
+fn scoped_token_auth(req: &Request, ctx: &mut Context, token: &str) -> Option<Response> {
+    // Get not expired token by token hash and record its use
+    let user_id_and_scopes = if token.starts_with(PERSONAL_ACCESS_TOKEN_PREFIX) {
+        find_valid_personal_access_token(ctx, token).map(|mut personal_access_token| {
+            personal_access_token.touch(ctx);
+            (personal_access_token.user_id, personal_access_token.scopes)
+        })
+    } else {
+        find_valid_oauth_access_token(ctx, token).map(|mut oauth_token| {
+            oauth_token.touch(ctx);
+            (oauth_token.user_id, oauth_token.scopes)
+        })
+    };
+    let (user_id, scopes) = match user_id_and_scopes {
+        Some(user_id_and_scopes) => user_id_and_scopes,
+        None => return Some(unauthorized()),
+    };
+
+    // Check if token has the scope for this route
+    match required_token_scope(req) {
+        Some(scope) if scopes & scope as i64 != 0 => {}
+        Some(scope) => {
+            return Some(
+                Response::new()
+                    .status(Status::Forbidden)
+                    .body(format!("403 Forbidden: missing {} scope", scope.name())),
+            );
+        }
+        None => {
+            return Some(
+                Response::new()
+                    .status(Status::Forbidden)
+                    .body("403 Forbidden: this route can't be used with a scoped token"),
+            );
+        }
+    }
+
+    // Tokens keep working after the user logged out, so check the account is still active
+    if find_active_user_suspension(ctx, user_id).is_some() {
+        return Some(
+            Response::new()
+                .status(Status::Forbidden)
+                .body("403 Forbidden: account is suspended"),
+        );
+    }
+    if find_user_deletion(ctx, user_id).is_some() {
+        return Some(
+            Response::new()
+                .status(Status::Forbidden)
+                .body("403 Forbidden: account is pending deletion"),
+        );
+    }
+
+    // Get user by token user_id
+    ctx.auth_user = ctx
+        .database
+        .query::<models::User>(
+            formatcp!(
+                "SELECT {} FROM users WHERE id = ? LIMIT 1",
+                models::User::columns()
+            ),
+            user_id,
+        )
+        .next();
+
+    None
+}
+
+// MARK: Basic auth
+// Username or email and password credentials for simple scripts, they don't create a session
+fn basic_auth(req: &Request, ctx: &mut Context, logon: &str, password: &str) -> Option<Response> {
+    // Basic credentials have no session so can't be used on the session and token routes
+    if required_token_scope(req).is_none() {
+        return Some(
+            Response::new()
+                .status(Status::Forbidden)
+                .body("403 Forbidden: this route can't be used with Basic credentials"),
+        );
+    }
+
+    // Check if logon or ip address is locked out
+    let normalized_logon = normalize_logon(logon);
+    let ip_address = req.client_addr.ip().to_string();
+    if let Some(retry_after) = login_retry_after(ctx, &normalized_logon, &ip_address) {
+        return Some(
+            Response::new()
+                .status(Status::TooManyRequests)
+                .header("Retry-After", retry_after.to_string())
+                .body("429 Too Many Requests"),
+        );
+    }
+
+    // Basic credentials can't carry a proof of work, so they are refused after repeated failures
+    if count_failed_logins(ctx, &normalized_logon, &ip_address) >= LOGIN_ATTEMPTS_BEFORE_POW {
+        return Some(
+            Response::new()
+                .status(Status::TooManyRequests)
+                .body("429 Too Many Requests: proof of work required, login to continue"),
+        );
+    }
+
+    // Find user by username or email and check password
+    let user = ctx
+        .database
+        .query::<models::User>(
+            formatcp!(
+                "SELECT {} FROM users WHERE username = ? OR email = ? LIMIT 1",
+                models::User::columns()
+            ),
+            (logon.to_string(), logon.to_string()),
+        )
+        .next();
+
+    // Basic credentials would bypass the second factor, so they are refused before the password
+    // is checked to not reveal whether it was correct
+    if let Some(user) = &user {
+        if find_enabled_two_factor(ctx, user.id).is_some() {
+            password_verify_dummy(password);
+            return Some(unauthorized());
+        }
+    }
+    let user = match user {
+        Some(user) if password_verify(password, &user.password) => user,
+        None => {
+            password_verify_dummy(password);
+            record_failed_login(ctx, &normalized_logon, &ip_address);
+            return Some(unauthorized());
+        }
+        Some(user) => {
+            record_failed_login(ctx, &normalized_logon, &ip_address);
+            record_security_event(req, ctx, user.id, SecurityEventType::LoginFailed);
+            return Some(unauthorized());
+        }
+    };
+
+    if find_active_user_suspension(ctx, user.id).is_some() {
+        return Some(
+            Response::new()
+                .status(Status::Forbidden)
+                .body("403 Forbidden: account is suspended"),
+        );
+    }
+
+    // Basic credentials are a login, so they cancel a pending account deletion as well
+    clear_failed_logins(ctx, &[&normalized_logon]);
+    ctx.database
+        .execute("DELETE FROM user_deletions WHERE user_id = ?", user.id);
+    ctx.auth_user = Some(user);
+    None
+}
+
+// MARK: Auth optional
+pub fn auth_optional_pre_layer(req: &Request, ctx: &mut Context) -> Option<Response> {
+    // Requests with malformed credentials continue as guest
+    let token = match request_credentials(req).ok()?? {
+        Credentials::Bearer(token) => {
+            // Requests with an invalid or insufficient scoped token continue as guest
+            if is_scoped_token(&token) {
+                _ = scoped_token_auth(req, ctx, &token);
+                return None;
+            }
+            token
+        }
+        // Basic credentials are only checked by the auth required layer, to not verify the
+        // password twice per request
+        Credentials::Basic { .. } => return None,
+        // Cookie requests without a valid CSRF token continue as guest
+        Credentials::Cookie(token) => {
+            if !is_valid_csrf_token(req, &token) {
+                return None;
+            }
+            token
+        }
+    };
+
+    // Get active session by token hash
     let session = ctx
         .database
         .query::<models::Session>(
             formatcp!(
-                "SELECT {} FROM sessions WHERE token = ? AND expires_at > ? LIMIT 1",
+                "SELECT {} FROM sessions WHERE token = ? AND expires_at > ? AND last_seen_at > ? LIMIT 1",
                 Session::columns()
             ),
-            (token, Utc::now()),
+            (
+                hash_token(&token),
+                Utc::now(),
+                Utc::now() - ctx.settings.session_idle_timeout,
+            ),
         )
         .next();
-    let session = session?;
+    let mut session = session?;
+    session.touch(ctx, &req.client_addr.ip().to_string());
 
     // Get user by session user_id
     ctx.auth_user = ctx
@@ -51,44 +353,52 @@ pub fn auth_optional_pre_layer(req: &Request, ctx: &mut Context) -> Option<Respo
 
 // MARK: Auth required
 pub fn auth_required_pre_layer(req: &Request, ctx: &mut Context) -> Option<Response> {
-    // Get token from Authorization header
-    let authorization = match req
-        .headers
-        .get("Authorization")
-        .or(req.headers.get("authorization"))
-    {
-        Some(authorization) => authorization,
-        None => {
-            return Some(
-                Response::new()
-                    .status(Status::Unauthorized)
-                    .body("401 Unauthorized"),
-            );
+    let token = match request_credentials(req) {
+        Ok(Some(Credentials::Bearer(token))) => {
+            if is_scoped_token(&token) {
+                return scoped_token_auth(req, ctx, &token);
+            }
+            token
         }
+        Ok(Some(Credentials::Basic { logon, password })) => {
+            return basic_auth(req, ctx, &logon, &password);
+        }
+        Ok(Some(Credentials::Cookie(token))) => {
+            if !is_valid_csrf_token(req, &token) {
+                return Some(
+                    Response::new()
+                        .status(Status::Forbidden)
+                        .body("403 Forbidden: invalid CSRF token"),
+                );
+            }
+            token
+        }
+        Ok(None) => return Some(unauthorized()),
+        Err(res) => return Some(res),
     };
-    let token = authorization[7..].trim().to_string();
 
-    // Get active session by token
+    // Get active session by token hash
     let session = ctx
         .database
         .query::<models::Session>(
             formatcp!(
-                "SELECT {} FROM sessions WHERE token = ? AND expires_at > ? LIMIT 1",
+                "SELECT {} FROM sessions WHERE token = ? AND expires_at > ? AND last_seen_at > ? LIMIT 1",
                 Session::columns()
             ),
-            (token, Utc::now()),
+            (
+                hash_token(&token),
+                Utc::now(),
+                Utc::now() - ctx.settings.session_idle_timeout,
+            ),
         )
         .next();
-    let session = match session {
+    let mut session = match session {
         Some(session) => session,
         None => {
-            return Some(
-                Response::new()
-                    .status(Status::Unauthorized)
-                    .body("401 Unauthorized"),
-            );
+            return Some(unauthorized());
         }
     };
+    session.touch(ctx, &req.client_addr.ip().to_string());
 
     // Get user by session user_id
     ctx.auth_user = ctx
@@ -110,9 +420,13 @@ pub fn auth_required_pre_layer(req: &Request, ctx: &mut Context) -> Option<Respo
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::models::UserRole;
+    use crate::api;
+    use crate::database::Extension;
+    use crate::models::session::SESSION_LAST_SEEN_UPDATE_INTERVAL;
+    use crate::models::{PersonalAccessToken, TwoFactor, UserDeletion, UserRole, UserSuspension};
+    use crate::password::password_hash;
     use crate::router;
-    use crate::test_utils::create_user_session;
+    use crate::test_utils::{create_user, create_user_session};
 
     #[test]
     fn test_unauthed() {
@@ -121,6 +435,50 @@ mod test {
 
         let res = router.handle(&Request::with_url("http://localhost/auth/validate"));
         assert_eq!(res.status, Status::Unauthorized);
+        assert_eq!(
+            res.headers.get("WWW-Authenticate"),
+            Some(&WWW_AUTHENTICATE.to_string())
+        );
+    }
+
+    #[test]
+    fn test_session_idle_timeout() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, session) = create_user_session(&ctx, UserRole::Normal);
+
+        // Session that wasn't used for longer than the idle timeout is rejected
+        ctx.database.execute(
+            "UPDATE sessions SET last_seen_at = ? WHERE id = ?",
+            (Utc::now() - ctx.settings.session_idle_timeout, session.id),
+        );
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+    }
+
+    #[test]
+    fn test_session_last_seen() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, session) = create_user_session(&ctx, UserRole::Normal);
+        let last_seen_at = Utc::now() - SESSION_LAST_SEEN_UPDATE_INTERVAL * 2;
+        ctx.database.execute(
+            "UPDATE sessions SET last_seen_at = ? WHERE id = ?",
+            (last_seen_at, session.id),
+        );
+
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", format!("Bearer {}", session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::AuthValidateResponse>(&res.body).unwrap();
+        assert!(res.session.last_seen_at > last_seen_at);
+        assert_eq!(
+            res.session.last_ip_address,
+            req.client_addr.ip().to_string()
+        );
     }
 
     #[test]
@@ -137,4 +495,320 @@ mod test {
         let res = router.handle(&req);
         assert_eq!(res.status, Status::Ok);
     }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("token", "token"));
+        assert!(!constant_time_eq("token", "tokem"));
+        assert!(!constant_time_eq("token", "token2"));
+        assert!(constant_time_eq("", ""));
+    }
+
+    #[test]
+    fn test_parse_authorization() {
+        assert_eq!(
+            parse_authorization("Bearer pbs_token"),
+            Some(Credentials::Bearer("pbs_token".to_string()))
+        );
+        assert_eq!(
+            parse_authorization("bearer   pbs_token "),
+            Some(Credentials::Bearer("pbs_token".to_string()))
+        );
+        assert_eq!(
+            parse_authorization(&format!("BASIC {}", BASE64.encode("test:pass:word"))),
+            Some(Credentials::Basic {
+                logon: "test".to_string(),
+                password: "pass:word".to_string(),
+            })
+        );
+
+        // Malformed headers
+        assert_eq!(parse_authorization(""), None);
+        assert_eq!(parse_authorization("Bearer"), None);
+        assert_eq!(parse_authorization("Bearer "), None);
+        assert_eq!(parse_authorization("Bearer a b"), None);
+        assert_eq!(parse_authorization("Token12 pbs_token"), None);
+        assert_eq!(parse_authorization("Basic !!!"), None);
+        assert_eq!(
+            parse_authorization(&format!("Basic {}", BASE64.encode("nocolon"))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_malformed_authorization() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+
+        // Short headers don't crash the worker
+        for authorization in ["", "abc", "Bearer", "Basic ???"] {
+            let req = Request::with_url("http://localhost/auth/validate")
+                .header("Authorization", authorization);
+            let res = router.handle(&req);
+            assert_eq!(res.status, Status::BadRequest);
+            assert!(res.headers.get("WWW-Authenticate").is_some());
+        }
+
+        // Guest routes ignore malformed headers
+        let req = Request::with_url("http://localhost/posts").header("Authorization", "abc");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let user = create_user(&ctx, UserRole::Normal);
+        ctx.database.execute(
+            "UPDATE users SET password = ? WHERE id = ?",
+            (password_hash("password"), user.id),
+        );
+        let basic = |logon: &str, password: &str| {
+            format!("Basic {}", BASE64.encode(format!("{}:{}", logon, password)))
+        };
+
+        // Username or email with password is accepted
+        for logon in [&user.username, &user.email] {
+            let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+                .header("Authorization", basic(logon, "password"));
+            let res = router.handle(&req);
+            assert_eq!(res.status, Status::Ok);
+        }
+
+        // Wrong password is rejected with a challenge
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .header("Authorization", basic(&user.username, "wrong"));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+        assert_eq!(
+            res.headers.get("WWW-Authenticate"),
+            Some(&WWW_AUTHENTICATE.to_string())
+        );
+
+        // Basic credentials can't be used on auth routes
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", basic(&user.username, "password"));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Basic credentials cancel a pending account deletion like a login
+        ctx.database.insert_user_deletion(UserDeletion {
+            user_id: user.id,
+            ..Default::default()
+        });
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .header("Authorization", basic(&user.username, "password"));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        assert!(find_user_deletion(&ctx, user.id).is_none());
+    }
+
+    #[test]
+    fn test_basic_auth_pow_threshold() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let user = create_user(&ctx, UserRole::Normal);
+        ctx.database.execute(
+            "UPDATE users SET password = ? WHERE id = ?",
+            (password_hash("password"), user.id),
+        );
+        let basic = |password: &str| {
+            format!(
+                "Basic {}",
+                BASE64.encode(format!("{}:{}", user.username, password))
+            )
+        };
+
+        // First failures are rejected with a challenge
+        for _ in 0..LOGIN_ATTEMPTS_BEFORE_POW {
+            let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+                .header("Authorization", basic("wrong"));
+            let res = router.handle(&req);
+            assert_eq!(res.status, Status::Unauthorized);
+        }
+
+        // After that even the correct password is refused, because no proof of work can be sent
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .header("Authorization", basic("password"));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::TooManyRequests);
+    }
+
+    #[test]
+    fn test_basic_auth_two_factor() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let user = create_user(&ctx, UserRole::Normal);
+        ctx.database.execute(
+            "UPDATE users SET password = ? WHERE id = ?",
+            (password_hash("password"), user.id),
+        );
+        ctx.database.insert_two_factor(TwoFactor {
+            user_id: user.id,
+            enabled_at: Some(Utc::now()),
+            ..Default::default()
+        });
+        ctx.database.insert_user_suspension(UserSuspension {
+            user_id: user.id,
+            ..Default::default()
+        });
+
+        // Correct and wrong passwords get the same response, even for a suspended account
+        for password in ["password", "wrong"] {
+            let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+                .header(
+                    "Authorization",
+                    format!(
+                        "Basic {}",
+                        BASE64.encode(format!("{}:{}", user.username, password))
+                    ),
+                );
+            let res = router.handle(&req);
+            assert_eq!(res.status, Status::Unauthorized);
+        }
+    }
+
+    #[test]
+    fn test_session_cookie() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let (_, session) = create_user_session(&ctx, UserRole::Normal);
+        let cookie = format!("theme=dark; plaatbook_session={}", session.token);
+
+        // Cookie is accepted for reading requests
+        let req = Request::with_url("http://localhost/auth/validate").header("Cookie", &cookie);
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let res = serde_json::from_slice::<api::AuthValidateResponse>(&res.body).unwrap();
+        assert_eq!(res.csrf_token, Some(generate_csrf_token(&session.token)));
+
+        // Mutating requests without CSRF token are rejected
+        let req = Request::with_url("http://localhost/auth/logout")
+            .method(Method::Put)
+            .header("Cookie", &cookie);
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+        let req = Request::with_url("http://localhost/auth/logout")
+            .method(Method::Put)
+            .header("Cookie", &cookie)
+            .header("X-CSRF-Token", "wrong_token");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Mutating requests with CSRF token are accepted
+        let req = Request::with_url("http://localhost/auth/logout")
+            .method(Method::Put)
+            .header("Cookie", &cookie)
+            .header("X-CSRF-Token", generate_csrf_token(&session.token));
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        assert!(res
+            .headers
+            .get("Set-Cookie")
+            .unwrap()
+            .starts_with("plaatbook_session=;"));
+    }
+
+    #[test]
+    fn test_personal_access_token_scopes() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let user = create_user(&ctx, UserRole::Normal);
+        ctx.database
+            .insert_personal_access_token(PersonalAccessToken {
+                user_id: user.id,
+                token: hash_token("pbp_test"),
+                scopes: TokenScope::PostsRead as i64,
+                ..Default::default()
+            });
+
+        // Token with posts:read scope can read posts
+        let req = Request::with_url(format!("http://localhost/users/{}/posts", user.id))
+            .header("Authorization", "Bearer pbp_test");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Ok);
+        let used_count = ctx
+            .database
+            .query::<i64>(
+                "SELECT COUNT(id) FROM personal_access_tokens WHERE last_used_at IS NOT NULL",
+                (),
+            )
+            .next()
+            .unwrap();
+        assert_eq!(used_count, 1);
+
+        // But can't create posts or access account routes
+        let req = Request::with_url("http://localhost/posts")
+            .method(Method::Post)
+            .header("Authorization", "Bearer pbp_test")
+            .body("text=Hello");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .header("Authorization", "Bearer pbp_test");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Tokens can never be used on auth routes
+        let req = Request::with_url("http://localhost/auth/validate")
+            .header("Authorization", "Bearer pbp_test");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+    }
+
+    #[test]
+    fn test_personal_access_token_inactive_user() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let admin = create_user(&ctx, UserRole::Admin);
+        let user = create_user(&ctx, UserRole::Normal);
+        ctx.database
+            .insert_personal_access_token(PersonalAccessToken {
+                user_id: user.id,
+                token: hash_token("pbp_test"),
+                scopes: TokenScope::PostsRead as i64,
+                ..Default::default()
+            });
+        let req = Request::with_url(format!("http://localhost/users/{}/posts", user.id))
+            .header("Authorization", "Bearer pbp_test");
+
+        // Token of suspended user is rejected
+        ctx.database.insert_user_suspension(UserSuspension {
+            user_id: user.id,
+            suspended_by_user_id: Some(admin.id),
+            ..Default::default()
+        });
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+
+        // Token of user pending deletion is rejected
+        ctx.database
+            .execute("DELETE FROM user_suspensions WHERE user_id = ?", user.id);
+        ctx.database.insert_user_deletion(UserDeletion {
+            user_id: user.id,
+            ..Default::default()
+        });
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Forbidden);
+    }
+
+    #[test]
+    fn test_personal_access_token_expired() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+        let user = create_user(&ctx, UserRole::Normal);
+        ctx.database
+            .insert_personal_access_token(PersonalAccessToken {
+                user_id: user.id,
+                token: hash_token("pbp_test"),
+                expires_at: Some(Utc::now()),
+                ..Default::default()
+            });
+
+        let req = Request::with_url(format!("http://localhost/users/{}/sessions", user.id))
+            .header("Authorization", "Bearer pbp_test");
+        let res = router.handle(&req);
+        assert_eq!(res.status, Status::Unauthorized);
+    }
 }
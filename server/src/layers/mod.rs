@@ -26,10 +26,21 @@ pub fn cors_pre_layer(req: &Request, ctx: &mut Context) -> Option<Response> {
     }
 }
 
-pub fn cors_post_layer(_: &Request, _: &mut Context, res: Response) -> Response {
-    res.header("Access-Control-Allow-Origin", "*")
-        .header("Access-Control-Allow-Methods", "GET, POST, PUT, DELETE")
-        .header("Access-Control-Allow-Headers", "Authorization")
+pub fn cors_post_layer(req: &Request, ctx: &mut Context, res: Response) -> Response {
+    // The web client sends the session cookie, which requires an exact origin instead of a wildcard
+    let origin = req.headers.get("Origin").or(req.headers.get("origin"));
+    let res = if origin.is_some_and(|origin| *origin == ctx.settings.app_url) {
+        res.header("Access-Control-Allow-Origin", &ctx.settings.app_url)
+            .header("Access-Control-Allow-Credentials", "true")
+            .header("Vary", "Origin")
+    } else {
+        res.header("Access-Control-Allow-Origin", "*")
+    };
+    res.header("Access-Control-Allow-Methods", "GET, POST, PUT, DELETE")
+        .header(
+            "Access-Control-Allow-Headers",
+            "Authorization, X-CSRF-Token",
+        )
         .header("Access-Control-Max-Age", "86400")
 }
 
@@ -68,11 +79,39 @@ mod test {
         );
         assert_eq!(
             res.headers.get("Access-Control-Allow-Headers"),
-            Some(&"Authorization".to_string())
+            Some(&"Authorization, X-CSRF-Token".to_string())
         );
         assert_eq!(
             res.headers.get("Access-Control-Max-Age"),
             Some(&"86400".to_string())
         );
     }
+
+    #[test]
+    fn test_cors_credentials() {
+        let ctx = Context::with_test_database();
+        let router = router(ctx.clone());
+
+        // App origin may send credentials
+        let req = Request::with_url("http://localhost/").header("Origin", &ctx.settings.app_url);
+        let res = router.handle(&req);
+        assert_eq!(
+            res.headers.get("Access-Control-Allow-Origin"),
+            Some(&ctx.settings.app_url)
+        );
+        assert_eq!(
+            res.headers.get("Access-Control-Allow-Credentials"),
+            Some(&"true".to_string())
+        );
+
+        // Other origins get the wildcard without credentials
+        let req =
+            Request::with_url("http://localhost/").header("Origin", "https://evil.example.com");
+        let res = router.handle(&req);
+        assert_eq!(
+            res.headers.get("Access-Control-Allow-Origin"),
+            Some(&"*".to_string())
+        );
+        assert_eq!(res.headers.get("Access-Control-Allow-Credentials"), None);
+    }
 }
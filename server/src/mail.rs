@@ -0,0 +1,229 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use uuid::Uuid;
+
+// MARK: Mail
+#[derive(Clone, Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Mail {
+    fn to_message(&self, from: &str) -> String {
+        format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nMessage-ID: <{}@plaatbook>\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            from,
+            self.to,
+            self.subject,
+            Uuid::now_v7(),
+            self.body.replace("\r\n", "\n").replace('\n', "\r\n")
+        )
+    }
+}
+
+pub trait MailTransport: Send + Sync {
+    fn send(&self, mail: &Mail) -> io::Result<()>;
+}
+
+pub fn mail_transport_from_env() -> Arc<dyn MailTransport> {
+    match std::env::var("SMTP_HOST") {
+        Ok(host) => Arc::new(SmtpTransport {
+            host,
+            port: std::env::var("SMTP_PORT")
+                .ok()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(25),
+            username: std::env::var("SMTP_USERNAME").ok(),
+            password: std::env::var("SMTP_PASSWORD").ok(),
+            from: mail_from_env(),
+        }),
+        Err(_) => Arc::new(OutboxTransport::with_dir("outbox")),
+    }
+}
+
+fn mail_from_env() -> String {
+    std::env::var("MAIL_FROM").unwrap_or_else(|_| "PlaatBook <noreply@plaatsoft.nl>".to_string())
+}
+
+// MARK: SMTP transport
+pub struct SmtpTransport {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+}
+
+impl SmtpTransport {
+    fn command(
+        reader: &mut BufReader<TcpStream>,
+        command: Option<&str>,
+        expected_code: &str,
+    ) -> io::Result<()> {
+        if let Some(command) = command {
+            reader
+                .get_mut()
+                .write_all(format!("{}\r\n", command).as_bytes())?;
+        }
+        // Read possible multiline reply, the last line has a space after the code
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "SMTP connection closed",
+                ));
+            }
+            if !line.starts_with(expected_code) {
+                return Err(io::Error::other(format!(
+                    "Unexpected SMTP reply: {}",
+                    line.trim_end()
+                )));
+            }
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl MailTransport for SmtpTransport {
+    fn send(&self, mail: &Mail) -> io::Result<()> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(30)))?;
+        let mut reader = BufReader::new(stream);
+
+        Self::command(&mut reader, None, "220")?;
+        Self::command(&mut reader, Some("EHLO plaatbook"), "250")?;
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            let credentials = BASE64.encode(format!("\0{}\0{}", username, password));
+            Self::command(
+                &mut reader,
+                Some(&format!("AUTH PLAIN {}", credentials)),
+                "235",
+            )?;
+        }
+        let from_address = extract_address(&self.from);
+        Self::command(
+            &mut reader,
+            Some(&format!("MAIL FROM:<{}>", from_address)),
+            "250",
+        )?;
+        Self::command(
+            &mut reader,
+            Some(&format!("RCPT TO:<{}>", extract_address(&mail.to))),
+            "250",
+        )?;
+        Self::command(&mut reader, Some("DATA"), "354")?;
+
+        // Escape lines starting with a dot
+        let message = mail.to_message(&self.from).replace("\r\n.", "\r\n..");
+        Self::command(&mut reader, Some(&format!("{}.", message)), "250")?;
+        Self::command(&mut reader, Some("QUIT"), "221")
+    }
+}
+
+fn extract_address(mailbox: &str) -> &str {
+    match (mailbox.find('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox.trim(),
+    }
+}
+
+// MARK: Outbox transport
+// Keeps sent mails in memory and optionally writes them to a directory, for development and tests
+#[derive(Default)]
+pub struct OutboxTransport {
+    dir: Option<PathBuf>,
+    mails: Mutex<Vec<Mail>>,
+}
+
+impl OutboxTransport {
+    pub fn with_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+            mails: Mutex::new(Vec::new()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn mails(&self) -> Vec<Mail> {
+        self.mails.lock().expect("Can't lock outbox").clone()
+    }
+}
+
+impl MailTransport for OutboxTransport {
+    fn send(&self, mail: &Mail) -> io::Result<()> {
+        if let Some(dir) = &self.dir {
+            fs::create_dir_all(dir)?;
+            fs::write(
+                dir.join(format!("{}.eml", Uuid::now_v7())),
+                mail.to_message(&mail_from_env()),
+            )?;
+        }
+        self.mails
+            .lock()
+            .expect("Can't lock outbox")
+            .push(mail.clone());
+        Ok(())
+    }
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_outbox_transport() {
+        let outbox = OutboxTransport::default();
+        outbox
+            .send(&Mail {
+                to: "test@example.com".to_string(),
+                subject: "Hello".to_string(),
+                body: "Hello world".to_string(),
+            })
+            .unwrap();
+        let mails = outbox.mails();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].subject, "Hello");
+    }
+
+    #[test]
+    fn test_mail_to_message() {
+        let message = Mail {
+            to: "test@example.com".to_string(),
+            subject: "Hello".to_string(),
+            body: "Line 1\nLine 2".to_string(),
+        }
+        .to_message("PlaatBook <noreply@plaatsoft.nl>");
+        assert!(message
+            .starts_with("From: PlaatBook <noreply@plaatsoft.nl>\r\nTo: test@example.com\r\n"));
+        assert!(message.ends_with("\r\n\r\nLine 1\r\nLine 2\r\n"));
+    }
+
+    #[test]
+    fn test_extract_address() {
+        assert_eq!(
+            extract_address("PlaatBook <noreply@plaatsoft.nl>"),
+            "noreply@plaatsoft.nl"
+        );
+        assert_eq!(extract_address("test@example.com"), "test@example.com");
+    }
+}
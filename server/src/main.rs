@@ -6,39 +6,76 @@
 
 use std::net::{Ipv4Addr, TcpListener};
 use std::path::Path;
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use std::time::Duration;
 
 use bsqlite::Connection;
 use simple_useragent::UserAgentParser;
 use small_router::{Router, RouterBuilder};
 
-use crate::controllers::auth::{auth_login, auth_logout, auth_validate};
+use crate::controllers::auth::{
+    auth_forgot_password, auth_login, auth_login_two_factor, auth_logout, auth_magic_link,
+    auth_magic_link_login, auth_pow_challenge, auth_reset_password, auth_validate,
+    auth_verify_email,
+};
+use crate::controllers::invite_codes::{invite_codes_create, invite_codes_delete};
+use crate::controllers::oauth::{
+    oauth_apps_create, oauth_apps_delete, oauth_authorize, oauth_authorize_approve,
+    oauth_grants_delete, oauth_revoke, oauth_token,
+};
+use crate::controllers::oidc::{auth_oidc_authorize, auth_oidc_callback};
+use crate::controllers::personal_access_tokens::{
+    personal_access_tokens_create, personal_access_tokens_delete,
+};
+use crate::controllers::post_filters::{
+    post_filters_create, post_filters_delete, post_filters_update,
+};
 use crate::controllers::posts::{
     posts_create, posts_create_reply, posts_delete, posts_dislike, posts_dislike_delete,
     posts_index, posts_like, posts_like_delete, posts_replies, posts_repost, posts_show,
     posts_update,
 };
 use crate::controllers::sessions::{sessions_index, sessions_revoke, sessions_show};
+use crate::controllers::two_factor::{
+    auth_two_factor_confirm, auth_two_factor_disable, auth_two_factor_enroll,
+};
 use crate::controllers::users::{
-    users_change_password, users_create, users_index, users_posts, users_sessions, users_show,
-    users_update,
+    users_block, users_block_delete, users_blocks, users_change_password, users_change_role,
+    users_create, users_delete, users_impersonate, users_index, users_invite_codes,
+    users_lockout_delete, users_mute, users_mute_delete, users_mutes, users_oauth_apps,
+    users_oauth_grants, users_personal_access_tokens, users_post_filters, users_posts,
+    users_resend_email_verification, users_security_events, users_sessions, users_sessions_revoke,
+    users_show, users_suspend, users_suspend_delete, users_suspensions, users_update,
 };
 use crate::controllers::{home, not_found};
+use crate::geoip::GeoIpProvider;
 use crate::layers::{
     auth_optional_pre_layer, auth_required_pre_layer, cors_post_layer, cors_pre_layer,
     log_pre_layer,
 };
+use crate::mail::MailTransport;
+use crate::models::session::record_expired_impersonations;
+use crate::models::user_deletion::purge_user_deletions;
 use crate::models::{Session, User};
+use crate::settings::Settings;
 
 mod api {
     include!(concat!(env!("OUT_DIR"), "/api.rs"));
 }
 mod controllers;
 mod database;
+mod geoip;
 mod layers;
+mod mail;
 mod models;
+mod oidc;
+mod password;
+mod permissions;
+mod settings;
 #[cfg(test)]
 mod test_utils;
+mod totp;
 
 // MARK: Context
 static USER_AGENT_PARSER: LazyLock<UserAgentParser> = LazyLock::new(UserAgentParser::new);
@@ -48,6 +85,9 @@ pub(crate) struct Context {
     database: Connection,
     auth_user: Option<User>,
     auth_session: Option<Session>,
+    mailer: Arc<dyn MailTransport>,
+    geoip: Arc<dyn GeoIpProvider>,
+    settings: Settings,
 }
 
 impl Context {
@@ -57,10 +97,14 @@ impl Context {
         database.apply_various_performance_settings();
         database::create_tables(&database);
         database::seed(&database);
+        let settings = Settings::from_env();
         Self {
             database,
             auth_user: None,
             auth_session: None,
+            mailer: mail::mail_transport_from_env(),
+            geoip: geoip::geoip_provider_from_settings(&settings),
+            settings,
         }
     }
 
@@ -72,6 +116,13 @@ impl Context {
             database,
             auth_user: None,
             auth_session: None,
+            mailer: Arc::new(mail::OutboxTransport::default()),
+            geoip: Arc::new(geoip::NoopGeoIpProvider),
+            // Tests skip the proof of work, its own tests set a difficulty
+            settings: Settings {
+                pow_difficulty: 0,
+                ..Settings::default()
+            },
         }
     }
 }
@@ -87,6 +138,18 @@ pub(crate) fn router(ctx: Context) -> Router<Context> {
         .get("/", home)
         // Auth
         .post("/auth/login", auth_login)
+        .post("/auth/pow_challenge", auth_pow_challenge)
+        .post("/auth/login/two_factor", auth_login_two_factor)
+        .post("/auth/forgot_password", auth_forgot_password)
+        .post("/auth/reset_password", auth_reset_password)
+        .post("/auth/magic_link", auth_magic_link)
+        .post("/auth/magic_link/login", auth_magic_link_login)
+        .post("/auth/verify_email", auth_verify_email)
+        .get("/auth/oidc/authorize", auth_oidc_authorize)
+        .post("/auth/oidc/callback", auth_oidc_callback)
+        // OAuth
+        .post("/oauth/token", oauth_token)
+        .post("/oauth/revoke", oauth_revoke)
         // Posts
         .get("/posts", posts_index)
         .get("/posts/:post_id", posts_show)
@@ -104,6 +167,9 @@ pub(crate) fn router(ctx: Context) -> Router<Context> {
         // Auth
         .get("/auth/validate", auth_validate)
         .put("/auth/logout", auth_logout)
+        .post("/auth/two_factor/enroll", auth_two_factor_enroll)
+        .post("/auth/two_factor/confirm", auth_two_factor_confirm)
+        .post("/auth/two_factor/disable", auth_two_factor_disable)
         // Posts
         .post("/posts", posts_create)
         .put("/posts/:post_id", posts_update)
@@ -117,8 +183,54 @@ pub(crate) fn router(ctx: Context) -> Router<Context> {
         // Users
         .get("/users", users_index)
         .put("/users/:user_id", users_update)
+        .delete("/users/:user_id", users_delete)
         .put("/users/:user_id/change_password", users_change_password)
+        .put("/users/:user_id/role", users_change_role)
+        .post(
+            "/users/:user_id/resend_email_verification",
+            users_resend_email_verification,
+        )
         .get("/users/:user_id/sessions", users_sessions)
+        .delete("/users/:user_id/sessions", users_sessions_revoke)
+        .get("/users/:user_id/blocks", users_blocks)
+        .put("/users/:user_id/block", users_block)
+        .delete("/users/:user_id/block", users_block_delete)
+        .get("/users/:user_id/mutes", users_mutes)
+        .put("/users/:user_id/mute", users_mute)
+        .delete("/users/:user_id/mute", users_mute_delete)
+        .get("/users/:user_id/post_filters", users_post_filters)
+        .get("/users/:user_id/invite_codes", users_invite_codes)
+        .get(
+            "/users/:user_id/personal_access_tokens",
+            users_personal_access_tokens,
+        )
+        .get("/users/:user_id/oauth_apps", users_oauth_apps)
+        .get("/users/:user_id/oauth_grants", users_oauth_grants)
+        .get("/users/:user_id/security_events", users_security_events)
+        .get("/users/:user_id/suspensions", users_suspensions)
+        .post("/users/:user_id/suspend", users_suspend)
+        .delete("/users/:user_id/suspend", users_suspend_delete)
+        .delete("/users/:user_id/lockout", users_lockout_delete)
+        .post("/users/:user_id/impersonate", users_impersonate)
+        // Invite codes
+        .post("/invite_codes", invite_codes_create)
+        .delete("/invite_codes/:invite_code_id", invite_codes_delete)
+        // Post filters
+        .post("/post_filters", post_filters_create)
+        .put("/post_filters/:post_filter_id", post_filters_update)
+        .delete("/post_filters/:post_filter_id", post_filters_delete)
+        // Personal access tokens
+        .post("/personal_access_tokens", personal_access_tokens_create)
+        .delete(
+            "/personal_access_tokens/:personal_access_token_id",
+            personal_access_tokens_delete,
+        )
+        // OAuth
+        .get("/oauth/authorize", oauth_authorize)
+        .post("/oauth/authorize", oauth_authorize_approve)
+        .post("/oauth/apps", oauth_apps_create)
+        .delete("/oauth/apps/:oauth_app_id", oauth_apps_delete)
+        .delete("/oauth/grants/:oauth_grant_id", oauth_grants_delete)
         // Sessions
         .get("/sessions", sessions_index)
         .get("/sessions/:session_id", sessions_show)
@@ -131,9 +243,17 @@ fn main() {
     println!("Starting PlaatBook server...");
 
     // Init database and user agent parser
-    let router = router(Context::with_database("database.db"));
+    let ctx = Context::with_database("database.db");
+    let router = router(ctx.clone());
     let _ = &*USER_AGENT_PARSER;
 
+    // Start background purge of deleted users and audit of timed out impersonations
+    thread::spawn(move || loop {
+        purge_user_deletions(&ctx);
+        record_expired_impersonations(&ctx);
+        thread::sleep(Duration::from_secs(60 * 60));
+    });
+
     // Start server
     const HTTP_PORT: u16 = 8080;
     println!("Server is listening on: http://localhost:{}/", HTTP_PORT);
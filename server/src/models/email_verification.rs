@@ -0,0 +1,123 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::time::Duration;
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use super::User;
+use crate::controllers::auth::{generate_url_token, hash_token};
+use crate::database::Extension;
+use crate::mail::Mail;
+use crate::Context;
+
+pub const EMAIL_VERIFICATION_EXPIRE_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+// MARK: Email verification
+#[derive(Clone, FromRow)]
+pub struct EmailVerification {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub email: String,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for EmailVerification {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            email: "".to_string(),
+            token: "".to_string(),
+            expires_at: now + EMAIL_VERIFICATION_EXPIRE_DURATION,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+// Finds the latest email verification of the user, the email of it is the pending email
+pub fn find_pending_email_verification(ctx: &Context, user_id: Uuid) -> Option<EmailVerification> {
+    ctx.database
+        .query::<EmailVerification>(
+            formatcp!(
+                "SELECT {} FROM email_verifications WHERE user_id = ? ORDER BY created_at DESC LIMIT 1",
+                EmailVerification::columns()
+            ),
+            user_id,
+        )
+        .next()
+}
+
+// Finds a not expired email verification by its plain token
+pub fn find_valid_email_verification(ctx: &Context, token: &str) -> Option<EmailVerification> {
+    ctx.database
+        .query::<EmailVerification>(
+            formatcp!(
+                "SELECT {} FROM email_verifications WHERE token = ? AND expires_at > ? LIMIT 1",
+                EmailVerification::columns()
+            ),
+            (hash_token(token), Utc::now()),
+        )
+        .next()
+}
+
+// Replaces the pending email verification of the user and mails a new verification link
+pub fn send_email_verification(ctx: &Context, user: &User, email: &str) {
+    ctx.database
+        .execute("DELETE FROM email_verifications WHERE user_id = ?", user.id);
+    let token = generate_url_token();
+    ctx.database.insert_email_verification(EmailVerification {
+        user_id: user.id,
+        email: email.to_string(),
+        token: hash_token(&token),
+        ..Default::default()
+    });
+
+    let mail = Mail {
+        to: email.to_string(),
+        subject: "Verify your PlaatBook email address".to_string(),
+        body: format!(
+            "Hi {},\n\nPlease confirm this email address for your PlaatBook account by opening the link below:\n\n{}/auth/verify_email?token={}\n\nThis link expires in 24 hours. If you didn't request this, you can ignore this email.\n",
+            user.username, ctx.settings.app_url, token
+        ),
+    };
+    if let Err(err) = ctx.mailer.send(&mail) {
+        eprintln!("Can't send email verification mail: {}", err);
+    }
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_send_email_verification_replaces_pending() {
+        let ctx = Context::with_test_database();
+        let user = User::default();
+        ctx.database.insert_user(user.clone());
+
+        send_email_verification(&ctx, &user, "first@example.com");
+        send_email_verification(&ctx, &user, "second@example.com");
+
+        let pending = find_pending_email_verification(&ctx, user.id).unwrap();
+        assert_eq!(pending.email, "second@example.com");
+        let count = ctx
+            .database
+            .query::<i64>("SELECT COUNT(id) FROM email_verifications", ())
+            .next()
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,157 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use crate::{api, Context};
+
+// Without ambiguous characters so codes can be shared by hand
+const INVITE_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const INVITE_CODE_LENGTH: usize = 12;
+
+// MARK: Invite code
+#[derive(Clone, FromRow)]
+pub struct InviteCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub code: String,
+    pub max_uses: i64,
+    pub uses: i64,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for InviteCode {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            code: generate_invite_code(),
+            max_uses: 1,
+            uses: 0,
+            expires_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+impl From<InviteCode> for api::InviteCode {
+    fn from(invite_code: InviteCode) -> Self {
+        Self {
+            id: invite_code.id,
+            code: invite_code.code,
+            max_uses: invite_code.max_uses,
+            uses: invite_code.uses,
+            expires_at: invite_code.expires_at,
+            created_at: invite_code.created_at,
+            updated_at: invite_code.updated_at,
+        }
+    }
+}
+
+pub fn generate_invite_code() -> String {
+    let mut code_bytes = [0u8; INVITE_CODE_LENGTH];
+    getrandom::fill(&mut code_bytes).expect("Can't get random bytes");
+    code_bytes
+        .iter()
+        .map(|byte| INVITE_CODE_ALPHABET[*byte as usize % INVITE_CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+// MARK: Usage
+pub const INVITE_CODE_USABLE_CONDITION: &str =
+    "uses < max_uses AND (expires_at IS NULL OR expires_at > ?)";
+
+pub fn find_usable_invite_code(ctx: &Context, code: &str) -> Option<InviteCode> {
+    ctx.database
+        .query::<InviteCode>(
+            formatcp!(
+                "SELECT {} FROM invite_codes WHERE code = ? AND {} LIMIT 1",
+                InviteCode::columns(),
+                INVITE_CODE_USABLE_CONDITION
+            ),
+            (code.trim().to_uppercase(), Utc::now()),
+        )
+        .next()
+}
+
+// Users may only have a limited number of remaining invite uses at the same time, so the uses of
+// all usable codes are counted instead of the codes
+pub fn count_remaining_invite_uses(ctx: &Context, user_id: Uuid) -> i64 {
+    ctx.database
+        .query::<i64>(
+            formatcp!(
+                "SELECT COALESCE(SUM(max_uses - uses), 0) FROM invite_codes WHERE user_id = ? AND {}",
+                INVITE_CODE_USABLE_CONDITION
+            ),
+            (user_id, Utc::now()),
+        )
+        .next()
+        .expect("Should be some")
+}
+
+impl InviteCode {
+    pub fn record_use(&mut self, ctx: &Context) {
+        self.uses += 1;
+        self.updated_at = Utc::now();
+        ctx.database.execute(
+            "UPDATE invite_codes SET uses = uses + 1, updated_at = ? WHERE id = ?",
+            (self.updated_at, self.id),
+        );
+    }
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Extension;
+    use crate::models::UserRole;
+    use crate::test_utils::create_user;
+
+    #[test]
+    fn test_generate_invite_code() {
+        let code = generate_invite_code();
+        assert_eq!(code.len(), INVITE_CODE_LENGTH);
+        assert!(code.bytes().all(|c| INVITE_CODE_ALPHABET.contains(&c)));
+        assert_ne!(code, generate_invite_code());
+    }
+
+    #[test]
+    fn test_find_usable_invite_code() {
+        let ctx = Context::with_test_database();
+        let user = create_user(&ctx, UserRole::Admin);
+        let invite_code = InviteCode {
+            user_id: user.id,
+            ..Default::default()
+        };
+        ctx.database.insert_invite_code(invite_code.clone());
+
+        // Codes are matched case insensitive
+        let mut found = find_usable_invite_code(&ctx, &invite_code.code.to_lowercase()).unwrap();
+        assert_eq!(count_remaining_invite_uses(&ctx, user.id), 1);
+
+        // Used up codes can't be used
+        found.record_use(&ctx);
+        assert!(find_usable_invite_code(&ctx, &invite_code.code).is_none());
+        assert_eq!(count_remaining_invite_uses(&ctx, user.id), 0);
+
+        // Expired codes can't be used
+        let expired_invite_code = InviteCode {
+            user_id: user.id,
+            expires_at: Some(Utc::now()),
+            ..Default::default()
+        };
+        ctx.database.insert_invite_code(expired_invite_code.clone());
+        assert!(find_usable_invite_code(&ctx, &expired_invite_code.code).is_none());
+    }
+}
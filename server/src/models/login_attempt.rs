@@ -0,0 +1,161 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::time::Duration;
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::database::Extension;
+use crate::Context;
+
+// MARK: Login attempt
+pub const LOGIN_ATTEMPTS_WINDOW: Duration = Duration::from_secs(15 * 60);
+pub const LOGIN_ATTEMPTS_PER_LOGON: i64 = 5;
+pub const LOGIN_ATTEMPTS_PER_IP_ADDRESS: i64 = 20;
+// Logins need a proof of work after this many failures, before the lockout kicks in
+pub const LOGIN_ATTEMPTS_BEFORE_POW: i64 = 2;
+
+#[derive(Clone, FromRow)]
+pub struct LoginAttempt {
+    pub id: Uuid,
+    pub logon: String,
+    pub ip_address: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Default for LoginAttempt {
+    fn default() -> Self {
+        Self {
+            id: Uuid::now_v7(),
+            logon: "".to_string(),
+            ip_address: "".to_string(),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+// MARK: Lockout
+// Logons are stored lowercased so changing the case doesn't give extra attempts
+pub fn normalize_logon(logon: &str) -> String {
+    logon.trim().to_lowercase()
+}
+
+fn locked_until(
+    ctx: &Context,
+    column: &str,
+    value: &str,
+    max_attempts: i64,
+) -> Option<DateTime<Utc>> {
+    // When the oldest failure that still counts leaves the window the lockout is over
+    ctx.database
+        .query::<DateTime<Utc>>(
+            &format!(
+                "SELECT created_at FROM login_attempts WHERE {} = ? AND created_at > ? ORDER BY created_at DESC LIMIT 1 OFFSET ?",
+                column
+            ),
+            (value.to_string(), Utc::now() - LOGIN_ATTEMPTS_WINDOW, max_attempts - 1),
+        )
+        .next()
+        .map(|created_at| created_at + LOGIN_ATTEMPTS_WINDOW)
+}
+
+pub fn login_retry_after(ctx: &Context, logon: &str, ip_address: &str) -> Option<i64> {
+    let logon_locked_until = locked_until(ctx, "logon", logon, LOGIN_ATTEMPTS_PER_LOGON);
+    let ip_address_locked_until =
+        locked_until(ctx, "ip_address", ip_address, LOGIN_ATTEMPTS_PER_IP_ADDRESS);
+    logon_locked_until
+        .max(ip_address_locked_until)
+        .map(|locked_until| (locked_until.timestamp() - Utc::now().timestamp()).max(1))
+}
+
+pub fn count_failed_logins(ctx: &Context, logon: &str, ip_address: &str) -> i64 {
+    ctx.database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM login_attempts WHERE (logon = ? OR ip_address = ?) AND created_at > ?",
+            (
+                logon.to_string(),
+                ip_address.to_string(),
+                Utc::now() - LOGIN_ATTEMPTS_WINDOW,
+            ),
+        )
+        .next()
+        .expect("Should be some")
+}
+
+pub fn record_failed_login(ctx: &Context, logon: &str, ip_address: &str) {
+    ctx.database.execute(
+        "DELETE FROM login_attempts WHERE created_at <= ?",
+        Utc::now() - LOGIN_ATTEMPTS_WINDOW,
+    );
+    ctx.database.insert_login_attempt(LoginAttempt {
+        logon: logon.to_string(),
+        ip_address: ip_address.to_string(),
+        ..Default::default()
+    });
+}
+
+// Logons must be normalized with `normalize_logon`, the same as when they are recorded
+pub fn clear_failed_logins(ctx: &Context, logons: &[&str]) {
+    for logon in logons {
+        ctx.database.execute(
+            "DELETE FROM login_attempts WHERE logon = ?",
+            logon.to_string(),
+        );
+    }
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_login_retry_after_logon() {
+        let ctx = Context::with_test_database();
+        for _ in 0..LOGIN_ATTEMPTS_PER_LOGON - 1 {
+            record_failed_login(&ctx, "test", "127.0.0.1");
+        }
+        assert!(login_retry_after(&ctx, "test", "127.0.0.1").is_none());
+
+        record_failed_login(&ctx, "test", "127.0.0.1");
+        let retry_after = login_retry_after(&ctx, "test", "127.0.0.1").unwrap();
+        assert!(retry_after > 0 && retry_after <= LOGIN_ATTEMPTS_WINDOW.as_secs() as i64);
+        assert!(login_retry_after(&ctx, "other", "127.0.0.2").is_none());
+
+        clear_failed_logins(&ctx, &[&normalize_logon("TEST")]);
+        assert!(login_retry_after(&ctx, "test", "127.0.0.1").is_none());
+    }
+
+    #[test]
+    fn test_login_retry_after_ip_address() {
+        let ctx = Context::with_test_database();
+        for i in 0..LOGIN_ATTEMPTS_PER_IP_ADDRESS {
+            record_failed_login(&ctx, &format!("user{}", i), "127.0.0.1");
+        }
+        assert!(login_retry_after(&ctx, "new", "127.0.0.1").is_some());
+        assert!(login_retry_after(&ctx, "new", "127.0.0.2").is_none());
+    }
+
+    #[test]
+    fn test_record_failed_login_prunes_old_attempts() {
+        let ctx = Context::with_test_database();
+        ctx.database.insert_login_attempt(LoginAttempt {
+            logon: "test".to_string(),
+            ip_address: "127.0.0.1".to_string(),
+            created_at: Utc::now() - LOGIN_ATTEMPTS_WINDOW,
+            ..Default::default()
+        });
+        record_failed_login(&ctx, "test", "127.0.0.1");
+        let count = ctx
+            .database
+            .query::<i64>("SELECT COUNT(id) FROM login_attempts", ())
+            .next()
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
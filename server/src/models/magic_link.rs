@@ -0,0 +1,173 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::time::Duration;
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use crate::controllers::auth::hash_token;
+use crate::database::Extension;
+use crate::Context;
+
+pub const MAGIC_LINK_EXPIRE_DURATION: Duration = Duration::from_secs(15 * 60);
+pub const MAGIC_LINK_REQUESTS_WINDOW: Duration = Duration::from_secs(15 * 60);
+pub const MAGIC_LINK_REQUESTS_PER_EMAIL: i64 = 3;
+pub const MAGIC_LINK_REQUESTS_PER_IP_ADDRESS: i64 = 10;
+
+// MARK: Magic link
+#[derive(Clone, FromRow)]
+pub struct MagicLink {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for MagicLink {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            token: "".to_string(),
+            expires_at: now + MAGIC_LINK_EXPIRE_DURATION,
+            used_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+// Finds an unused and not expired magic link by its plain token
+pub fn find_valid_magic_link(ctx: &Context, token: &str) -> Option<MagicLink> {
+    ctx.database
+        .query::<MagicLink>(
+            formatcp!(
+                "SELECT {} FROM magic_links WHERE token = ? AND used_at IS NULL AND expires_at > ? LIMIT 1",
+                MagicLink::columns()
+            ),
+            (hash_token(token), Utc::now()),
+        )
+        .next()
+}
+
+// MARK: Magic link request
+// Every request is recorded, also for unknown emails, so rate limiting doesn't reveal which accounts exist
+#[derive(Clone, FromRow)]
+pub struct MagicLinkRequest {
+    pub id: Uuid,
+    pub email: String,
+    pub ip_address: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Default for MagicLinkRequest {
+    fn default() -> Self {
+        Self {
+            id: Uuid::now_v7(),
+            email: "".to_string(),
+            ip_address: "".to_string(),
+            created_at: Utc::now(),
+        }
+    }
+}
+
+fn limited_until(
+    ctx: &Context,
+    column: &str,
+    value: &str,
+    max_requests: i64,
+) -> Option<DateTime<Utc>> {
+    ctx.database
+        .query::<DateTime<Utc>>(
+            &format!(
+                "SELECT created_at FROM magic_link_requests WHERE {} = ? AND created_at > ? ORDER BY created_at DESC LIMIT 1 OFFSET ?",
+                column
+            ),
+            (value.to_string(), Utc::now() - MAGIC_LINK_REQUESTS_WINDOW, max_requests - 1),
+        )
+        .next()
+        .map(|created_at| created_at + MAGIC_LINK_REQUESTS_WINDOW)
+}
+
+pub fn magic_link_retry_after(ctx: &Context, email: &str, ip_address: &str) -> Option<i64> {
+    let email_limited_until = limited_until(ctx, "email", email, MAGIC_LINK_REQUESTS_PER_EMAIL);
+    let ip_address_limited_until = limited_until(
+        ctx,
+        "ip_address",
+        ip_address,
+        MAGIC_LINK_REQUESTS_PER_IP_ADDRESS,
+    );
+    email_limited_until
+        .max(ip_address_limited_until)
+        .map(|limited_until| (limited_until.timestamp() - Utc::now().timestamp()).max(1))
+}
+
+pub fn record_magic_link_request(ctx: &Context, email: &str, ip_address: &str) {
+    ctx.database.execute(
+        "DELETE FROM magic_link_requests WHERE created_at <= ?",
+        Utc::now() - MAGIC_LINK_REQUESTS_WINDOW,
+    );
+    ctx.database.insert_magic_link_request(MagicLinkRequest {
+        email: email.to_string(),
+        ip_address: ip_address.to_string(),
+        ..Default::default()
+    });
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::User;
+
+    #[test]
+    fn test_find_valid_magic_link() {
+        let ctx = Context::with_test_database();
+        let user = User::default();
+        ctx.database.insert_user(user.clone());
+
+        ctx.database.insert_magic_link(MagicLink {
+            user_id: user.id,
+            token: hash_token("valid"),
+            ..Default::default()
+        });
+        ctx.database.insert_magic_link(MagicLink {
+            user_id: user.id,
+            token: hash_token("expired"),
+            expires_at: Utc::now(),
+            ..Default::default()
+        });
+        ctx.database.insert_magic_link(MagicLink {
+            user_id: user.id,
+            token: hash_token("used"),
+            used_at: Some(Utc::now()),
+            ..Default::default()
+        });
+
+        assert!(find_valid_magic_link(&ctx, "valid").is_some());
+        assert!(find_valid_magic_link(&ctx, "expired").is_none());
+        assert!(find_valid_magic_link(&ctx, "used").is_none());
+        assert!(find_valid_magic_link(&ctx, "unknown").is_none());
+    }
+
+    #[test]
+    fn test_magic_link_retry_after() {
+        let ctx = Context::with_test_database();
+        for i in 0..MAGIC_LINK_REQUESTS_PER_EMAIL {
+            assert!(magic_link_retry_after(&ctx, "test@example.com", "127.0.0.1").is_none());
+            record_magic_link_request(&ctx, "test@example.com", &format!("127.0.0.{}", i));
+        }
+        assert!(magic_link_retry_after(&ctx, "test@example.com", "127.0.0.100").is_some());
+        assert!(magic_link_retry_after(&ctx, "other@example.com", "127.0.0.100").is_none());
+    }
+}
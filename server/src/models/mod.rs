@@ -7,15 +7,47 @@
 use serde::Deserialize;
 use validate::Validate;
 
+pub use self::email_verification::EmailVerification;
+pub use self::invite_code::InviteCode;
+pub use self::login_attempt::LoginAttempt;
+pub use self::magic_link::{MagicLink, MagicLinkRequest};
+pub use self::oauth::{OAuthApp, OAuthAuthorizationCode, OAuthGrant, OAuthToken};
+pub use self::oidc_login_request::OidcLoginRequest;
+pub use self::password_reset::PasswordReset;
+pub use self::personal_access_token::{PersonalAccessToken, TokenScope};
 pub use self::post::{Post, PostType};
+pub use self::post_filter::{PostFilter, PostFilterAction, PostFilterContext};
 pub use self::post_interaction::{PostInteraction, PostInteractionType};
+pub use self::pow_challenge::PowChallenge;
+pub use self::security_event::{SecurityEvent, SecurityEventType};
 pub use self::session::Session;
+pub use self::two_factor::{TwoFactor, TwoFactorChallenge, TwoFactorRecoveryCode};
 pub use self::user::{User, UserRole};
+pub use self::user_deletion::UserDeletion;
+pub use self::user_identity::UserIdentity;
+pub use self::user_relation::{UserRelation, UserRelationType};
+pub use self::user_suspension::UserSuspension;
 
+pub mod email_verification;
+pub mod invite_code;
+pub mod login_attempt;
+pub mod magic_link;
+pub mod oauth;
+pub mod oidc_login_request;
+pub mod password_reset;
+pub mod personal_access_token;
 pub mod post;
+pub mod post_filter;
 pub mod post_interaction;
+pub mod pow_challenge;
+pub mod security_event;
 pub mod session;
+pub mod two_factor;
 pub mod user;
+pub mod user_deletion;
+pub mod user_identity;
+pub mod user_relation;
+pub mod user_suspension;
 
 // MARK: Index query
 #[derive(Deserialize, Validate)]
@@ -0,0 +1,359 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::time::Duration;
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use super::TokenScope;
+use crate::controllers::auth::{generate_code_challenge, generate_url_token, hash_token};
+use crate::database::Extension;
+use crate::{api, Context};
+
+pub const OAUTH_AUTHORIZATION_CODE_EXPIRE_DURATION: Duration = Duration::from_secs(10 * 60);
+pub const OAUTH_ACCESS_TOKEN_EXPIRE_DURATION: Duration = Duration::from_secs(60 * 60);
+pub const OAUTH_REFRESH_TOKEN_EXPIRE_DURATION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+// Only write last used info once per interval to avoid a write on every request
+pub const OAUTH_TOKEN_LAST_USED_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+// Recognizable prefixes so the auth layer and secret scanners can tell tokens apart
+pub const OAUTH_ACCESS_TOKEN_PREFIX: &str = "pbo_";
+pub const OAUTH_REFRESH_TOKEN_PREFIX: &str = "pbr_";
+
+// MARK: OAuth app
+#[derive(Clone, FromRow)]
+pub struct OAuthApp {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub redirect_uri: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for OAuthApp {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            name: "".to_string(),
+            redirect_uri: "".to_string(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+impl From<OAuthApp> for api::OAuthApp {
+    fn from(oauth_app: OAuthApp) -> Self {
+        Self {
+            id: oauth_app.id,
+            name: oauth_app.name,
+            redirect_uri: oauth_app.redirect_uri,
+            created_at: oauth_app.created_at,
+            updated_at: oauth_app.updated_at,
+        }
+    }
+}
+
+pub fn find_oauth_app(ctx: &Context, id: Uuid) -> Option<OAuthApp> {
+    ctx.database
+        .query::<OAuthApp>(
+            formatcp!(
+                "SELECT {} FROM oauth_apps WHERE id = ? LIMIT 1",
+                OAuthApp::columns()
+            ),
+            id,
+        )
+        .next()
+}
+
+// MARK: OAuth authorization code
+#[derive(Clone, FromRow)]
+pub struct OAuthAuthorizationCode {
+    pub id: Uuid,
+    pub app_id: Uuid,
+    pub user_id: Uuid,
+    pub code: String,
+    pub redirect_uri: String,
+    pub scopes: i64,
+    pub code_challenge: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Default for OAuthAuthorizationCode {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            app_id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            code: "".to_string(),
+            redirect_uri: "".to_string(),
+            scopes: 0,
+            code_challenge: "".to_string(),
+            expires_at: now + OAUTH_AUTHORIZATION_CODE_EXPIRE_DURATION,
+            created_at: now,
+        }
+    }
+}
+
+impl OAuthAuthorizationCode {
+    // Checks the PKCE code verifier against the S256 code challenge
+    pub fn verify_code_verifier(&self, code_verifier: &str) -> bool {
+        generate_code_challenge(code_verifier) == self.code_challenge
+    }
+}
+
+// Finds a not expired authorization code by its plain code and deletes it so it can only be used once
+pub fn take_oauth_authorization_code(ctx: &Context, code: &str) -> Option<OAuthAuthorizationCode> {
+    let authorization_code = ctx
+        .database
+        .query::<OAuthAuthorizationCode>(
+            formatcp!(
+                "SELECT {} FROM oauth_authorization_codes WHERE code = ? AND expires_at > ? LIMIT 1",
+                OAuthAuthorizationCode::columns()
+            ),
+            (hash_token(code), Utc::now()),
+        )
+        .next()?;
+    ctx.database.execute(
+        "DELETE FROM oauth_authorization_codes WHERE id = ?",
+        authorization_code.id,
+    );
+    Some(authorization_code)
+}
+
+// MARK: OAuth grant
+#[derive(Clone, FromRow)]
+pub struct OAuthGrant {
+    pub id: Uuid,
+    pub app_id: Uuid,
+    pub user_id: Uuid,
+    pub scopes: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[sqlite(skip)]
+    pub app: Option<OAuthApp>,
+}
+
+impl Default for OAuthGrant {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            app_id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            scopes: 0,
+            created_at: now,
+            updated_at: now,
+            app: None,
+        }
+    }
+}
+
+impl From<OAuthGrant> for api::OAuthGrant {
+    fn from(oauth_grant: OAuthGrant) -> Self {
+        Self {
+            id: oauth_grant.id,
+            scopes: TokenScope::names(oauth_grant.scopes),
+            created_at: oauth_grant.created_at,
+            updated_at: oauth_grant.updated_at,
+            app: oauth_grant.app.map(|app| app.into()),
+        }
+    }
+}
+
+pub fn find_oauth_grant(ctx: &Context, app_id: Uuid, user_id: Uuid) -> Option<OAuthGrant> {
+    ctx.database
+        .query::<OAuthGrant>(
+            formatcp!(
+                "SELECT {} FROM oauth_grants WHERE app_id = ? AND user_id = ? LIMIT 1",
+                OAuthGrant::columns()
+            ),
+            (app_id, user_id),
+        )
+        .next()
+}
+
+// Stores the consent of the user, approving again replaces the scopes of the grant
+pub fn upsert_oauth_grant(ctx: &Context, app_id: Uuid, user_id: Uuid, scopes: i64) -> OAuthGrant {
+    match find_oauth_grant(ctx, app_id, user_id) {
+        Some(mut oauth_grant) => {
+            oauth_grant.scopes = scopes;
+            oauth_grant.updated_at = Utc::now();
+            ctx.database.execute(
+                "UPDATE oauth_grants SET scopes = ?, updated_at = ? WHERE id = ?",
+                (oauth_grant.scopes, oauth_grant.updated_at, oauth_grant.id),
+            );
+            oauth_grant
+        }
+        None => {
+            let oauth_grant = OAuthGrant {
+                app_id,
+                user_id,
+                scopes,
+                ..Default::default()
+            };
+            ctx.database.insert_oauth_grant(oauth_grant.clone());
+            oauth_grant
+        }
+    }
+}
+
+impl OAuthGrant {
+    pub fn fetch_relationships(&mut self, ctx: &Context) {
+        self.app = find_oauth_app(ctx, self.app_id);
+    }
+}
+
+// MARK: OAuth token
+#[derive(Clone, FromRow)]
+pub struct OAuthToken {
+    pub id: Uuid,
+    pub grant_id: Uuid,
+    pub app_id: Uuid,
+    pub user_id: Uuid,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub scopes: i64,
+    pub expires_at: DateTime<Utc>,
+    pub refresh_expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for OAuthToken {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            grant_id: Uuid::nil(),
+            app_id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            access_token: "".to_string(),
+            refresh_token: "".to_string(),
+            scopes: 0,
+            expires_at: now + OAUTH_ACCESS_TOKEN_EXPIRE_DURATION,
+            refresh_expires_at: now + OAUTH_REFRESH_TOKEN_EXPIRE_DURATION,
+            last_used_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+impl OAuthToken {
+    pub fn touch(&mut self, ctx: &Context) {
+        let now = Utc::now();
+        if self
+            .last_used_at
+            .is_some_and(|last_used_at| now < last_used_at + OAUTH_TOKEN_LAST_USED_UPDATE_INTERVAL)
+        {
+            return;
+        }
+        self.last_used_at = Some(now);
+        ctx.database.execute(
+            "UPDATE oauth_tokens SET last_used_at = ? WHERE id = ?",
+            (now, self.id),
+        );
+    }
+}
+
+// Issues a new access and refresh token pair for the grant, returns the plain tokens
+pub fn issue_oauth_token(
+    ctx: &Context,
+    oauth_grant: &OAuthGrant,
+    scopes: i64,
+) -> (OAuthToken, String, String) {
+    let access_token = format!("{}{}", OAUTH_ACCESS_TOKEN_PREFIX, generate_url_token());
+    let refresh_token = format!("{}{}", OAUTH_REFRESH_TOKEN_PREFIX, generate_url_token());
+    let oauth_token = OAuthToken {
+        grant_id: oauth_grant.id,
+        app_id: oauth_grant.app_id,
+        user_id: oauth_grant.user_id,
+        access_token: hash_token(&access_token),
+        refresh_token: hash_token(&refresh_token),
+        // Tokens never get more scopes than the user currently grants the app
+        scopes: scopes & oauth_grant.scopes,
+        ..Default::default()
+    };
+    ctx.database.insert_oauth_token(oauth_token.clone());
+    (oauth_token, access_token, refresh_token)
+}
+
+// Finds a not expired OAuth token by its plain access token
+pub fn find_valid_oauth_access_token(ctx: &Context, access_token: &str) -> Option<OAuthToken> {
+    ctx.database
+        .query::<OAuthToken>(
+            formatcp!(
+                "SELECT {} FROM oauth_tokens WHERE access_token = ? AND expires_at > ? LIMIT 1",
+                OAuthToken::columns()
+            ),
+            (hash_token(access_token), Utc::now()),
+        )
+        .next()
+}
+
+// Finds a not expired OAuth token by its plain refresh token
+pub fn find_valid_oauth_refresh_token(ctx: &Context, refresh_token: &str) -> Option<OAuthToken> {
+    ctx.database
+        .query::<OAuthToken>(
+            formatcp!(
+                "SELECT {} FROM oauth_tokens WHERE refresh_token = ? AND refresh_expires_at > ? LIMIT 1",
+                OAuthToken::columns()
+            ),
+            (hash_token(refresh_token), Utc::now()),
+        )
+        .next()
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::User;
+
+    #[test]
+    fn test_verify_code_verifier() {
+        // Example from RFC 7636 appendix B
+        let authorization_code = OAuthAuthorizationCode {
+            code_challenge: "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM".to_string(),
+            ..Default::default()
+        };
+        assert!(
+            authorization_code.verify_code_verifier("dBjjJiEjHBgQpS2eYn1u7ZKyG2YrCCAmpUt4Hj5kPwc")
+        );
+        assert!(!authorization_code.verify_code_verifier("wrong"));
+    }
+
+    #[test]
+    fn test_take_oauth_authorization_code_once() {
+        let ctx = Context::with_test_database();
+        let user = User::default();
+        ctx.database.insert_user(user.clone());
+        let oauth_app = OAuthApp {
+            user_id: user.id,
+            ..Default::default()
+        };
+        ctx.database.insert_oauth_app(oauth_app.clone());
+        ctx.database
+            .insert_oauth_authorization_code(OAuthAuthorizationCode {
+                app_id: oauth_app.id,
+                user_id: user.id,
+                code: hash_token("code"),
+                ..Default::default()
+            });
+
+        assert!(take_oauth_authorization_code(&ctx, "code").is_some());
+        assert!(take_oauth_authorization_code(&ctx, "code").is_none());
+    }
+}
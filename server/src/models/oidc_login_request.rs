@@ -0,0 +1,62 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::time::Duration;
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use crate::controllers::auth::hash_token;
+use crate::Context;
+
+pub const OIDC_LOGIN_REQUEST_EXPIRE_DURATION: Duration = Duration::from_secs(10 * 60);
+
+// MARK: OIDC login request
+// Pending single sign-on login, the state, nonce and PKCE verifier are checked on the callback
+#[derive(Clone, FromRow)]
+pub struct OidcLoginRequest {
+    pub id: Uuid,
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Default for OidcLoginRequest {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            state: "".to_string(),
+            nonce: "".to_string(),
+            code_verifier: "".to_string(),
+            expires_at: now + OIDC_LOGIN_REQUEST_EXPIRE_DURATION,
+            created_at: now,
+        }
+    }
+}
+
+// Finds a not expired login request by its plain state and deletes it so it can only be used once
+pub fn take_oidc_login_request(ctx: &Context, state: &str) -> Option<OidcLoginRequest> {
+    let oidc_login_request = ctx
+        .database
+        .query::<OidcLoginRequest>(
+            formatcp!(
+                "SELECT {} FROM oidc_login_requests WHERE state = ? AND expires_at > ? LIMIT 1",
+                OidcLoginRequest::columns()
+            ),
+            (hash_token(state), Utc::now()),
+        )
+        .next()?;
+    ctx.database.execute(
+        "DELETE FROM oidc_login_requests WHERE id = ?",
+        oidc_login_request.id,
+    );
+    Some(oidc_login_request)
+}
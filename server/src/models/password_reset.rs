@@ -0,0 +1,95 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::time::Duration;
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use crate::controllers::auth::hash_token;
+use crate::Context;
+
+pub const PASSWORD_RESET_EXPIRE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+// MARK: Password reset
+#[derive(Clone, FromRow)]
+pub struct PasswordReset {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for PasswordReset {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            token: "".to_string(),
+            expires_at: now + PASSWORD_RESET_EXPIRE_DURATION,
+            used_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+// Finds an unused and not expired password reset by its plain token
+pub fn find_valid_password_reset(ctx: &Context, token: &str) -> Option<PasswordReset> {
+    ctx.database
+        .query::<PasswordReset>(
+            formatcp!(
+                "SELECT {} FROM password_resets WHERE token = ? AND used_at IS NULL AND expires_at > ? LIMIT 1",
+                PasswordReset::columns()
+            ),
+            (hash_token(token), Utc::now()),
+        )
+        .next()
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Extension;
+    use crate::models::User;
+
+    #[test]
+    fn test_find_valid_password_reset() {
+        let ctx = Context::with_test_database();
+        let user = User::default();
+        ctx.database.insert_user(user.clone());
+
+        ctx.database.insert_password_reset(PasswordReset {
+            user_id: user.id,
+            token: hash_token("valid"),
+            ..Default::default()
+        });
+        ctx.database.insert_password_reset(PasswordReset {
+            user_id: user.id,
+            token: hash_token("expired"),
+            expires_at: Utc::now(),
+            ..Default::default()
+        });
+        ctx.database.insert_password_reset(PasswordReset {
+            user_id: user.id,
+            token: hash_token("used"),
+            used_at: Some(Utc::now()),
+            ..Default::default()
+        });
+
+        assert!(find_valid_password_reset(&ctx, "valid").is_some());
+        assert!(find_valid_password_reset(&ctx, "expired").is_none());
+        assert!(find_valid_password_reset(&ctx, "used").is_none());
+        assert!(find_valid_password_reset(&ctx, "unknown").is_none());
+    }
+}
@@ -0,0 +1,179 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::time::Duration;
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use crate::controllers::auth::hash_token;
+use crate::{api, Context};
+
+// Only write last used info once per interval to avoid a write on every request
+pub const PERSONAL_ACCESS_TOKEN_LAST_USED_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+// Recognizable prefix so the auth layer and secret scanners can tell these apart from session tokens
+pub const PERSONAL_ACCESS_TOKEN_PREFIX: &str = "pbp_";
+
+// MARK: Personal access token
+#[derive(Clone, FromRow)]
+pub struct PersonalAccessToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub token: String,
+    pub scopes: i64,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for PersonalAccessToken {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            name: "".to_string(),
+            token: "".to_string(),
+            scopes: TokenScope::ALL,
+            expires_at: None,
+            last_used_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+// Scopes are shared by personal access tokens and OAuth access tokens
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum TokenScope {
+    PostsRead = 1,
+    PostsWrite = 2,
+    Account = 4,
+}
+
+impl TokenScope {
+    pub const ALL: i64 = Self::PostsRead as i64 | Self::PostsWrite as i64 | Self::Account as i64;
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PostsRead => "posts:read",
+            Self::PostsWrite => "posts:write",
+            Self::Account => "account",
+        }
+    }
+
+    pub fn parse_list(value: &str) -> Option<i64> {
+        let mut scopes = 0;
+        for scope in value.split(',').map(|scope| scope.trim()) {
+            let scope = match scope {
+                "posts:read" => Self::PostsRead,
+                "posts:write" => Self::PostsWrite,
+                "account" => Self::Account,
+                _ => return None,
+            };
+            scopes |= scope as i64;
+        }
+        Some(scopes)
+    }
+
+    pub fn names(scopes: i64) -> Vec<String> {
+        [Self::PostsRead, Self::PostsWrite, Self::Account]
+            .into_iter()
+            .filter(|scope| scopes & *scope as i64 != 0)
+            .map(|scope| scope.name().to_string())
+            .collect()
+    }
+}
+
+impl From<PersonalAccessToken> for api::PersonalAccessToken {
+    fn from(personal_access_token: PersonalAccessToken) -> Self {
+        Self {
+            id: personal_access_token.id,
+            name: personal_access_token.name,
+            scopes: TokenScope::names(personal_access_token.scopes),
+            expires_at: personal_access_token.expires_at,
+            last_used_at: personal_access_token.last_used_at,
+            created_at: personal_access_token.created_at,
+            updated_at: personal_access_token.updated_at,
+        }
+    }
+}
+
+// Finds a not expired personal access token by its plain token
+pub fn find_valid_personal_access_token(ctx: &Context, token: &str) -> Option<PersonalAccessToken> {
+    ctx.database
+        .query::<PersonalAccessToken>(
+            formatcp!(
+                "SELECT {} FROM personal_access_tokens WHERE token = ? AND (expires_at IS NULL OR expires_at > ?) LIMIT 1",
+                PersonalAccessToken::columns()
+            ),
+            (hash_token(token), Utc::now()),
+        )
+        .next()
+}
+
+// MARK: Last used
+impl PersonalAccessToken {
+    pub fn touch(&mut self, ctx: &Context) {
+        let now = Utc::now();
+        if self.last_used_at.is_some_and(|last_used_at| {
+            now < last_used_at + PERSONAL_ACCESS_TOKEN_LAST_USED_UPDATE_INTERVAL
+        }) {
+            return;
+        }
+        self.last_used_at = Some(now);
+        ctx.database.execute(
+            "UPDATE personal_access_tokens SET last_used_at = ? WHERE id = ?",
+            (now, self.id),
+        );
+    }
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Extension;
+    use crate::models::User;
+
+    #[test]
+    fn test_personal_access_token_scope_parse_list() {
+        assert_eq!(
+            TokenScope::parse_list("posts:read, account"),
+            Some(TokenScope::PostsRead as i64 | TokenScope::Account as i64)
+        );
+        assert_eq!(TokenScope::parse_list("posts:read,admin"), None);
+    }
+
+    #[test]
+    fn test_find_valid_personal_access_token() {
+        let ctx = Context::with_test_database();
+        let user = User::default();
+        ctx.database.insert_user(user.clone());
+
+        ctx.database
+            .insert_personal_access_token(PersonalAccessToken {
+                user_id: user.id,
+                token: hash_token("valid"),
+                ..Default::default()
+            });
+        ctx.database
+            .insert_personal_access_token(PersonalAccessToken {
+                user_id: user.id,
+                token: hash_token("expired"),
+                expires_at: Some(Utc::now()),
+                ..Default::default()
+            });
+
+        assert!(find_valid_personal_access_token(&ctx, "valid").is_some());
+        assert!(find_valid_personal_access_token(&ctx, "expired").is_none());
+        assert!(find_valid_personal_access_token(&ctx, "unknown").is_none());
+    }
+}
@@ -13,7 +13,8 @@ use from_enum::FromEnum;
 use regex::Regex;
 use uuid::Uuid;
 
-use super::{PostInteractionType, User};
+use super::user_relation::BLOCKED_USERS_CTE;
+use super::{PostFilter, PostFilterAction, PostInteractionType, User};
 use crate::{api, Context};
 
 // MARK: Post
@@ -46,6 +47,8 @@ pub struct Post {
     pub auth_user_liked: Option<bool>,
     #[sqlite(skip)]
     pub auth_user_disliked: Option<bool>,
+    #[sqlite(skip)]
+    pub filter_name: Option<String>,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, FromEnum, FromValue)]
@@ -77,6 +80,7 @@ impl Default for Post {
             replies: None,
             auth_user_liked: None,
             auth_user_disliked: None,
+            filter_name: None,
         }
     }
 }
@@ -103,6 +107,8 @@ impl From<Post> for api::Post {
                 .map(|replies| replies.into_iter().map(|post| post.into()).collect()),
             auth_user_liked: post.auth_user_liked,
             auth_user_disliked: post.auth_user_disliked,
+            filtered: post.filter_name.is_some(),
+            filter_name: post.filter_name,
         }
     }
 }
@@ -198,6 +204,47 @@ impl Post {
     }
 }
 
+// MARK: Visibility
+// Condition that hides the posts and reposts of the users in the `hidden_users` table
+pub const POST_NOT_HIDDEN_CONDITION: &str = formatcp!(
+    "user_id NOT IN (SELECT id FROM hidden_users)
+    AND NOT (type = {} AND parent_post_id IN (SELECT id FROM posts WHERE user_id IN (SELECT id FROM hidden_users)))",
+    PostType::Repost as i64
+);
+
+impl Post {
+    pub fn is_hidden_for_auth_user(&self, ctx: &Context) -> bool {
+        let auth_user_id = ctx.auth_user.as_ref().map_or(Uuid::nil(), |user| user.id);
+        ctx.database
+            .query::<i64>(
+                formatcp!(
+                    "{} SELECT COUNT(id) FROM posts WHERE id = ? AND NOT ({})",
+                    BLOCKED_USERS_CTE,
+                    POST_NOT_HIDDEN_CONDITION
+                ),
+                (auth_user_id, auth_user_id, Utc::now(), self.id),
+            )
+            .next()
+            .expect("Should be some")
+            > 0
+    }
+}
+
+// MARK: Filters
+impl Post {
+    // Hide filters are already applied in the query with `POST_NOT_FILTERED_CONDITION`, warn
+    // filters only label the post
+    pub fn apply_filters(mut self, post_filters: &[PostFilter]) -> Self {
+        self.filter_name = post_filters
+            .iter()
+            .find(|post_filter| {
+                post_filter.action == PostFilterAction::Warn && post_filter.matches(&self.text)
+            })
+            .map(|post_filter| post_filter.name.clone());
+        self
+    }
+}
+
 // MARK: Post Markdown
 static URL_REGEX: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"(https?://[\w./?=&-]+)").expect("Should compile"));
@@ -0,0 +1,239 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use bsqlite::{FromRow, FromValue};
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use from_enum::FromEnum;
+use uuid::Uuid;
+
+use crate::{api, Context};
+
+// MARK: Post filter
+#[derive(Clone, FromRow)]
+pub struct PostFilter {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub phrase: String,
+    pub contexts: i64,
+    pub action: PostFilterAction,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for PostFilter {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            name: "".to_string(),
+            phrase: "".to_string(),
+            contexts: PostFilterContext::ALL,
+            action: PostFilterAction::Hide,
+            expires_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum PostFilterContext {
+    Home = 1,
+    Replies = 2,
+    Search = 4,
+    Profile = 8,
+}
+
+impl PostFilterContext {
+    pub const ALL: i64 =
+        Self::Home as i64 | Self::Replies as i64 | Self::Search as i64 | Self::Profile as i64;
+
+    pub fn parse_list(value: &str) -> Option<i64> {
+        let mut contexts = 0;
+        for context in value.split(',').map(|context| context.trim()) {
+            let context = match context {
+                "home" => Self::Home,
+                "replies" => Self::Replies,
+                "search" => Self::Search,
+                "profile" => Self::Profile,
+                _ => return None,
+            };
+            contexts |= context as i64;
+        }
+        Some(contexts)
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, FromEnum, FromValue)]
+#[from_enum(api::PostFilterAction)]
+pub enum PostFilterAction {
+    Hide = 0,
+    Warn = 1,
+}
+
+impl From<PostFilter> for api::PostFilter {
+    fn from(post_filter: PostFilter) -> Self {
+        Self {
+            id: post_filter.id,
+            name: post_filter.name,
+            phrase: post_filter.phrase,
+            contexts: [
+                (PostFilterContext::Home, api::PostFilterContext::Home),
+                (PostFilterContext::Replies, api::PostFilterContext::Replies),
+                (PostFilterContext::Search, api::PostFilterContext::Search),
+                (PostFilterContext::Profile, api::PostFilterContext::Profile),
+            ]
+            .into_iter()
+            .filter(|(context, _)| post_filter.contexts & *context as i64 != 0)
+            .map(|(_, api_context)| api_context)
+            .collect(),
+            action: post_filter.action.into(),
+            expires_at: post_filter.expires_at,
+            created_at: post_filter.created_at,
+            updated_at: post_filter.updated_at,
+        }
+    }
+}
+
+// MARK: Matching
+impl PostFilter {
+    pub fn matches(&self, text: &str) -> bool {
+        // Match phrase ASCII case insensitive on word boundaries, the same as the SQL condition
+        let text = text.to_ascii_lowercase();
+        let phrase = self.phrase.to_ascii_lowercase();
+        let is_word_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        text.match_indices(&phrase).any(|(index, _)| {
+            let before = text[..index].chars().next_back();
+            let after = text[index + phrase.len()..].chars().next();
+            !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+        })
+    }
+}
+
+// Condition that leaves out the posts matched by a hide filter of the auth user in the given
+// context, so pagination stays correct. The phrase is escaped for GLOB and matched on word
+// boundaries, the text is padded so phrases can match at the start and end
+pub const POST_NOT_FILTERED_CONDITION: &str = formatcp!(
+    "NOT EXISTS (SELECT post_filters.id FROM post_filters WHERE post_filters.user_id = ?
+        AND post_filters.action = {} AND post_filters.contexts & ? != 0
+        AND (post_filters.expires_at IS NULL OR post_filters.expires_at > ?)
+        AND (' ' || lower(posts.text) || ' ') GLOB ('*[^a-z0-9_]'
+            || replace(replace(replace(lower(post_filters.phrase), '[', '[[]'), '*', '[*]'), '?', '[?]')
+            || '[^a-z0-9_]*'))",
+    PostFilterAction::Hide as i64
+);
+
+pub fn fetch_auth_user_post_filters(ctx: &Context, context: PostFilterContext) -> Vec<PostFilter> {
+    let auth_user = match &ctx.auth_user {
+        Some(auth_user) => auth_user,
+        None => return Vec::new(),
+    };
+    ctx.database
+        .query::<PostFilter>(
+            formatcp!(
+                "SELECT {} FROM post_filters WHERE user_id = ? AND contexts & ? != 0 AND (expires_at IS NULL OR expires_at > ?)",
+                PostFilter::columns()
+            ),
+            (auth_user.id, context as i64, Utc::now()),
+        )
+        .collect()
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Extension;
+    use crate::models::UserRole;
+    use crate::test_utils::create_user;
+
+    #[test]
+    fn test_post_filter_matches() {
+        let post_filter = PostFilter {
+            phrase: "Rust".to_string(),
+            ..Default::default()
+        };
+        assert!(post_filter.matches("I like rust"));
+        assert!(post_filter.matches("RUST, is it any good?"));
+        assert!(!post_filter.matches("My bike is rusty"));
+        assert!(!post_filter.matches("Hello world"));
+    }
+
+    #[test]
+    fn test_post_filter_matches_hashtag_and_phrase() {
+        let post_filter = PostFilter {
+            phrase: "#spoilers".to_string(),
+            ..Default::default()
+        };
+        assert!(post_filter.matches("Ending explained #spoilers"));
+        assert!(!post_filter.matches("No #spoilersfree posts"));
+
+        let post_filter = PostFilter {
+            phrase: "hello world".to_string(),
+            ..Default::default()
+        };
+        assert!(post_filter.matches("Hello world!"));
+        assert!(!post_filter.matches("Hello big world"));
+    }
+
+    #[test]
+    fn test_post_not_filtered_condition() {
+        let ctx = Context::with_test_database();
+        let user = create_user(&ctx, UserRole::Normal);
+        ctx.database.insert_post_filter(PostFilter {
+            user_id: user.id,
+            phrase: "Rust*".to_string(),
+            ..Default::default()
+        });
+        let is_filtered = |text: &str| {
+            ctx.database
+                .query::<i64>(
+                    formatcp!(
+                        "SELECT COUNT(*) FROM (SELECT ? AS text) AS posts WHERE NOT ({})",
+                        POST_NOT_FILTERED_CONDITION
+                    ),
+                    (
+                        text.to_string(),
+                        user.id,
+                        PostFilterContext::Home as i64,
+                        Utc::now(),
+                    ),
+                )
+                .next()
+                .unwrap()
+                > 0
+        };
+
+        // Matches the same posts as the Rust matcher
+        for text in [
+            "I like rust*",
+            "RUST*, is it good?",
+            "rust*",
+            "rust",
+            "rust*y",
+            "Rustacean",
+        ] {
+            let post_filter = PostFilter {
+                phrase: "Rust*".to_string(),
+                ..Default::default()
+            };
+            assert_eq!(is_filtered(text), post_filter.matches(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_post_filter_context_parse_list() {
+        assert_eq!(
+            PostFilterContext::parse_list("home, search"),
+            Some(PostFilterContext::Home as i64 | PostFilterContext::Search as i64)
+        );
+        assert_eq!(PostFilterContext::parse_list("home,unknown"), None);
+    }
+}
@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::time::Duration;
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::controllers::auth::generate_url_token;
+use crate::{api, Context};
+
+pub const POW_CHALLENGE_EXPIRE_DURATION: Duration = Duration::from_secs(10 * 60);
+
+// MARK: Proof of work challenge
+// Hashcash style challenge, the client must find a nonce so that the SHA-256 hash of
+// `{challenge}:{nonce}` starts with the given number of zero bits
+#[derive(Clone, FromRow)]
+pub struct PowChallenge {
+    pub id: Uuid,
+    pub challenge: String,
+    pub difficulty: i64,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Default for PowChallenge {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            challenge: generate_url_token(),
+            difficulty: 0,
+            expires_at: now + POW_CHALLENGE_EXPIRE_DURATION,
+            created_at: now,
+        }
+    }
+}
+
+impl From<PowChallenge> for api::PowChallenge {
+    fn from(pow_challenge: PowChallenge) -> Self {
+        Self {
+            challenge: pow_challenge.challenge,
+            difficulty: pow_challenge.difficulty,
+            expires_at: pow_challenge.expires_at,
+        }
+    }
+}
+
+// MARK: Verification
+fn leading_zero_bits(hash: &[u8]) -> i64 {
+    let mut bits = 0;
+    for byte in hash {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            bits += byte.leading_zeros() as i64;
+            break;
+        }
+    }
+    bits
+}
+
+pub fn is_pow_solution(challenge: &str, difficulty: i64, nonce: &str) -> bool {
+    let hash = Sha256::digest(format!("{}:{}", challenge, nonce).as_bytes());
+    leading_zero_bits(&hash) >= difficulty
+}
+
+// Checks the solution and deletes the challenge so it can only be used once
+pub fn verify_pow_challenge(ctx: &Context, challenge: &str, nonce: &str) -> bool {
+    let pow_challenge = match ctx
+        .database
+        .query::<PowChallenge>(
+            formatcp!(
+                "SELECT {} FROM pow_challenges WHERE challenge = ? AND expires_at > ? LIMIT 1",
+                PowChallenge::columns()
+            ),
+            (challenge.to_string(), Utc::now()),
+        )
+        .next()
+    {
+        Some(pow_challenge) => pow_challenge,
+        None => return false,
+    };
+    ctx.database
+        .execute("DELETE FROM pow_challenges WHERE id = ?", pow_challenge.id);
+    is_pow_solution(&pow_challenge.challenge, pow_challenge.difficulty, nonce)
+}
+
+// MARK: Tests
+#[cfg(test)]
+pub(crate) fn solve_pow_challenge(challenge: &str, difficulty: i64) -> String {
+    (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| is_pow_solution(challenge, difficulty, nonce))
+        .expect("Should be some")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Extension;
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x0f]), 4);
+        assert_eq!(leading_zero_bits(&[0x00, 0x01]), 15);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[test]
+    fn test_verify_pow_challenge() {
+        let ctx = Context::with_test_database();
+        let pow_challenge = PowChallenge {
+            difficulty: 8,
+            ..Default::default()
+        };
+        ctx.database.insert_pow_challenge(pow_challenge.clone());
+        let nonce = solve_pow_challenge(&pow_challenge.challenge, pow_challenge.difficulty);
+
+        // Challenge can only be used once
+        assert!(verify_pow_challenge(&ctx, &pow_challenge.challenge, &nonce));
+        assert!(!verify_pow_challenge(
+            &ctx,
+            &pow_challenge.challenge,
+            &nonce
+        ));
+
+        // Expired challenge is rejected
+        let expired_pow_challenge = PowChallenge {
+            expires_at: Utc::now(),
+            ..Default::default()
+        };
+        ctx.database
+            .insert_pow_challenge(expired_pow_challenge.clone());
+        assert!(!verify_pow_challenge(
+            &ctx,
+            &expired_pow_challenge.challenge,
+            "0"
+        ));
+    }
+}
@@ -0,0 +1,176 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use bsqlite::{FromRow, FromValue};
+use chrono::{DateTime, Utc};
+use from_enum::FromEnum;
+use small_http::Request;
+use uuid::Uuid;
+
+use crate::database::Extension;
+use crate::mail::Mail;
+use crate::models::User;
+use crate::{api, Context, USER_AGENT_PARSER};
+
+// MARK: Security event
+#[derive(Clone, FromRow)]
+pub struct SecurityEvent {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub r#type: SecurityEventType,
+    pub actor_user_id: Option<Uuid>,
+    pub ip_address: String,
+    pub ip_country: Option<String>,
+    pub client_name: Option<String>,
+    pub client_version: Option<String>,
+    pub client_os: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Default for SecurityEvent {
+    fn default() -> Self {
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            r#type: SecurityEventType::LoginSucceeded,
+            actor_user_id: None,
+            ip_address: "".to_string(),
+            ip_country: None,
+            client_name: None,
+            client_version: None,
+            client_os: None,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, FromEnum, FromValue)]
+#[from_enum(api::SecurityEventType)]
+pub enum SecurityEventType {
+    LoginSucceeded = 0,
+    LoginFailed = 1,
+    PasswordChanged = 2,
+    SessionRevoked = 3,
+    TwoFactorEnabled = 4,
+    TwoFactorDisabled = 5,
+    TokenCreated = 6,
+    ImpersonationStarted = 7,
+    ImpersonationEnded = 8,
+    IdentityLinked = 9,
+}
+
+impl From<SecurityEvent> for api::SecurityEvent {
+    fn from(security_event: SecurityEvent) -> Self {
+        Self {
+            id: security_event.id,
+            r#type: security_event.r#type.into(),
+            actor_user_id: security_event.actor_user_id,
+            ip_address: security_event.ip_address,
+            ip_country: security_event.ip_country,
+            client_name: security_event.client_name,
+            client_version: security_event.client_version,
+            client_os: security_event.client_os,
+            created_at: security_event.created_at,
+        }
+    }
+}
+
+// MARK: Record
+// Records the event with the ip address and client info of the request, the actor is the admin
+// when acting on another user or impersonating the user
+pub fn record_security_event(
+    req: &Request,
+    ctx: &Context,
+    user_id: Uuid,
+    r#type: SecurityEventType,
+) -> SecurityEvent {
+    let user_agent = req
+        .headers
+        .get("User-Agent")
+        .map(|user_agent| USER_AGENT_PARSER.parse(user_agent));
+    let actor_user_id = ctx
+        .auth_session
+        .as_ref()
+        .and_then(|session| session.impersonator_user_id)
+        .or(ctx.auth_user.as_ref().map(|auth_user| auth_user.id))
+        .filter(|actor_user_id| *actor_user_id != user_id);
+    let security_event = SecurityEvent {
+        user_id,
+        r#type,
+        actor_user_id,
+        ip_address: req.client_addr.ip().to_string(),
+        ip_country: ctx
+            .geoip
+            .lookup(req.client_addr.ip())
+            .and_then(|ip_info| ip_info.country),
+        client_name: user_agent.as_ref().map(|ua| ua.client.family.to_string()),
+        client_version: user_agent.as_ref().and_then(|ua| ua.client.version.clone()),
+        client_os: user_agent.as_ref().map(|ua| ua.os.family.to_string()),
+        ..Default::default()
+    };
+    ctx.database.insert_security_event(security_event.clone());
+    security_event
+}
+
+// MARK: New device alert
+// A device is the combination of client, OS and country of earlier successful logins
+fn is_new_device(ctx: &Context, security_event: &SecurityEvent) -> bool {
+    let logins_count = ctx
+        .database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM security_events WHERE user_id = ? AND type = ? AND id != ?",
+            (
+                security_event.user_id,
+                SecurityEventType::LoginSucceeded,
+                security_event.id,
+            ),
+        )
+        .next()
+        .expect("Should be some");
+    if logins_count == 0 {
+        // The first login of an account is no alert
+        return false;
+    }
+    let device_logins_count = ctx
+        .database
+        .query::<i64>(
+            "SELECT COUNT(id) FROM security_events WHERE user_id = ? AND type = ? AND id != ?
+                AND IFNULL(client_name, '') = ? AND IFNULL(client_os, '') = ? AND IFNULL(ip_country, '') = ?",
+            (
+                security_event.user_id,
+                SecurityEventType::LoginSucceeded,
+                security_event.id,
+                security_event.client_name.clone().unwrap_or_default(),
+                security_event.client_os.clone().unwrap_or_default(),
+                security_event.ip_country.clone().unwrap_or_default(),
+            ),
+        )
+        .next()
+        .expect("Should be some");
+    device_logins_count == 0
+}
+
+pub fn send_new_device_alert(ctx: &Context, user: &User, security_event: &SecurityEvent) {
+    if !is_new_device(ctx, security_event) {
+        return;
+    }
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: "New login to your PlaatBook account".to_string(),
+        body: format!(
+            "Hi {},\n\nYour PlaatBook account was just logged in from a new device:\n\nClient: {} on {}\nIP address: {} ({})\n\nIf this was you, you can ignore this email. If not, change your password and revoke the session at {}/settings.\n",
+            user.username,
+            security_event.client_name.as_deref().unwrap_or("Unknown client"),
+            security_event.client_os.as_deref().unwrap_or("unknown OS"),
+            security_event.ip_address,
+            security_event.ip_country.as_deref().unwrap_or("unknown country"),
+            ctx.settings.app_url
+        ),
+    };
+    if let Err(err) = ctx.mailer.send(&mail) {
+        eprintln!("Can't send new device alert mail: {}", err);
+    }
+}
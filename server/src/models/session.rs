@@ -11,10 +11,17 @@ use chrono::{DateTime, Utc};
 use const_format::formatcp;
 use uuid::Uuid;
 
-use super::User;
+use super::{SecurityEvent, SecurityEventType, User};
+use crate::database::Extension;
 use crate::{api, Context};
 
 pub const SESSION_EXPIRE_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+// Only write last seen info once per interval to avoid a write on every request
+pub const SESSION_LAST_SEEN_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+// Recognizable prefix so leaked tokens can be detected by secret scanners
+pub const SESSION_TOKEN_PREFIX: &str = "pbs_";
+// Impersonation sessions are short lived and can't be extended
+pub const IMPERSONATION_SESSION_DURATION: Duration = Duration::from_secs(60 * 60);
 
 #[derive(Clone, FromRow)]
 pub struct Session {
@@ -29,11 +36,17 @@ pub struct Session {
     pub client_name: Option<String>,
     pub client_version: Option<String>,
     pub client_os: Option<String>,
+    pub remember: bool,
+    pub impersonator_user_id: Option<Uuid>,
+    pub last_seen_at: DateTime<Utc>,
+    pub last_ip_address: String,
     pub expires_at: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     #[sqlite(skip)]
     pub user: Option<User>,
+    #[sqlite(skip)]
+    pub impersonator: Option<User>,
 }
 
 impl Default for Session {
@@ -51,10 +64,15 @@ impl Default for Session {
             client_name: None,
             client_version: None,
             client_os: None,
+            remember: true,
+            impersonator_user_id: None,
+            last_seen_at: now,
+            last_ip_address: String::new(),
             expires_at: now + SESSION_EXPIRE_DURATION,
             created_at: now,
             updated_at: now,
             user: None,
+            impersonator: None,
         }
     }
 }
@@ -71,14 +89,139 @@ impl From<Session> for api::Session {
             client_name: session.client_name,
             client_version: session.client_version,
             client_os: session.client_os,
+            remember: session.remember,
+            last_seen_at: session.last_seen_at,
+            last_ip_address: session.last_ip_address,
             expires_at: session.expires_at,
             created_at: session.created_at,
             updated_at: session.updated_at,
             user: session.user.map(|user| user.into()),
+            impersonator: session.impersonator.map(|user| user.into()),
+        }
+    }
+}
+
+// MARK: Impersonation
+impl Session {
+    pub fn is_impersonation(&self) -> bool {
+        self.impersonator_user_id.is_some()
+    }
+
+    pub fn with_impersonator(mut self, ctx: &Context) -> Self {
+        if let Some(impersonator_user_id) = self.impersonator_user_id {
+            self.impersonator = ctx
+                .database
+                .query::<User>(
+                    formatcp!("SELECT {} FROM users WHERE id = ? LIMIT 1", User::columns()),
+                    impersonator_user_id,
+                )
+                .next();
+        }
+        self
+    }
+}
+
+// MARK: Last seen
+impl Session {
+    pub fn touch(&mut self, ctx: &Context, ip_address: &str) {
+        let now = Utc::now();
+        if now < self.last_seen_at + SESSION_LAST_SEEN_UPDATE_INTERVAL
+            && self.last_ip_address == ip_address
+        {
+            return;
         }
+        self.last_seen_at = now;
+        self.last_ip_address = ip_address.to_string();
+        ctx.database.execute(
+            "UPDATE sessions SET last_seen_at = ?, last_ip_address = ? WHERE id = ?",
+            (self.last_seen_at, self.last_ip_address.clone(), self.id),
+        );
+    }
+}
+
+// Records the end of an impersonation session at its expire time with the impersonator as actor,
+// the exact time links the event to the session so it is recorded only once
+pub fn record_impersonation_ended(ctx: &Context, session: &Session) {
+    ctx.database.insert_security_event(SecurityEvent {
+        user_id: session.user_id,
+        r#type: SecurityEventType::ImpersonationEnded,
+        actor_user_id: session.impersonator_user_id,
+        ip_address: session.last_ip_address.clone(),
+        ip_country: session.ip_country.clone(),
+        client_name: session.client_name.clone(),
+        client_version: session.client_version.clone(),
+        client_os: session.client_os.clone(),
+        created_at: session.expires_at,
+        ..Default::default()
+    });
+}
+
+// Records the end of impersonation sessions that timed out, run by the background thread
+pub fn record_expired_impersonations(ctx: &Context) {
+    let sessions = ctx
+        .database
+        .query::<Session>(
+            formatcp!(
+                "SELECT {} FROM sessions WHERE impersonator_user_id IS NOT NULL AND expires_at <= ?
+                    AND NOT EXISTS (SELECT id FROM security_events WHERE security_events.user_id = sessions.user_id
+                        AND security_events.actor_user_id = sessions.impersonator_user_id
+                        AND security_events.type = {} AND security_events.created_at = sessions.expires_at)",
+                Session::columns(),
+                SecurityEventType::ImpersonationEnded as i64
+            ),
+            Utc::now(),
+        )
+        .collect::<Vec<_>>();
+    for session in sessions {
+        record_impersonation_ended(ctx, &session);
     }
 }
 
+// MARK: Revoke
+// Revokes the session, ending an active impersonation is recorded
+pub fn revoke_session(ctx: &Context, mut session: Session) {
+    let now = Utc::now();
+    ctx.database.execute(
+        "UPDATE sessions SET expires_at = ? WHERE id = ?",
+        (now, session.id),
+    );
+    if session.is_impersonation() && session.expires_at > now {
+        session.expires_at = now;
+        record_impersonation_ended(ctx, &session);
+    }
+}
+
+// Revokes all active sessions of the user, optionally except one session
+pub fn revoke_user_sessions(ctx: &Context, user_id: Uuid, except_session_id: Option<Uuid>) {
+    let sessions = ctx
+        .database
+        .query::<Session>(
+            formatcp!(
+                "SELECT {} FROM sessions WHERE user_id = ? AND expires_at > ? AND id != ?",
+                Session::columns()
+            ),
+            (
+                user_id,
+                Utc::now(),
+                except_session_id.unwrap_or(Uuid::nil()),
+            ),
+        )
+        .collect::<Vec<_>>();
+    for session in sessions {
+        revoke_session(ctx, session);
+    }
+}
+
+// Deletes all personal access tokens and OAuth tokens of the user, they don't expire with sessions
+pub fn revoke_user_tokens(ctx: &Context, user_id: Uuid) {
+    ctx.database.execute(
+        "DELETE FROM personal_access_tokens WHERE user_id = ?",
+        user_id,
+    );
+    ctx.database
+        .execute("DELETE FROM oauth_tokens WHERE user_id = ?", user_id);
+}
+
 // MARK: Relationships
 impl Session {
     pub fn fetch_relationships(&mut self, ctx: &Context) {
@@ -91,3 +234,73 @@ impl Session {
             .next();
     }
 }
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn count_impersonation_ended(ctx: &Context, user_id: Uuid) -> i64 {
+        ctx.database
+            .query::<i64>(
+                formatcp!(
+                    "SELECT COUNT(id) FROM security_events WHERE user_id = ? AND type = {}",
+                    SecurityEventType::ImpersonationEnded as i64
+                ),
+                user_id,
+            )
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_revoke_user_sessions_impersonation() {
+        let ctx = Context::with_test_database();
+        let user_id = Uuid::now_v7();
+        ctx.database.insert_session(Session {
+            user_id,
+            token: "active".to_string(),
+            impersonator_user_id: Some(Uuid::now_v7()),
+            ..Default::default()
+        });
+        ctx.database.insert_session(Session {
+            user_id,
+            token: "normal".to_string(),
+            ..Default::default()
+        });
+
+        // Only the active impersonation session records its end
+        revoke_user_sessions(&ctx, user_id, None);
+        assert_eq!(count_impersonation_ended(&ctx, user_id), 1);
+
+        // Revoked sessions are not recorded again as timed out
+        record_expired_impersonations(&ctx);
+        assert_eq!(count_impersonation_ended(&ctx, user_id), 1);
+    }
+
+    #[test]
+    fn test_record_expired_impersonations() {
+        let ctx = Context::with_test_database();
+        let user_id = Uuid::now_v7();
+        let impersonator_user_id = Uuid::now_v7();
+        ctx.database.insert_session(Session {
+            user_id,
+            token: "expired".to_string(),
+            impersonator_user_id: Some(impersonator_user_id),
+            expires_at: Utc::now() - Duration::from_secs(60),
+            ..Default::default()
+        });
+        ctx.database.insert_session(Session {
+            user_id,
+            token: "active".to_string(),
+            impersonator_user_id: Some(impersonator_user_id),
+            expires_at: Utc::now() + IMPERSONATION_SESSION_DURATION,
+            ..Default::default()
+        });
+
+        // Only the timed out session is recorded, once
+        record_expired_impersonations(&ctx);
+        record_expired_impersonations(&ctx);
+        assert_eq!(count_impersonation_ended(&ctx, user_id), 1);
+    }
+}
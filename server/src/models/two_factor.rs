@@ -0,0 +1,250 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::time::Duration;
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use crate::database::Extension;
+use crate::password::{password_hash, password_verify};
+use crate::totp::{base32_decode, base32_encode, generate_secret, verify_totp};
+use crate::Context;
+
+pub const TWO_FACTOR_CHALLENGE_EXPIRE_DURATION: Duration = Duration::from_secs(5 * 60);
+pub const TWO_FACTOR_RECOVERY_CODES_COUNT: usize = 10;
+
+// MARK: Two factor
+#[derive(Clone, FromRow)]
+pub struct TwoFactor {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub secret: String,
+    pub last_used_counter: Option<i64>,
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for TwoFactor {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            secret: "".to_string(),
+            last_used_counter: None,
+            enabled_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+impl TwoFactor {
+    pub fn secret_bytes(&self) -> Vec<u8> {
+        base32_decode(&self.secret).expect("Should be valid base32")
+    }
+
+    // Verifies a TOTP code and marks it as used so it can't be replayed
+    pub fn verify_code(&mut self, ctx: &Context, code: &str) -> bool {
+        let now = Utc::now();
+        let counter = match verify_totp(&self.secret_bytes(), code, now.timestamp() as u64) {
+            Some(counter) => counter as i64,
+            None => return false,
+        };
+        if self.last_used_counter.is_some_and(|last| counter <= last) {
+            return false;
+        }
+        self.last_used_counter = Some(counter);
+        self.updated_at = now;
+        ctx.database.execute(
+            "UPDATE two_factors SET last_used_counter = ?, updated_at = ? WHERE id = ?",
+            (self.last_used_counter, self.updated_at, self.id),
+        );
+        true
+    }
+}
+
+pub fn find_two_factor(ctx: &Context, user_id: Uuid) -> Option<TwoFactor> {
+    ctx.database
+        .query::<TwoFactor>(
+            formatcp!(
+                "SELECT {} FROM two_factors WHERE user_id = ? LIMIT 1",
+                TwoFactor::columns()
+            ),
+            user_id,
+        )
+        .next()
+}
+
+pub fn find_enabled_two_factor(ctx: &Context, user_id: Uuid) -> Option<TwoFactor> {
+    find_two_factor(ctx, user_id).filter(|two_factor| two_factor.enabled_at.is_some())
+}
+
+pub fn generate_two_factor_secret() -> String {
+    base32_encode(&generate_secret())
+}
+
+// MARK: Two factor recovery code
+#[derive(Clone, FromRow)]
+pub struct TwoFactorRecoveryCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub code: String,
+    pub used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for TwoFactorRecoveryCode {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            code: "".to_string(),
+            used_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    getrandom::fill(&mut bytes).expect("Can't get random bytes");
+    let code = base32_encode(&bytes).to_lowercase();
+    format!("{}-{}", &code[..4], &code[4..])
+}
+
+// Replaces all recovery codes of the user and returns the new plain codes
+pub fn generate_recovery_codes(ctx: &Context, user_id: Uuid) -> Vec<String> {
+    ctx.database.execute(
+        "DELETE FROM two_factor_recovery_codes WHERE user_id = ?",
+        user_id,
+    );
+    let codes = (0..TWO_FACTOR_RECOVERY_CODES_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect::<Vec<_>>();
+    for code in &codes {
+        ctx.database
+            .insert_two_factor_recovery_code(TwoFactorRecoveryCode {
+                user_id,
+                code: password_hash(code),
+                ..Default::default()
+            });
+    }
+    codes
+}
+
+pub fn use_recovery_code(ctx: &Context, user_id: Uuid, code: &str) -> bool {
+    let code = code.trim().to_lowercase();
+    let recovery_code = ctx
+        .database
+        .query::<TwoFactorRecoveryCode>(
+            formatcp!(
+                "SELECT {} FROM two_factor_recovery_codes WHERE user_id = ? AND used_at IS NULL",
+                TwoFactorRecoveryCode::columns()
+            ),
+            user_id,
+        )
+        .find(|recovery_code| password_verify(&code, &recovery_code.code));
+    match recovery_code {
+        Some(recovery_code) => {
+            let now = Utc::now();
+            ctx.database.execute(
+                "UPDATE two_factor_recovery_codes SET used_at = ?, updated_at = ? WHERE id = ?",
+                (now, now, recovery_code.id),
+            );
+            true
+        }
+        None => false,
+    }
+}
+
+// MARK: Two factor challenge
+#[derive(Clone, FromRow)]
+pub struct TwoFactorChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token: String,
+    pub remember: bool,
+    pub cookie: bool,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Default for TwoFactorChallenge {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            token: "".to_string(),
+            remember: true,
+            cookie: false,
+            expires_at: now + TWO_FACTOR_CHALLENGE_EXPIRE_DURATION,
+            created_at: now,
+        }
+    }
+}
+
+pub fn find_two_factor_challenge(ctx: &Context, token: &str) -> Option<TwoFactorChallenge> {
+    ctx.database
+        .query::<TwoFactorChallenge>(
+            formatcp!(
+                "SELECT {} FROM two_factor_challenges WHERE token = ? AND expires_at > ? LIMIT 1",
+                TwoFactorChallenge::columns()
+            ),
+            (token.to_string(), Utc::now()),
+        )
+        .next()
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::User;
+    use crate::totp::{hotp, totp_counter, TOTP_DIGITS};
+
+    #[test]
+    fn test_two_factor_verify_code_replay() {
+        let ctx = Context::with_test_database();
+        let user = User::default();
+        ctx.database.insert_user(user.clone());
+        let mut two_factor = TwoFactor {
+            user_id: user.id,
+            secret: generate_two_factor_secret(),
+            ..Default::default()
+        };
+        ctx.database.insert_two_factor(two_factor.clone());
+
+        let code = hotp(
+            &two_factor.secret_bytes(),
+            totp_counter(Utc::now().timestamp() as u64),
+            TOTP_DIGITS,
+        );
+        assert!(two_factor.verify_code(&ctx, &code));
+        assert!(!two_factor.verify_code(&ctx, &code));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let ctx = Context::with_test_database();
+        let user = User::default();
+        ctx.database.insert_user(user.clone());
+
+        let codes = generate_recovery_codes(&ctx, user.id);
+        assert_eq!(codes.len(), TWO_FACTOR_RECOVERY_CODES_COUNT);
+        assert!(use_recovery_code(&ctx, user.id, &codes[0].to_uppercase()));
+        assert!(!use_recovery_code(&ctx, user.id, &codes[0]));
+        assert!(!use_recovery_code(&ctx, user.id, "aaaa-aaaa"));
+    }
+}
@@ -6,9 +6,12 @@
 
 use bsqlite::{FromRow, FromValue};
 use chrono::{DateTime, NaiveDate, Utc};
-use pbkdf2::password_verify;
+use const_format::formatcp;
+use from_enum::FromEnum;
 use uuid::Uuid;
 
+use super::{PostInteractionType, PostType};
+use crate::password::password_verify;
 use crate::{api, Context};
 
 // MARK: User
@@ -25,6 +28,8 @@ pub struct User {
     pub location: Option<String>,
     pub website: Option<String>,
     pub role: UserRole,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub invited_by_user_id: Option<Uuid>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -44,16 +49,20 @@ impl Default for User {
             location: None,
             website: None,
             role: UserRole::Normal,
+            email_verified_at: None,
+            invited_by_user_id: None,
             created_at: now,
             updated_at: now,
         }
     }
 }
 
-#[derive(Clone, Copy, FromValue, Eq, PartialEq)]
+#[derive(Clone, Copy, FromEnum, FromValue, Eq, PartialEq)]
+#[from_enum(api::UserRole)]
 pub enum UserRole {
     Normal = 0,
     Admin = 1,
+    Moderator = 2,
 }
 
 impl From<User> for api::User {
@@ -68,12 +77,62 @@ impl From<User> for api::User {
             bio: user.bio,
             location: user.location,
             website: user.website,
+            role: user.role.into(),
+            email_verified_at: user.email_verified_at,
             created_at: user.created_at,
             updated_at: user.updated_at,
         }
     }
 }
 
+// MARK: Deletion
+impl User {
+    pub fn is_last_admin(&self, ctx: &Context) -> bool {
+        if self.role != UserRole::Admin {
+            return false;
+        }
+        let admins_count = ctx
+            .database
+            .query::<i64>(
+                "SELECT COUNT(id) FROM users WHERE role = ?",
+                UserRole::Admin,
+            )
+            .next()
+            .expect("Should be some");
+        admins_count <= 1
+    }
+
+    pub fn delete(&self, ctx: &Context) {
+        // Repair counters of other users posts the user liked, disliked, replied to or reposted
+        ctx.database.execute(
+            formatcp!(
+                "UPDATE posts SET
+                    likes = likes - (SELECT COUNT(id) FROM post_interactions WHERE post_id = posts.id AND user_id = ? AND type = {}),
+                    dislikes = dislikes - (SELECT COUNT(id) FROM post_interactions WHERE post_id = posts.id AND user_id = ? AND type = {})
+                WHERE user_id != ? AND id IN (SELECT post_id FROM post_interactions WHERE user_id = ?)",
+                PostInteractionType::Like as i64,
+                PostInteractionType::Dislike as i64
+            ),
+            (self.id, self.id, self.id, self.id),
+        );
+        ctx.database.execute(
+            formatcp!(
+                "UPDATE posts SET
+                    replies = replies - (SELECT COUNT(id) FROM posts AS child WHERE child.parent_post_id = posts.id AND child.user_id = ? AND child.type = {}),
+                    reposts = reposts - (SELECT COUNT(id) FROM posts AS child WHERE child.parent_post_id = posts.id AND child.user_id = ? AND child.type = {})
+                WHERE user_id != ? AND id IN (SELECT parent_post_id FROM posts WHERE user_id = ?)",
+                PostType::Reply as i64,
+                PostType::Repost as i64
+            ),
+            (self.id, self.id, self.id, self.id),
+        );
+
+        // Delete user, other rows are removed by the cascading foreign keys
+        ctx.database
+            .execute("DELETE FROM users WHERE id = ?", self.id);
+    }
+}
+
 // MARK: Validators
 pub fn is_unique_username(value: &str, context: &Context) -> validate::Result {
     let count = context
@@ -124,7 +183,7 @@ pub fn is_unique_email_or_auth_user_email(value: &str, context: &Context) -> val
 
 pub fn is_auth_user_current_password(value: &str, context: &Context) -> validate::Result {
     let user = context.auth_user.as_ref().expect("Not authed");
-    if !password_verify(value, &user.password).expect("Can't verify password") {
+    if !password_verify(value, &user.password) {
         return Err(validate::Error::new("incorrect"));
     }
     Ok(())
@@ -133,10 +192,9 @@ pub fn is_auth_user_current_password(value: &str, context: &Context) -> validate
 // MARK: Tests
 #[cfg(test)]
 mod test {
-    use pbkdf2::password_hash;
-
     use super::*;
     use crate::database::Extension;
+    use crate::password::password_hash;
 
     #[test]
     fn test_is_unique_username() {
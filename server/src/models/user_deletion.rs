@@ -0,0 +1,162 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::time::Duration;
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use super::User;
+use crate::{api, Context};
+
+// MARK: User deletion
+pub const USER_DELETION_GRACE_PERIOD: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Clone, FromRow)]
+pub struct UserDeletion {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub purge_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for UserDeletion {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            purge_at: now + USER_DELETION_GRACE_PERIOD,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+impl From<UserDeletion> for api::UserDeletion {
+    fn from(user_deletion: UserDeletion) -> Self {
+        Self {
+            id: user_deletion.id,
+            purge_at: user_deletion.purge_at,
+            created_at: user_deletion.created_at,
+            updated_at: user_deletion.updated_at,
+        }
+    }
+}
+
+pub fn find_user_deletion(ctx: &Context, user_id: Uuid) -> Option<UserDeletion> {
+    ctx.database
+        .query::<UserDeletion>(
+            formatcp!(
+                "SELECT {} FROM user_deletions WHERE user_id = ? LIMIT 1",
+                UserDeletion::columns()
+            ),
+            user_id,
+        )
+        .next()
+}
+
+// MARK: Purge
+pub fn purge_user_deletions(ctx: &Context) {
+    let users = ctx
+        .database
+        .query::<User>(
+            formatcp!(
+                "SELECT {} FROM users WHERE id IN (SELECT user_id FROM user_deletions WHERE purge_at <= ?)",
+                User::columns()
+            ),
+            Utc::now(),
+        )
+        .collect::<Vec<_>>();
+    for user in users {
+        // Another admin could have been deleted during the grace period, so check again
+        if user.is_last_admin(ctx) {
+            eprintln!("Can't purge user {}: last admin", user.username);
+            ctx.database
+                .execute("DELETE FROM user_deletions WHERE user_id = ?", user.id);
+            continue;
+        }
+        user.delete(ctx);
+    }
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::Extension;
+    use crate::models::UserRole;
+
+    #[test]
+    fn test_purge_user_deletions() {
+        let ctx = Context::with_test_database();
+        let user = User {
+            username: "pending".to_string(),
+            email: "pending@example.com".to_string(),
+            ..Default::default()
+        };
+        ctx.database.insert_user(user.clone());
+        ctx.database.insert_user_deletion(UserDeletion {
+            user_id: user.id,
+            ..Default::default()
+        });
+        let expired_user = User {
+            username: "expired".to_string(),
+            email: "expired@example.com".to_string(),
+            ..Default::default()
+        };
+        ctx.database.insert_user(expired_user.clone());
+        ctx.database.insert_user_deletion(UserDeletion {
+            user_id: expired_user.id,
+            purge_at: Utc::now(),
+            ..Default::default()
+        });
+
+        purge_user_deletions(&ctx);
+
+        let usernames = ctx
+            .database
+            .query::<String>("SELECT username FROM users", ())
+            .collect::<Vec<_>>();
+        assert_eq!(usernames, vec!["pending".to_string()]);
+        let count = ctx
+            .database
+            .query::<i64>("SELECT COUNT(id) FROM user_deletions", ())
+            .next()
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_purge_user_deletions_last_admin() {
+        let ctx = Context::with_test_database();
+        let admin = User {
+            username: "admin".to_string(),
+            email: "admin@example.com".to_string(),
+            role: UserRole::Admin,
+            ..Default::default()
+        };
+        ctx.database.insert_user(admin.clone());
+        ctx.database.insert_user_deletion(UserDeletion {
+            user_id: admin.id,
+            purge_at: Utc::now(),
+            ..Default::default()
+        });
+
+        // Last admin is kept and the deletion is cancelled
+        purge_user_deletions(&ctx);
+        assert!(find_user_deletion(&ctx, admin.id).is_none());
+        let count = ctx
+            .database
+            .query::<i64>("SELECT COUNT(id) FROM users", ())
+            .next()
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,50 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use crate::Context;
+
+// MARK: User identity
+// Links an identity of an OpenID Connect provider to a user by its stable subject
+#[derive(Clone, FromRow)]
+pub struct UserIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub issuer: String,
+    pub subject: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for UserIdentity {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            issuer: "".to_string(),
+            subject: "".to_string(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+pub fn find_user_identity(ctx: &Context, issuer: &str, subject: &str) -> Option<UserIdentity> {
+    ctx.database
+        .query::<UserIdentity>(
+            formatcp!(
+                "SELECT {} FROM user_identities WHERE issuer = ? AND subject = ? LIMIT 1",
+                UserIdentity::columns()
+            ),
+            (issuer.to_string(), subject.to_string()),
+        )
+        .next()
+}
@@ -0,0 +1,64 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use bsqlite::{FromRow, FromValue};
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+// MARK: User relation
+#[derive(FromRow)]
+pub struct UserRelation {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub target_user_id: Uuid,
+    pub r#type: UserRelationType,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for UserRelation {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            target_user_id: Uuid::nil(),
+            r#type: UserRelationType::Block,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+#[derive(Clone, Copy, FromValue)]
+pub enum UserRelationType {
+    Block = 0,
+    Mute = 1,
+}
+
+// MARK: Hidden users
+// These common table expressions define a `hidden_users` table with the ids of the users that
+// should be hidden for the auth user (blocked, suspended or deactivated), both take the auth user
+// id twice and the current time as params
+pub const BLOCKED_USERS_CTE: &str = formatcp!(
+    "WITH hidden_users (id) AS (
+    SELECT target_user_id FROM user_relations WHERE user_id = ? AND type = {block}
+    UNION SELECT user_id FROM user_relations WHERE target_user_id = ? AND type = {block}
+    UNION SELECT user_id FROM user_suspensions WHERE lifted_at IS NULL AND (expires_at IS NULL OR expires_at > ?)
+    UNION SELECT user_id FROM user_deletions
+)",
+    block = UserRelationType::Block as i64
+);
+pub const BLOCKED_OR_MUTED_USERS_CTE: &str = formatcp!(
+    "WITH hidden_users (id) AS (
+    SELECT target_user_id FROM user_relations WHERE user_id = ?
+    UNION SELECT user_id FROM user_relations WHERE target_user_id = ? AND type = {block}
+    UNION SELECT user_id FROM user_suspensions WHERE lifted_at IS NULL AND (expires_at IS NULL OR expires_at > ?)
+    UNION SELECT user_id FROM user_deletions
+)",
+    block = UserRelationType::Block as i64
+);
@@ -0,0 +1,78 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use bsqlite::FromRow;
+use chrono::{DateTime, Utc};
+use const_format::formatcp;
+use uuid::Uuid;
+
+use crate::{api, Context};
+
+// MARK: User suspension
+#[derive(Clone, FromRow)]
+pub struct UserSuspension {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub suspended_by_user_id: Option<Uuid>,
+    pub reason: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub lifted_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Default for UserSuspension {
+    fn default() -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::now_v7(),
+            user_id: Uuid::nil(),
+            suspended_by_user_id: None,
+            reason: "".to_string(),
+            expires_at: None,
+            lifted_at: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+impl From<UserSuspension> for api::UserSuspension {
+    fn from(user_suspension: UserSuspension) -> Self {
+        Self {
+            id: user_suspension.id,
+            reason: user_suspension.reason,
+            expires_at: user_suspension.expires_at,
+            lifted_at: user_suspension.lifted_at,
+            created_at: user_suspension.created_at,
+            updated_at: user_suspension.updated_at,
+        }
+    }
+}
+
+impl UserSuspension {
+    pub fn message(&self) -> String {
+        match self.expires_at {
+            Some(expires_at) => format!(
+                "Your account is suspended until {}: {}",
+                expires_at, self.reason
+            ),
+            None => format!("Your account is permanently suspended: {}", self.reason),
+        }
+    }
+}
+
+pub fn find_active_user_suspension(ctx: &Context, user_id: Uuid) -> Option<UserSuspension> {
+    ctx.database
+        .query::<UserSuspension>(
+            formatcp!(
+                "SELECT {} FROM user_suspensions WHERE user_id = ? AND lifted_at IS NULL AND (expires_at IS NULL OR expires_at > ?) LIMIT 1",
+                UserSuspension::columns()
+            ),
+            (user_id, Utc::now()),
+        )
+        .next()
+}
@@ -0,0 +1,293 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use chrono::Utc;
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::signature::Verifier;
+use rsa::{BigUint, RsaPublicKey};
+use serde::Deserialize;
+use sha2::Sha256;
+use small_http::{Method, Request, Status};
+
+use crate::settings::Settings;
+
+// Allowed clock difference between us and the identity provider
+const CLOCK_SKEW_LEEWAY: i64 = 60;
+
+// The small_http client has no TLS support, so all provider endpoints must be plain http
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://")
+}
+
+// MARK: Discovery
+#[derive(Clone, Deserialize)]
+pub struct OidcProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+pub fn fetch_provider_metadata(settings: &Settings) -> Option<OidcProviderMetadata> {
+    let issuer_url = settings.oidc_issuer_url.trim_end_matches('/');
+    let res = Request::with_url(format!("{}/.well-known/openid-configuration", issuer_url))
+        .fetch()
+        .ok()?;
+    if res.status != Status::Ok {
+        return None;
+    }
+    let metadata = serde_json::from_slice::<OidcProviderMetadata>(&res.body).ok()?;
+
+    // Issuer must be the configured issuer, else the provider could impersonate another one
+    if metadata.issuer.trim_end_matches('/') != issuer_url {
+        return None;
+    }
+    if !is_http_url(&metadata.token_endpoint) || !is_http_url(&metadata.jwks_uri) {
+        return None;
+    }
+    Some(metadata)
+}
+
+// MARK: Token exchange
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+// Exchanges the authorization code for an ID token at the token endpoint
+pub fn exchange_code(
+    settings: &Settings,
+    metadata: &OidcProviderMetadata,
+    code: &str,
+    code_verifier: &str,
+) -> Option<String> {
+    let body = serde_urlencoded::to_string([
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", settings.oidc_redirect_uri.as_str()),
+        ("client_id", settings.oidc_client_id.as_str()),
+        ("client_secret", settings.oidc_client_secret.as_str()),
+        ("code_verifier", code_verifier),
+    ])
+    .ok()?;
+    let res = Request::with_url(&metadata.token_endpoint)
+        .method(Method::Post)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .fetch()
+        .ok()?;
+    if res.status != Status::Ok {
+        return None;
+    }
+    serde_json::from_slice::<TokenResponse>(&res.body)
+        .ok()
+        .map(|token_response| token_response.id_token)
+}
+
+// MARK: JSON Web Keys
+#[derive(Deserialize)]
+pub struct JsonWebKeySet {
+    pub keys: Vec<JsonWebKey>,
+}
+
+#[derive(Deserialize)]
+pub struct JsonWebKey {
+    pub kty: String,
+    pub kid: Option<String>,
+    pub n: Option<String>,
+    pub e: Option<String>,
+}
+
+impl JsonWebKey {
+    fn rsa_public_key(&self) -> Option<RsaPublicKey> {
+        let n = BASE64_URL_SAFE_NO_PAD.decode(self.n.as_ref()?).ok()?;
+        let e = BASE64_URL_SAFE_NO_PAD.decode(self.e.as_ref()?).ok()?;
+        RsaPublicKey::new(BigUint::from_bytes_be(&n), BigUint::from_bytes_be(&e)).ok()
+    }
+}
+
+pub fn fetch_json_web_key_set(metadata: &OidcProviderMetadata) -> Option<JsonWebKeySet> {
+    let res = Request::with_url(&metadata.jwks_uri).fetch().ok()?;
+    if res.status != Status::Ok {
+        return None;
+    }
+    serde_json::from_slice::<JsonWebKeySet>(&res.body).ok()
+}
+
+// MARK: ID token
+#[derive(Deserialize)]
+struct IdTokenHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: serde_json::Value,
+    pub azp: Option<String>,
+    pub exp: i64,
+    pub iat: i64,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub preferred_username: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+}
+
+// Verifies the RS256 signature and the claims of an ID token (OpenID Connect Core 3.1.3.7)
+pub fn verify_id_token(
+    id_token: &str,
+    json_web_key_set: &JsonWebKeySet,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims, &'static str> {
+    let mut parts = id_token.split('.');
+    let (header, payload, signature) =
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature), None) => (header, payload, signature),
+            _ => return Err("malformed id token"),
+        };
+
+    // Verify signature with the key from the identity provider
+    let id_token_header = BASE64_URL_SAFE_NO_PAD
+        .decode(header)
+        .ok()
+        .and_then(|header| serde_json::from_slice::<IdTokenHeader>(&header).ok())
+        .ok_or("malformed id token header")?;
+    if id_token_header.alg != "RS256" {
+        return Err("unsupported id token algorithm");
+    }
+    let public_key = json_web_key_set
+        .keys
+        .iter()
+        .filter(|key| key.kty == "RSA")
+        .find(|key| id_token_header.kid.is_none() || key.kid == id_token_header.kid)
+        .and_then(|key| key.rsa_public_key())
+        .ok_or("unknown id token key")?;
+    let signature = BASE64_URL_SAFE_NO_PAD
+        .decode(signature)
+        .ok()
+        .and_then(|signature| Signature::try_from(signature.as_slice()).ok())
+        .ok_or("malformed id token signature")?;
+    VerifyingKey::<Sha256>::new(public_key)
+        .verify(format!("{}.{}", header, payload).as_bytes(), &signature)
+        .map_err(|_| "invalid id token signature")?;
+
+    // Validate claims
+    let claims = BASE64_URL_SAFE_NO_PAD
+        .decode(payload)
+        .ok()
+        .and_then(|payload| serde_json::from_slice::<IdTokenClaims>(&payload).ok())
+        .ok_or("malformed id token claims")?;
+    if claims.iss != issuer {
+        return Err("wrong id token issuer");
+    }
+    let is_audience = match &claims.aud {
+        serde_json::Value::String(aud) => aud == client_id,
+        serde_json::Value::Array(aud) => aud.iter().any(|aud| aud == client_id),
+        _ => false,
+    };
+    if !is_audience || claims.azp.as_ref().is_some_and(|azp| azp != client_id) {
+        return Err("wrong id token audience");
+    }
+    let now = Utc::now().timestamp();
+    if claims.exp < now - CLOCK_SKEW_LEEWAY || claims.iat > now + CLOCK_SKEW_LEEWAY {
+        return Err("expired id token");
+    }
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err("wrong id token nonce");
+    }
+    Ok(claims)
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::MockIdentityProvider;
+
+    #[test]
+    fn test_verify_id_token() {
+        let idp = MockIdentityProvider::start();
+        let json_web_key_set =
+            serde_json::from_value::<JsonWebKeySet>(idp.json_web_key_set()).unwrap();
+        let claims = |extra: serde_json::Value| {
+            let mut claims = serde_json::json!({
+                "iss": idp.issuer,
+                "sub": "1234",
+                "aud": "plaatbook",
+                "exp": Utc::now().timestamp() + 300,
+                "iat": Utc::now().timestamp(),
+                "nonce": "nonce",
+            });
+            for (key, value) in extra.as_object().unwrap() {
+                claims[key] = value.clone();
+            }
+            claims
+        };
+
+        let id_token = idp.sign_id_token(&claims(serde_json::json!({})));
+        let verified = verify_id_token(
+            &id_token,
+            &json_web_key_set,
+            &idp.issuer,
+            "plaatbook",
+            "nonce",
+        )
+        .unwrap();
+        assert_eq!(verified.sub, "1234");
+
+        // Tampered payload
+        let mut parts = id_token.split('.').collect::<Vec<_>>();
+        let other_payload = BASE64_URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&claims(serde_json::json!({ "sub": "5678" }))).unwrap());
+        parts[1] = other_payload.as_str();
+        assert!(verify_id_token(
+            &parts.join("."),
+            &json_web_key_set,
+            &idp.issuer,
+            "plaatbook",
+            "nonce"
+        )
+        .is_err());
+
+        // Wrong audience, expired and wrong nonce
+        for extra in [
+            serde_json::json!({ "aud": "other" }),
+            serde_json::json!({ "exp": Utc::now().timestamp() - 300 }),
+            serde_json::json!({ "nonce": "other" }),
+        ] {
+            let id_token = idp.sign_id_token(&claims(extra));
+            assert!(verify_id_token(
+                &id_token,
+                &json_web_key_set,
+                &idp.issuer,
+                "plaatbook",
+                "nonce"
+            )
+            .is_err());
+        }
+
+        // Unsigned tokens are rejected
+        let header = BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#);
+        let payload = BASE64_URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&claims(serde_json::json!({}))).unwrap());
+        assert!(verify_id_token(
+            &format!("{}.{}.", header, payload),
+            &json_web_key_set,
+            &idp.issuer,
+            "plaatbook",
+            "nonce"
+        )
+        .is_err());
+    }
+}
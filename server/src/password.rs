@@ -0,0 +1,136 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::sync::LazyLock;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+
+// MARK: Constants
+// Argon2id parameters from the OWASP password storage cheat sheet, hashes with other parameters
+// are upgraded on the next successful login
+pub const ARGON2_MEMORY_COST: u32 = 19 * 1024;
+pub const ARGON2_TIME_COST: u32 = 2;
+pub const ARGON2_PARALLELISM: u32 = 1;
+const SALT_SIZE: usize = 16;
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(
+            ARGON2_MEMORY_COST,
+            ARGON2_TIME_COST,
+            ARGON2_PARALLELISM,
+            None,
+        )
+        .expect("Invalid Argon2 params"),
+    )
+}
+
+// MARK: Hash
+// Returns a PHC string like `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>` that carries its parameters
+pub fn password_hash(password: &str) -> String {
+    let mut salt_bytes = [0u8; SALT_SIZE];
+    getrandom::fill(&mut salt_bytes).expect("Can't get random bytes");
+    let salt = SaltString::encode_b64(&salt_bytes).expect("Can't encode salt");
+    argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Can't hash password")
+        .to_string()
+}
+
+// MARK: Verify
+// Verifies Argon2 hashes with the parameters stored in the hash, older PBKDF2 hashes keep working
+pub fn password_verify(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        return match PasswordHash::new(hash) {
+            Ok(hash) => Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok(),
+            Err(_) => false,
+        };
+    }
+    pbkdf2::password_verify(password, hash).unwrap_or(false)
+}
+
+// Verifies against a hash of a random password, so logons without a user take as long as a wrong
+// password and can't be told apart by timing
+pub fn password_verify_dummy(password: &str) {
+    static DUMMY_PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| {
+        let mut password_bytes = [0u8; SALT_SIZE];
+        getrandom::fill(&mut password_bytes).expect("Can't get random bytes");
+        password_hash(&String::from_utf8_lossy(&password_bytes))
+    });
+    _ = password_verify(password, &DUMMY_PASSWORD_HASH);
+}
+
+// Checks if the hash is not created with the current algorithm and parameters
+pub fn password_needs_rehash(hash: &str) -> bool {
+    let hash = match PasswordHash::new(hash) {
+        Ok(hash) => hash,
+        Err(_) => return true,
+    };
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(&hash) {
+        Ok(params) => {
+            params.m_cost() != ARGON2_MEMORY_COST
+                || params.t_cost() != ARGON2_TIME_COST
+                || params.p_cost() != ARGON2_PARALLELISM
+        }
+        Err(_) => true,
+    }
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_password_hash_verify() {
+        let hash = password_hash("password");
+        assert!(hash.starts_with(&format!(
+            "$argon2id$v=19$m={},t={},p={}$",
+            ARGON2_MEMORY_COST, ARGON2_TIME_COST, ARGON2_PARALLELISM
+        )));
+        assert!(password_verify("password", &hash));
+        assert!(!password_verify("wrong_password", &hash));
+        assert!(!password_needs_rehash(&hash));
+
+        // Same password gets a different salt
+        assert_ne!(hash, password_hash("password"));
+    }
+
+    #[test]
+    fn test_password_verify_legacy_pbkdf2() {
+        let hash = pbkdf2::password_hash("password");
+        assert!(password_verify("password", &hash));
+        assert!(!password_verify("wrong_password", &hash));
+        assert!(password_needs_rehash(&hash));
+    }
+
+    #[test]
+    fn test_password_needs_rehash_outdated_params() {
+        let mut salt_bytes = [0u8; SALT_SIZE];
+        getrandom::fill(&mut salt_bytes).unwrap();
+        let salt = SaltString::encode_b64(&salt_bytes).unwrap();
+        let hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(8 * 1024, 1, 1, None).unwrap(),
+        )
+        .hash_password(b"password", &salt)
+        .unwrap()
+        .to_string();
+        assert!(password_verify("password", &hash));
+        assert!(password_needs_rehash(&hash));
+        assert!(password_needs_rehash("invalid"));
+    }
+}
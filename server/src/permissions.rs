@@ -0,0 +1,227 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use small_http::{Response, Status};
+use uuid::Uuid;
+
+use crate::models::{User, UserRole};
+use crate::Context;
+
+// MARK: Ability
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Ability {
+    PostUpdate,
+    PostDelete,
+    PostFilterUpdate,
+    PostFilterDelete,
+    PersonalAccessTokenDelete,
+    OAuthAppDelete,
+    OAuthGrantRevoke,
+    SessionViewAny,
+    SessionRevoke,
+    UserViewAny,
+    UserViewPrivate,
+    UserUpdate,
+    UserChangePassword,
+    UserChangeRole,
+    UserDelete,
+    UserSuspend,
+    UserUnlock,
+    UserImpersonate,
+    InviteCodeCreate,
+    InviteCodeDelete,
+}
+
+impl Ability {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PostUpdate => "post.update",
+            Self::PostDelete => "post.delete",
+            Self::PostFilterUpdate => "post_filter.update",
+            Self::PostFilterDelete => "post_filter.delete",
+            Self::PersonalAccessTokenDelete => "personal_access_token.delete",
+            Self::OAuthAppDelete => "oauth_app.delete",
+            Self::OAuthGrantRevoke => "oauth_grant.revoke",
+            Self::SessionViewAny => "session.view_any",
+            Self::SessionRevoke => "session.revoke",
+            Self::UserViewAny => "user.view_any",
+            Self::UserViewPrivate => "user.view_private",
+            Self::UserUpdate => "user.update",
+            Self::UserChangePassword => "user.change_password",
+            Self::UserChangeRole => "user.change_role",
+            Self::UserDelete => "user.delete",
+            Self::UserSuspend => "user.suspend",
+            Self::UserUnlock => "user.unlock",
+            Self::UserImpersonate => "user.impersonate",
+            Self::InviteCodeCreate => "invite_code.create",
+            Self::InviteCodeDelete => "invite_code.delete",
+        }
+    }
+}
+
+// MARK: Roles
+fn role_abilities(role: UserRole) -> &'static [Ability] {
+    match role {
+        UserRole::Normal => &[],
+        UserRole::Moderator => &[
+            Ability::PostUpdate,
+            Ability::PostDelete,
+            Ability::UserViewAny,
+            Ability::UserSuspend,
+        ],
+        UserRole::Admin => &[
+            Ability::PostUpdate,
+            Ability::PostDelete,
+            Ability::PostFilterUpdate,
+            Ability::PostFilterDelete,
+            Ability::PersonalAccessTokenDelete,
+            Ability::OAuthAppDelete,
+            Ability::OAuthGrantRevoke,
+            Ability::SessionViewAny,
+            Ability::SessionRevoke,
+            Ability::UserViewAny,
+            Ability::UserViewPrivate,
+            Ability::UserUpdate,
+            Ability::UserChangePassword,
+            Ability::UserChangeRole,
+            Ability::UserDelete,
+            Ability::UserSuspend,
+            Ability::UserUnlock,
+            Ability::UserImpersonate,
+            Ability::InviteCodeCreate,
+            Ability::InviteCodeDelete,
+        ],
+    }
+}
+
+// Higher roles can moderate lower roles, but never equal or higher roles
+fn role_rank(role: UserRole) -> i64 {
+    match role {
+        UserRole::Normal => 0,
+        UserRole::Moderator => 1,
+        UserRole::Admin => 2,
+    }
+}
+
+pub fn outranks(user: &User, target: &User) -> bool {
+    role_rank(user.role) > role_rank(target.role)
+}
+
+// MARK: Authorization
+// Owners can always act on their own resources, pass no owner for abilities that are role only
+pub fn can(user: &User, ability: Ability, owner_id: Option<Uuid>) -> bool {
+    owner_id == Some(user.id) || role_abilities(user.role).contains(&ability)
+}
+
+pub fn authorize(ctx: &Context, ability: Ability, owner_id: Option<Uuid>) -> Option<Response> {
+    let auth_user = match ctx.auth_user.as_ref() {
+        Some(auth_user) => auth_user,
+        None => {
+            return Some(
+                Response::new()
+                    .status(Status::Unauthorized)
+                    .body("401 Unauthorized"),
+            );
+        }
+    };
+    if !can(auth_user, ability, owner_id) {
+        return Some(
+            Response::new()
+                .status(Status::Forbidden)
+                .body(format!("403 Forbidden: missing {} ability", ability.name())),
+        );
+    }
+    None
+}
+
+// MARK: Impersonation
+// Impersonation sessions can see everything the user sees, but can't change the credentials of
+// the user or create tokens that outlive the session
+pub fn deny_impersonation(ctx: &Context) -> Option<Response> {
+    if ctx
+        .auth_session
+        .as_ref()
+        .is_some_and(|session| session.is_impersonation())
+    {
+        return Some(
+            Response::new()
+                .status(Status::Forbidden)
+                .body("403 Forbidden: not allowed while impersonating"),
+        );
+    }
+    None
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_can_owner() {
+        let user = User::default();
+        assert!(can(&user, Ability::PostUpdate, Some(user.id)));
+        assert!(!can(&user, Ability::PostUpdate, Some(Uuid::now_v7())));
+        assert!(!can(&user, Ability::UserSuspend, None));
+    }
+
+    #[test]
+    fn test_can_roles() {
+        let moderator = User {
+            role: UserRole::Moderator,
+            ..Default::default()
+        };
+        assert!(can(&moderator, Ability::PostDelete, Some(Uuid::now_v7())));
+        assert!(can(&moderator, Ability::UserSuspend, None));
+        assert!(!can(&moderator, Ability::SessionViewAny, None));
+
+        let admin = User {
+            role: UserRole::Admin,
+            ..Default::default()
+        };
+        assert!(can(&admin, Ability::SessionViewAny, None));
+        assert!(can(
+            &admin,
+            Ability::UserChangePassword,
+            Some(Uuid::now_v7())
+        ));
+    }
+
+    #[test]
+    fn test_outranks() {
+        let user = User::default();
+        let moderator = User {
+            role: UserRole::Moderator,
+            ..Default::default()
+        };
+        let admin = User {
+            role: UserRole::Admin,
+            ..Default::default()
+        };
+        assert!(outranks(&moderator, &user));
+        assert!(!outranks(&moderator, &moderator));
+        assert!(!outranks(&moderator, &admin));
+        assert!(outranks(&admin, &moderator));
+        assert!(!outranks(&admin, &admin));
+    }
+
+    #[test]
+    fn test_authorize() {
+        let mut ctx = Context::with_test_database();
+        let res = authorize(&ctx, Ability::UserViewAny, None).unwrap();
+        assert_eq!(res.status, Status::Unauthorized);
+
+        ctx.auth_user = Some(User::default());
+        let res = authorize(&ctx, Ability::UserViewAny, None).unwrap();
+        assert_eq!(res.status, Status::Forbidden);
+
+        ctx.auth_user = Some(User {
+            role: UserRole::Moderator,
+            ..Default::default()
+        });
+        assert!(authorize(&ctx, Ability::UserViewAny, None).is_none());
+    }
+}
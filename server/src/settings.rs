@@ -0,0 +1,202 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use std::env;
+use std::time::Duration;
+
+// MARK: Setting values
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum RegistrationMode {
+    Open,
+    InviteOnly,
+    Closed,
+}
+
+impl RegistrationMode {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "open" => Some(Self::Open),
+            "invite_only" => Some(Self::InviteOnly),
+            "closed" => Some(Self::Closed),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::InviteOnly => "invite_only",
+            Self::Closed => "closed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum GeoIpProviderType {
+    None,
+    Csv,
+    Mmdb,
+    Http,
+}
+
+impl GeoIpProviderType {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Self::None),
+            "csv" => Some(Self::Csv),
+            "mmdb" => Some(Self::Mmdb),
+            "http" => Some(Self::Http),
+            _ => None,
+        }
+    }
+}
+
+// MARK: Settings
+#[derive(Clone)]
+pub(crate) struct Settings {
+    pub app_url: String,
+    pub require_verified_email_to_post: bool,
+    pub registration_mode: RegistrationMode,
+    pub user_invite_quota: i64,
+    pub pow_difficulty: i64,
+    pub session_short_lifetime: Duration,
+    pub session_long_lifetime: Duration,
+    pub session_idle_timeout: Duration,
+    pub geoip_provider: GeoIpProviderType,
+    pub geoip_database_path: String,
+    pub geoip_http_url: String,
+    pub geoip_http_timeout: Duration,
+    pub oidc_issuer_url: String,
+    pub oidc_client_id: String,
+    pub oidc_client_secret: String,
+    pub oidc_redirect_uri: String,
+    pub oidc_scopes: String,
+    pub oidc_auto_provision: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            app_url: "http://localhost:5173".to_string(),
+            require_verified_email_to_post: false,
+            registration_mode: RegistrationMode::Open,
+            user_invite_quota: 0,
+            pow_difficulty: 20,
+            session_short_lifetime: Duration::from_secs(24 * 60 * 60),
+            session_long_lifetime: Duration::from_secs(365 * 24 * 60 * 60),
+            session_idle_timeout: Duration::from_secs(30 * 24 * 60 * 60),
+            geoip_provider: GeoIpProviderType::None,
+            geoip_database_path: "geoip.mmdb".to_string(),
+            geoip_http_url: "http://ipinfo.io/{ip}/json".to_string(),
+            geoip_http_timeout: Duration::from_secs(2),
+            oidc_issuer_url: "".to_string(),
+            oidc_client_id: "".to_string(),
+            oidc_client_secret: "".to_string(),
+            oidc_redirect_uri: "http://localhost:5173/auth/oidc/callback".to_string(),
+            oidc_scopes: "openid email profile".to_string(),
+            oidc_auto_provision: false,
+        }
+    }
+}
+
+impl Settings {
+    pub(crate) fn from_env() -> Self {
+        let default = Self::default();
+        let app_url = env::var("APP_URL").unwrap_or(default.app_url);
+        let oidc_redirect_uri =
+            env::var("OIDC_REDIRECT_URI").unwrap_or(format!("{}/auth/oidc/callback", app_url));
+
+        // The HTTP client has no TLS support, so the identity provider must be reachable over
+        // plain http, for example via a TLS terminating proxy on the internal network
+        let oidc_issuer_url = env::var("OIDC_ISSUER_URL").unwrap_or(default.oidc_issuer_url);
+        if !oidc_issuer_url.is_empty() && !oidc_issuer_url.starts_with("http://") {
+            panic!("OIDC_ISSUER_URL must be a http:// url, https is not supported");
+        }
+        Self {
+            app_url,
+            require_verified_email_to_post: env_bool("REQUIRE_VERIFIED_EMAIL_TO_POST")
+                .unwrap_or(default.require_verified_email_to_post),
+            // One of open, invite_only or closed
+            registration_mode: env_parse("REGISTRATION_MODE", RegistrationMode::parse)
+                .unwrap_or(default.registration_mode),
+            // Number of usable invite codes normal users may have, admins are unlimited
+            user_invite_quota: env_number("USER_INVITE_QUOTA").unwrap_or(default.user_invite_quota),
+            // Leading zero bits of the proof of work hash, zero disables the challenge
+            pow_difficulty: env_number("POW_DIFFICULTY").unwrap_or(default.pow_difficulty),
+            session_short_lifetime: env_seconds("SESSION_SHORT_LIFETIME")
+                .unwrap_or(default.session_short_lifetime),
+            session_long_lifetime: env_seconds("SESSION_LONG_LIFETIME")
+                .unwrap_or(default.session_long_lifetime),
+            session_idle_timeout: env_seconds("SESSION_IDLE_TIMEOUT")
+                .unwrap_or(default.session_idle_timeout),
+            // One of none, csv, mmdb or http
+            geoip_provider: env_parse("GEOIP_PROVIDER", GeoIpProviderType::parse)
+                .unwrap_or(default.geoip_provider),
+            geoip_database_path: env::var("GEOIP_DATABASE_PATH")
+                .unwrap_or(default.geoip_database_path),
+            // Must be a http:// url, the HTTP client has no TLS support
+            geoip_http_url: env::var("GEOIP_HTTP_URL").unwrap_or(default.geoip_http_url),
+            geoip_http_timeout: env_seconds("GEOIP_HTTP_TIMEOUT")
+                .unwrap_or(default.geoip_http_timeout),
+            // Single sign-on is disabled when no issuer is configured
+            oidc_issuer_url,
+            oidc_client_id: env::var("OIDC_CLIENT_ID").unwrap_or(default.oidc_client_id),
+            oidc_client_secret: env::var("OIDC_CLIENT_SECRET")
+                .unwrap_or(default.oidc_client_secret),
+            oidc_redirect_uri,
+            oidc_scopes: env::var("OIDC_SCOPES").unwrap_or(default.oidc_scopes),
+            oidc_auto_provision: env_bool("OIDC_AUTO_PROVISION")
+                .unwrap_or(default.oidc_auto_provision),
+        }
+    }
+}
+
+// Unknown values are a configuration mistake, so refuse to start instead of guessing
+fn env_parse<T>(name: &str, parse: fn(&str) -> Option<T>) -> Option<T> {
+    env::var(name)
+        .ok()
+        .map(|value| parse(&value).unwrap_or_else(|| panic!("Invalid {} value: {}", name, value)))
+}
+
+fn env_bool(name: &str) -> Option<bool> {
+    env::var(name)
+        .ok()
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+fn env_number(name: &str) -> Option<i64> {
+    env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
+fn env_seconds(name: &str) -> Option<Duration> {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_secs)
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_registration_mode_parse() {
+        for mode in [
+            RegistrationMode::Open,
+            RegistrationMode::InviteOnly,
+            RegistrationMode::Closed,
+        ] {
+            assert_eq!(RegistrationMode::parse(mode.name()), Some(mode));
+        }
+        assert_eq!(RegistrationMode::parse("invite"), None);
+        assert_eq!(
+            GeoIpProviderType::parse("mmdb"),
+            Some(GeoIpProviderType::Mmdb)
+        );
+        assert_eq!(GeoIpProviderType::parse("maxmind"), None);
+    }
+}
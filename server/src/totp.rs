@@ -0,0 +1,177 @@
+/*
+ * Copyright (c) 2025 PlaatSoft
+ *
+ * SPDX-License-Identifier: MIT
+ */
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+// MARK: Constants
+pub const TOTP_DIGITS: u32 = 6;
+pub const TOTP_PERIOD: u64 = 30;
+const TOTP_SECRET_SIZE: usize = 20;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// MARK: Base32
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut output = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u64;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    output
+}
+
+pub fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(data.len() * 5 / 8);
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    for c in data.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+    Some(output)
+}
+
+// MARK: TOTP
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; TOTP_SECRET_SIZE];
+    getrandom::fill(&mut secret).expect("Can't get random bytes");
+    secret
+}
+
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> String {
+    // RFC 4226 dynamic truncation
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("Hmac accepts any key size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) % 10u32.pow(digits);
+    format!("{:0width$}", code, width = digits as usize)
+}
+
+pub fn totp_counter(unix_time: u64) -> u64 {
+    unix_time / TOTP_PERIOD
+}
+
+// Returns the matched counter so callers can reject a code that was already used
+pub fn verify_totp(secret: &[u8], code: &str, unix_time: u64) -> Option<u64> {
+    let code = code.trim().replace(' ', "");
+    if code.len() != TOTP_DIGITS as usize {
+        return None;
+    }
+    let counter = totp_counter(unix_time);
+    // Allow one period of clock drift in both directions
+    [counter.saturating_sub(1), counter, counter + 1]
+        .into_iter()
+        .find(|counter| hotp(secret, *counter, TOTP_DIGITS) == code)
+}
+
+pub fn otpauth_uri(secret: &[u8], issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        url_escape(issuer),
+        url_escape(account),
+        base32_encode(secret),
+        url_escape(issuer),
+        TOTP_DIGITS,
+        TOTP_PERIOD
+    )
+}
+
+fn url_escape(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            output.push(byte as char);
+        } else {
+            output.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    output
+}
+
+// MARK: Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(base32_encode(b""), "");
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+        assert!(base32_decode("MZXW1").is_none());
+    }
+
+    #[test]
+    fn test_hotp_rfc4226_vectors() {
+        let secret = b"12345678901234567890";
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(hotp(secret, counter as u64, 6), *code);
+        }
+    }
+
+    #[test]
+    fn test_totp_rfc6238_vectors() {
+        // SHA1 vectors from RFC 6238 appendix B
+        let secret = b"12345678901234567890";
+        for (time, code) in [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ] {
+            assert_eq!(hotp(secret, totp_counter(time), 8), code);
+        }
+    }
+
+    #[test]
+    fn test_verify_totp() {
+        let secret = b"12345678901234567890";
+        let time = 1111111111;
+        let code = hotp(secret, totp_counter(time), TOTP_DIGITS);
+        assert_eq!(verify_totp(secret, &code, time), Some(totp_counter(time)));
+        assert!(verify_totp(secret, &code, time + TOTP_PERIOD).is_some());
+        assert!(verify_totp(secret, &code, time + 3 * TOTP_PERIOD).is_none());
+        assert!(verify_totp(secret, "12345", time).is_none());
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        assert_eq!(
+            otpauth_uri(b"foobar", "PlaatBook", "test@example.com"),
+            "otpauth://totp/PlaatBook:test%40example.com?secret=MZXW6YTBOI&issuer=PlaatBook&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}